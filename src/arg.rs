@@ -1,11 +1,11 @@
 use std::{env::{self, Args}, iter::Peekable};
 
-type R<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+use git_starter_rust::R;
 
 pub mod opt {
     use std::{env::Args,iter::Peekable};
 
-    type R<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+    use git_starter_rust::R;
 
     pub fn named(args: &mut Peekable<Args>, name: &str) -> R<Option<String>> {
         match args.peek() {
@@ -40,17 +40,6 @@ pub fn unnamed(args: &mut Peekable<Args>, info: &str) -> R<String> {
     args.next().ok_or(format!("Not enough arguments provided: missing {}.", info).into())
 }
 
-pub fn named(args: &mut Peekable<Args>, name: &str) -> R<String> {
-    let arg = args.next()
-        .ok_or(format!("Not enough arguments provided: missing '{}'.", name))?;
-    if arg == name {
-        args.next()
-            .ok_or(format!("Not enough arguments provided: missing value for '{}'.", name).into())
-    } else {
-        Err(format!("Expecting argument '{}'. Got '{}' instead.", name, arg).into())
-    }
-}
-
 pub fn flag(args: &mut Peekable<Args>, flag: &str) -> R<()> {
     let arg = args.next()
         .ok_or(format!("Not enough arguments provided: missing flag '{}'.", flag))?;
@@ -0,0 +1,159 @@
+use std::{cell::RefCell, collections::HashMap, fs, path::{Path, PathBuf}, rc::Rc};
+use crate::{glob::Pattern, repo::Repository};
+
+use crate::error::R;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum State {
+    Set,
+    Unset,
+    Value(String),
+    Unspecified
+}
+
+impl State {
+    // Parses `name`, `-name`, `!name` or `name=value` as used in attributes files and pathspecs.
+    pub fn parse(attr: &str) -> (String, State) {
+        if let Some(name) = attr.strip_prefix('-') {
+            (name.to_string(), State::Unset)
+        } else if let Some(name) = attr.strip_prefix('!') {
+            (name.to_string(), State::Unspecified)
+        } else if let Some((name, value)) = attr.split_once('=') {
+            (name.to_string(), State::Value(value.to_string()))
+        } else {
+            (attr.to_string(), State::Set)
+        }
+    }
+}
+
+struct Rule {
+    pattern: Pattern,
+    attrs: Vec<(String, State)>
+}
+
+// The attributes of the paths in a repository, from `info/attributes`, the `.gitattributes` files
+// of the work tree and `core.attributesFile`, in that order of precedence.
+pub struct Attributes {
+    work_tree: Option<PathBuf>,
+    info: Vec<Rule>,
+    global: Vec<Rule>,
+    dirs: RefCell<HashMap<String, Rc<Vec<Rule>>>>
+}
+
+impl Attributes {
+    pub fn load(repo: &Repository) -> R<Attributes> {
+        let global = match repo.config()?.get_path("core.attributesFile")? {
+            Some(path) => read(&path, "")?,
+            None       => Vec::new()
+        };
+        Ok(Attributes {
+            work_tree: repo.work_tree().ok().map(Path::to_path_buf),
+            info: read(&repo.git_dir().join("info/attributes"), "")?,
+            global,
+            dirs: RefCell::new(HashMap::new())
+        })
+    }
+
+    // Within a file the last line that mentions the attribute wins, and `.gitattributes` files of
+    // deeper dirs take precedence over those above them.
+    pub fn get(&self, path: &str, name: &str) -> R<State> {
+        let find = |rules: &[Rule]| rules.iter().rev()
+            .filter(|r| r.pattern.matches(path, false))
+            .find_map(|r| r.attrs.iter().rev().find(|(n, _)| n == name).map(|(_, state)| state.clone()));
+
+        if let Some(state) = find(&self.info) {
+            return Ok(state);
+        }
+        let mut dir = path;
+        while let Some(pos) = dir.rfind('/') {
+            dir = &dir[..pos];
+            if let Some(state) = find(&self.rules(dir)?) {
+                return Ok(state);
+            }
+        }
+        if let Some(state) = find(&self.rules("")?) {
+            return Ok(state);
+        }
+        Ok(find(&self.global).unwrap_or(State::Unspecified))
+    }
+
+    fn rules(&self, dir: &str) -> R<Rc<Vec<Rule>>> {
+        let work_tree = match &self.work_tree {
+            Some(work_tree) => work_tree,
+            None            => return Ok(Rc::new(Vec::new()))
+        };
+        if let Some(rules) = self.dirs.borrow().get(dir) {
+            return Ok(rules.clone());
+        }
+        let rules = Rc::new(read(&work_tree.join(dir).join(".gitattributes"), dir)?);
+        self.dirs.borrow_mut().insert(dir.to_string(), rules.clone());
+        Ok(rules)
+    }
+}
+
+fn read(path: &Path, base: &str) -> R<Vec<Rule>> {
+    if !path.is_file() {
+        return Ok(Vec::new());
+    }
+    let content = fs::read(path)
+        .map_err(|e| format!("Failed to read attributes file {:?}: {}", path, e))?;
+
+    let mut rules = Vec::new();
+    for line in String::from_utf8_lossy(&content).lines() {
+        let mut words = line.split_whitespace();
+        let pattern = match words.next() {
+            Some(pattern) if !pattern.starts_with('#') => pattern,
+            _                                          => continue
+        };
+        let mut attrs = Vec::new();
+        for word in words {
+            match word {
+                // the only built-in macro
+                "binary" => attrs.extend(["binary", "-diff", "-merge", "-text"].iter().map(|a| State::parse(a))),
+                _        => attrs.push(State::parse(word))
+            }
+        }
+        rules.push(Rule { pattern: Pattern::parse(pattern, base), attrs });
+    }
+    Ok(rules)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testutil;
+    use super::*;
+
+    #[test]
+    fn parse_states() {
+        assert_eq!(State::parse("text"), ("text".to_string(), State::Set));
+        assert_eq!(State::parse("-text"), ("text".to_string(), State::Unset));
+        assert_eq!(State::parse("!text"), ("text".to_string(), State::Unspecified));
+        assert_eq!(State::parse("eol=crlf"), ("eol".to_string(), State::Value("crlf".to_string())));
+    }
+
+    #[test]
+    fn precedence() {
+        let (dir, repo) = testutil::repo();
+        let global = dir.path().join(".git/global-attributes");
+        repo.set_config("core.attributesFile", &global.to_string_lossy()).unwrap();
+        fs::write(&global, "* eol=lf\n*.sh -text\n").unwrap();
+        fs::write(dir.path().join(".gitattributes"), "# comment\n*.txt text\n*.txt -text diff\n*.png binary\n").unwrap();
+        fs::create_dir_all(dir.path().join("sub")).unwrap();
+        fs::write(dir.path().join("sub/.gitattributes"), "*.txt text=auto\n").unwrap();
+        fs::create_dir_all(repo.git_dir().join("info")).unwrap();
+        fs::write(repo.git_dir().join("info/attributes"), "secret.txt !diff\n").unwrap();
+
+        let attributes = Attributes::load(&repo).unwrap();
+        let get = |path, name| attributes.get(path, name).unwrap();
+        assert_eq!(get("a.txt", "text"), State::Unset);
+        assert_eq!(get("a.txt", "diff"), State::Set);
+        assert_eq!(get("a.txt", "eol"), State::Value("lf".into()));
+        assert_eq!(get("sub/a.txt", "text"), State::Value("auto".into()));
+        assert_eq!(get("sub/a.txt", "diff"), State::Set);
+        assert_eq!(get("secret.txt", "diff"), State::Unspecified);
+        assert_eq!(get("run.sh", "text"), State::Unset);
+        assert_eq!(get("x.png", "merge"), State::Unset);
+        assert_eq!(get("x.png", "binary"), State::Set);
+        assert_eq!(get("x.rs", "text"), State::Unspecified);
+    }
+}
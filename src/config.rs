@@ -0,0 +1,681 @@
+use std::{env, fs, ops::Range, path::{Path, PathBuf}};
+use crate::{lock::Lock, repo::Repository};
+
+use crate::error::R;
+
+const MAX_INCLUDE_DEPTH: usize = 10;
+
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub enum Scope {
+    System,
+    Global,
+    Local
+}
+
+impl Scope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Scope::System => "system",
+            Scope::Global => "global",
+            Scope::Local  => "local"
+        }
+    }
+}
+
+#[derive(Clone,Debug)]
+pub struct Entry {
+    pub key: String,
+    // `None` for keys without `=`, which count as `true`
+    pub value: Option<String>,
+    pub scope: Scope
+}
+
+// A key as it appears in a config file, together with the lines it spans so that it can be
+// rewritten in place.
+struct Item {
+    section: String,
+    subsection: Option<String>,
+    name: String,
+    value: Option<String>,
+    lines: Range<usize>
+}
+
+// An occurrence of a section header, the line of the header and the last line belonging to that
+// section. The header ends after `header_len` characters of its line, as a key may follow it.
+struct Section {
+    section: String,
+    subsection: Option<String>,
+    header_line: usize,
+    header_len: usize,
+    last_line: usize
+}
+
+// The merged entries of all config files of a repository, parsed once and then queried by key.
+#[derive(Default)]
+pub struct Config {
+    entries: Vec<Entry>
+}
+
+impl Config {
+    // Reads the system, global and, given a git dir, local config in the order git reads them.
+    pub fn load(git_dir: Option<&Path>) -> R<Config> {
+        let mut entries = Vec::new();
+        for scope in [Scope::System, Scope::Global, Scope::Local].iter() {
+            for file in files(*scope, git_dir) {
+                read_file(&file, *scope, 0, &mut entries)?;
+            }
+        }
+        Ok(Config { entries })
+    }
+
+    pub fn get(&self, key: &str) -> R<Option<String>> {
+        Ok(self.get_all(key)?.pop())
+    }
+
+    pub fn get_bool(&self, key: &str) -> R<Option<bool>> {
+        match self.find(key)?.pop() {
+            Some(Entry { value: None, .. })        => Ok(Some(true)),
+            Some(Entry { value: Some(value), .. }) => parse_bool(&value)
+                .map(Some)
+                .ok_or_else(|| format!("Invalid boolean value '{}' for '{}'.", value, key).into()),
+            None                                   => Ok(None)
+        }
+    }
+
+    pub fn get_int(&self, key: &str) -> R<Option<i64>> {
+        self.get(key)?.map(|v| parse_int(&v).ok_or_else(|| format!("Invalid integer value '{}' for '{}'.", v, key).into())).transpose()
+    }
+
+    // A path value, where a leading `~/` stands for the home dir.
+    pub fn get_path(&self, key: &str) -> R<Option<PathBuf>> {
+        self.get(key)?.map(|v| expand_home(&v)).transpose()
+    }
+
+    pub fn get_all(&self, key: &str) -> R<Vec<String>> {
+        Ok(self.find(key)?.into_iter().map(|e| e.value.unwrap_or_default()).collect())
+    }
+
+    pub fn find(&self, key: &str) -> R<Vec<Entry>> {
+        let key = normalize_key(key)?;
+        Ok(self.entries.iter().filter(|e| e.key == key).cloned().collect())
+    }
+}
+
+pub fn parse_bool(value: &str) -> Option<bool> {
+    match value.to_lowercase().as_str() {
+        "true" | "yes" | "on" | "1"        => Some(true),
+        "false" | "no" | "off" | "0" | ""  => Some(false),
+        _                                  => None
+    }
+}
+
+// Parses an integer with an optional unit suffix of k, m or g (powers of 1024).
+pub fn parse_int(value: &str) -> Option<i64> {
+    let value = value.trim();
+    let (number, factor) = match value.chars().last()?.to_ascii_lowercase() {
+        'k' => (&value[..value.len()-1], 1 << 10),
+        'm' => (&value[..value.len()-1], 1 << 20),
+        'g' => (&value[..value.len()-1], 1 << 30),
+        _   => (value, 1)
+    };
+    number.parse::<i64>().ok()?.checked_mul(factor)
+}
+
+pub fn list() -> R<Vec<Entry>> {
+    Ok(current()?.entries)
+}
+
+pub fn list_scope(scope: Scope) -> R<Vec<Entry>> {
+    let git_dir = Repository::find().ok().map(|repo| repo.git_dir().to_path_buf());
+    let mut entries = Vec::new();
+    for file in files(scope, git_dir.as_deref()) {
+        read_file(&file, scope, 0, &mut entries)?;
+    }
+    Ok(entries)
+}
+
+pub fn list_file(path: &Path, scope: Scope) -> R<Vec<Entry>> {
+    let mut entries = Vec::new();
+    read_file(path, scope, 0, &mut entries)?;
+    Ok(entries)
+}
+
+// The file that is written for the given scope.
+pub fn file(scope: Scope) -> R<PathBuf> {
+    match scope {
+        Scope::System => Ok(env::var_os("GIT_CONFIG_SYSTEM").map(PathBuf::from).unwrap_or_else(|| PathBuf::from("/etc/gitconfig"))),
+        Scope::Global => match env::var_os("GIT_CONFIG_GLOBAL") {
+            Some(path) => Ok(PathBuf::from(path)),
+            None       => Ok(home().ok_or("$HOME is not set.")?.join(".gitconfig"))
+        },
+        Scope::Local => Ok(Repository::find()?.git_dir().join("config"))
+    }
+}
+
+// Sets the key in the given config file. An existing value is replaced in place unless `add` is
+// set, in which case the value is added to the ones already present.
+pub fn set(path: &Path, key: &str, value: &str, add: bool) -> R<()> {
+    let (section, subsection, name) = split_key(key)?;
+    let lock = Lock::acquire(path.to_path_buf())?;
+    let content = read_to_string(path)?;
+    let (items, sections) = parse(&content)
+        .map_err(|e| format!("{} in {:?}", e, path))?;
+    let mut lines = content.lines().map(str::to_string).collect::<Vec<_>>();
+
+    let line = format!("\t{} = {}", name, quote(value));
+    let matches = items.iter()
+        .filter(|i| i.section == section && i.subsection == subsection && i.name == name.to_lowercase())
+        .collect::<Vec<_>>();
+
+    match (matches.as_slice(), add) {
+        ([item], false) => {
+            let mut replacement = header_of(item, &sections, &lines).into_iter().collect::<Vec<_>>();
+            replacement.push(line);
+            lines.splice(item.lines.clone(), replacement);
+        },
+        ([_, _, ..], false) => return Err(format!("Cannot overwrite multiple values of '{}' with a single value.", key).into()),
+        _ => match sections.iter().rev().find(|s| s.section == section && s.subsection == subsection) {
+            Some(s) => lines.insert(s.last_line + 1, line),
+            None    => {
+                lines.push(match &subsection {
+                    Some(sub) => format!("[{} \"{}\"]", section, sub.replace('\\', "\\\\").replace('"', "\\\"")),
+                    None      => format!("[{}]", section)
+                });
+                lines.push(line);
+            }
+        }
+    }
+
+    lock.commit(print_lines(&lines))
+}
+
+// Removes the key from the given config file. Without `all`, it's an error if the key has several
+// values.
+pub fn unset(path: &Path, key: &str, all: bool) -> R<()> {
+    let (section, subsection, name) = split_key(key)?;
+    let lock = Lock::acquire(path.to_path_buf())?;
+    let content = read_to_string(path)?;
+    let (items, sections) = parse(&content)
+        .map_err(|e| format!("{} in {:?}", e, path))?;
+    let mut lines = content.lines().map(str::to_string).collect::<Vec<_>>();
+
+    let matches = items.iter()
+        .filter(|i| i.section == section && i.subsection == subsection && i.name == name.to_lowercase())
+        .collect::<Vec<_>>();
+    if matches.is_empty() {
+        return Err(format!("Key '{}' is not set.", key).into());
+    }
+    if matches.len() > 1 && !all {
+        return Err(format!("Key '{}' has multiple values.", key).into());
+    }
+
+    for item in matches.iter().rev() {
+        let header = header_of(item, &sections, &lines);
+        lines.splice(item.lines.clone(), header);
+    }
+
+    lock.commit(print_lines(&lines))
+}
+
+// Removes all occurrences of the section, like `branch.main`, with their keys from the given config
+// file. False if there were none.
+pub fn remove_section(path: &Path, name: &str) -> R<bool> {
+    let (section, subsection) = split_section(name);
+    let lock = Lock::acquire(path.to_path_buf())?;
+    let content = read_to_string(path)?;
+    let (_, sections) = parse(&content)
+        .map_err(|e| format!("{} in {:?}", e, path))?;
+    let mut lines = content.lines().map(str::to_string).collect::<Vec<_>>();
+
+    let matches = sections.iter()
+        .filter(|s| s.section == section && s.subsection == subsection)
+        .collect::<Vec<_>>();
+    for s in matches.iter().rev() {
+        lines.drain(s.header_line..=s.last_line);
+    }
+
+    lock.commit(print_lines(&lines))?;
+    Ok(!matches.is_empty())
+}
+
+// Renames all occurrences of the section in the given config file, keeping their keys. False if
+// there were none.
+pub fn rename_section(path: &Path, old: &str, new: &str) -> R<bool> {
+    let (section, subsection) = split_section(old);
+    let header = match split_section(new) {
+        (new, Some(sub)) => format!("[{} \"{}\"]", new, sub.replace('\\', "\\\\").replace('"', "\\\"")),
+        (new, None)      => format!("[{}]", new)
+    };
+    let lock = Lock::acquire(path.to_path_buf())?;
+    let content = read_to_string(path)?;
+    let (_, sections) = parse(&content)
+        .map_err(|e| format!("{} in {:?}", e, path))?;
+    let mut lines = content.lines().map(str::to_string).collect::<Vec<_>>();
+
+    let matches = sections.iter()
+        .filter(|s| s.section == section && s.subsection == subsection)
+        .collect::<Vec<_>>();
+    for s in &matches {
+        let rest = lines[s.header_line].chars().skip(s.header_len).collect::<String>();
+        lines[s.header_line] = format!("{}{}", header, rest);
+    }
+
+    lock.commit(print_lines(&lines))?;
+    Ok(!matches.is_empty())
+}
+
+// The section header on the first line of the item, if the item follows it on the same line as in
+// `[core] bare = true`. It has to be kept when the item is replaced or removed.
+fn header_of(item: &Item, sections: &[Section], lines: &[String]) -> Option<String> {
+    sections.iter()
+        .find(|s| s.header_line == item.lines.start)
+        .map(|s| lines[s.header_line].chars().take(s.header_len).collect())
+}
+
+fn current() -> R<Config> {
+    Config::load(Repository::find().ok().as_ref().map(Repository::git_dir))
+}
+
+fn files(scope: Scope, git_dir: Option<&Path>) -> Vec<PathBuf> {
+    match scope {
+        Scope::System if env::var("GIT_CONFIG_NOSYSTEM").ok().and_then(|v| parse_bool(&v)) == Some(true) => Vec::new(),
+        Scope::Global if env::var_os("GIT_CONFIG_GLOBAL").is_none() => {
+            let xdg = env::var_os("XDG_CONFIG_HOME").map(PathBuf::from)
+                .or_else(|| home().map(|h| h.join(".config")));
+            xdg.map(|x| x.join("git/config")).into_iter()
+                .chain(home().map(|h| h.join(".gitconfig")))
+                .collect()
+        },
+        Scope::Local => git_dir.map(|g| g.join("config")).into_iter().collect(),
+        _ => file(scope).into_iter().collect()
+    }
+}
+
+fn home() -> Option<PathBuf> {
+    env::var_os("HOME").map(PathBuf::from)
+}
+
+fn expand_home(path: &str) -> R<PathBuf> {
+    match path.strip_prefix("~/") {
+        Some(rest) => Ok(home().ok_or("$HOME is not set.")?.join(rest)),
+        None       => Ok(PathBuf::from(path))
+    }
+}
+
+fn read_to_string(path: &Path) -> R<String> {
+    if !path.is_file() { return Ok(String::new()); }
+    fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read config file {:?}: {}", path, e).into())
+}
+
+// Reads the entries of the file, followed at each `include.path` entry by those of the included
+// file. Relative include paths are resolved against the directory of the including file.
+fn read_file(path: &Path, scope: Scope, depth: usize, entries: &mut Vec<Entry>) -> R<()> {
+    if depth > MAX_INCLUDE_DEPTH {
+        return Err(format!("Exceeded maximum include depth while including {:?}.", path).into());
+    }
+
+    let (items, _) = parse(&read_to_string(path)?)
+        .map_err(|e| format!("{} in {:?}", e, path))?;
+
+    for item in items {
+        let key = match &item.subsection {
+            Some(sub) => format!("{}.{}.{}", item.section, sub, item.name),
+            None      => format!("{}.{}", item.section, item.name)
+        };
+
+        let include = match &item.value {
+            Some(include) if key == "include.path" => Some(include.clone()),
+            _                                      => None
+        };
+        entries.push(Entry { key, value: item.value, scope });
+
+        if let Some(include) = include {
+            // relative paths are joined to the dir, absolute ones replace it
+            let include = path.parent().unwrap_or(Path::new(".")).join(expand_home(&include)?);
+            read_file(&include, scope, depth + 1, entries)?;
+        }
+    }
+
+    Ok(())
+}
+
+// Parses git's INI dialect. Section and key names are case-insensitive and returned in lowercase,
+// subsections keep their case. Values may be quoted, contain escapes and continue on the next
+// line after a trailing backslash.
+fn parse(content: &str) -> R<(Vec<Item>, Vec<Section>)> {
+    let chars = content.chars().collect::<Vec<_>>();
+    let mut pos = 0;
+    let mut line = 0;
+    let mut line_start = 0;
+    let mut items = Vec::new();
+    let mut sections: Vec<Section> = Vec::new();
+
+    let bad_line = |line: usize| format!("Bad config line {}", line + 1);
+
+    while pos < chars.len() {
+        match chars[pos] {
+            '\n' => { line += 1; pos += 1; line_start = pos; },
+            c if c.is_whitespace() => pos += 1,
+            '#' | ';' => while pos < chars.len() && chars[pos] != '\n' { pos += 1; },
+            '[' => {
+                pos += 1;
+                let start = pos;
+                while pos < chars.len() && (chars[pos].is_alphanumeric() || chars[pos] == '-' || chars[pos] == '.') { pos += 1; }
+                let name = chars[start..pos].iter().collect::<String>();
+                if name.is_empty() { return Err(bad_line(line).into()); }
+
+                let (section, subsection) = if pos < chars.len() && chars[pos].is_whitespace() && chars[pos] != '\n' {
+                    while pos < chars.len() && chars[pos].is_whitespace() && chars[pos] != '\n' { pos += 1; }
+                    if chars.get(pos) != Some(&'"') { return Err(bad_line(line).into()); }
+                    pos += 1;
+                    let mut sub = String::new();
+                    loop {
+                        match chars.get(pos) {
+                            Some('"')  => { pos += 1; break; },
+                            Some('\\') => { sub.push(*chars.get(pos + 1).ok_or_else(|| bad_line(line))?); pos += 2; },
+                            Some('\n') | None => return Err(bad_line(line).into()),
+                            Some(c)    => { sub.push(*c); pos += 1; }
+                        }
+                    }
+                    (name.to_lowercase(), Some(sub))
+                } else {
+                    // deprecated `[section.subsection]` syntax, where the subsection is lowercased
+                    match name.split_once('.') {
+                        Some((section, sub)) => (section.to_lowercase(), Some(sub.to_lowercase())),
+                        None                 => (name.to_lowercase(), None)
+                    }
+                };
+
+                if chars.get(pos) != Some(&']') { return Err(bad_line(line).into()); }
+                pos += 1;
+                sections.push(Section { section, subsection, header_line: line, header_len: pos - line_start, last_line: line });
+            },
+            c if c.is_alphabetic() => {
+                let section = sections.last_mut().ok_or_else(|| bad_line(line))?;
+                let first_line = line;
+                let start = pos;
+                while pos < chars.len() && (chars[pos].is_alphanumeric() || chars[pos] == '-') { pos += 1; }
+                let name = chars[start..pos].iter().collect::<String>().to_lowercase();
+                while pos < chars.len() && (chars[pos] == ' ' || chars[pos] == '\t') { pos += 1; }
+
+                let value = match chars.get(pos) {
+                    Some('=') => {
+                        pos += 1;
+                        Some(parse_value(&chars, &mut pos, &mut line)?)
+                    },
+                    Some('\n') | Some('\r') | Some('#') | Some(';') | None => None,
+                    Some(_) => return Err(bad_line(line).into())
+                };
+
+                section.last_line = line;
+                items.push(Item {
+                    section: section.section.clone(),
+                    subsection: section.subsection.clone(),
+                    name,
+                    value,
+                    lines: first_line..line + 1
+                });
+            },
+            _ => return Err(bad_line(line).into())
+        }
+    }
+
+    Ok((items, sections))
+}
+
+fn parse_value(chars: &[char], pos: &mut usize, line: &mut usize) -> R<String> {
+    let mut value = String::new();
+    let mut pending_space = String::new(); // trailing whitespace is dropped unless quoted
+    let mut quoted = false;
+
+    while *pos < chars.len() && (chars[*pos] == ' ' || chars[*pos] == '\t') { *pos += 1; }
+
+    while let Some(&c) = chars.get(*pos) {
+        *pos += 1;
+        match c {
+            '\n' if quoted => return Err(format!("Bad config line {}: unterminated quote", *line + 1).into()),
+            '\n' => { *pos -= 1; break; },
+            '\\' => {
+                let escaped = match chars.get(*pos) {
+                    Some('\n') => { *line += 1; *pos += 1; continue; },
+                    Some('n')  => '\n',
+                    Some('t')  => '\t',
+                    Some('b')  => '\u{8}',
+                    Some('\\') => '\\',
+                    Some('"')  => '"',
+                    _          => return Err(format!("Bad config line {}: invalid escape", *line + 1).into())
+                };
+                *pos += 1;
+                value.push_str(&pending_space);
+                pending_space.clear();
+                value.push(escaped);
+            },
+            '"' => {
+                value.push_str(&pending_space);
+                pending_space.clear();
+                quoted = !quoted;
+            },
+            ' ' | '\t' | '\r' if !quoted => pending_space.push(c),
+            '#' | ';' if !quoted => {
+                while *pos < chars.len() && chars[*pos] != '\n' { *pos += 1; }
+                break;
+            },
+            c => {
+                value.push_str(&pending_space);
+                pending_space.clear();
+                value.push(c);
+            }
+        }
+    }
+
+    if quoted {
+        return Err(format!("Bad config line {}: unterminated quote", *line + 1).into());
+    }
+    Ok(value)
+}
+
+fn quote(value: &str) -> String {
+    let escaped = value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+        .replace('\t', "\\t");
+    if value.starts_with(' ') || value.ends_with(' ') || value.contains('#') || value.contains(';') {
+        format!("\"{}\"", escaped)
+    } else {
+        escaped
+    }
+}
+
+fn print_lines(lines: &[String]) -> String {
+    lines.iter().map(|l| format!("{}\n", l)).collect()
+}
+
+// Splits `section.subsection.name` into its parts, lowercasing the case-insensitive ones.
+fn split_key(key: &str) -> R<(String, Option<String>, String)> {
+    let invalid = || format!("Invalid key '{}'.", key);
+    let (section, rest) = key.split_once('.').ok_or_else(invalid)?;
+    let (subsection, name) = match rest.rsplit_once('.') {
+        Some((sub, name)) => (Some(sub.to_string()), name),
+        None              => (None, rest)
+    };
+
+    if section.is_empty() || !section.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        || !name.starts_with(|c: char| c.is_ascii_alphabetic())
+        || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+        return Err(invalid().into());
+    }
+
+    Ok((section.to_lowercase(), subsection, name.to_string()))
+}
+
+// Splits a section name like `branch.main` into the section and the subsection.
+fn split_section(name: &str) -> (String, Option<String>) {
+    match name.split_once('.') {
+        Some((section, sub)) => (section.to_lowercase(), Some(sub.to_string())),
+        None                 => (name.to_lowercase(), None)
+    }
+}
+
+pub fn normalize_key(key: &str) -> R<String> {
+    let (section, subsection, name) = split_key(key)?;
+    Ok(match subsection {
+        Some(sub) => format!("{}.{}.{}", section, sub, name.to_lowercase()),
+        None      => format!("{}.{}", section, name.to_lowercase())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testutil::{self, TempDir};
+    use super::*;
+
+    fn entries(content: &str) -> Vec<(String, Option<String>)> {
+        let dir = TempDir::new();
+        let path = dir.path().join("config");
+        fs::write(&path, content).unwrap();
+        list_file(&path, Scope::Local).unwrap().into_iter().map(|e| (e.key, e.value)).collect()
+    }
+
+    fn entry(key: &str, value: Option<&str>) -> (String, Option<String>) {
+        (key.to_string(), value.map(str::to_string))
+    }
+
+    // The content of a config file after applying `change` to it.
+    fn edit<T>(content: &str, change: impl Fn(&Path) -> R<T>) -> R<String> {
+        let dir = TempDir::new();
+        let path = dir.path().join("config");
+        fs::write(&path, content).unwrap();
+        change(&path)?;
+        assert!(!dir.path().join("config.lock").exists());
+        Ok(fs::read_to_string(&path).unwrap())
+    }
+
+    #[test]
+    fn parse_sections_and_keys() {
+        let content = "# comment\n[Core]\n\tBare = false\n\tfilemode\n[remote \"Or\\\"ig\\\\in\"]\n\turl = x ; comment\n[Branch.Main]\n\tremote=origin\n";
+        assert_eq!(entries(content), [
+            entry("core.bare", Some("false")),
+            entry("core.filemode", None),
+            entry("remote.Or\"ig\\in.url", Some("x")),
+            entry("branch.main.remote", Some("origin"))
+        ]);
+    }
+
+    #[test]
+    fn parse_values() {
+        let content = "[a]\n\tquoted = \" x # y \"\n\tescapes = a\\tb\\nc\\\\d\\\"e\n\ttrailing = a b  \t\n\tcontinued = a\\\n  b\n\tempty =\n\tcrlf = v\r\n[b] inline = 1\n";
+        assert_eq!(entries(content), [
+            entry("a.quoted", Some(" x # y ")),
+            entry("a.escapes", Some("a\tb\nc\\d\"e")),
+            entry("a.trailing", Some("a b")),
+            entry("a.continued", Some("a  b")),
+            entry("a.empty", Some("")),
+            entry("a.crlf", Some("v")),
+            entry("b.inline", Some("1"))
+        ]);
+    }
+
+    #[test]
+    fn parse_errors() {
+        for content in ["key = x\n", "[a\n", "[a \"b]\n", "[]\n", "[a]\nk = \"x\n", "[a]\nk = \\x\n", "[a]\nk x\n"].iter() {
+            assert!(parse(content).is_err(), "{:?}", content);
+        }
+    }
+
+    #[test]
+    fn typed_values() {
+        assert_eq!(parse_bool("Yes"), Some(true));
+        assert_eq!(parse_bool("off"), Some(false));
+        assert_eq!(parse_bool(""), Some(false));
+        assert_eq!(parse_bool("always"), None);
+        assert_eq!(parse_int("-12"), Some(-12));
+        assert_eq!(parse_int("2k"), Some(2048));
+        assert_eq!(parse_int("1G"), Some(1 << 30));
+        assert_eq!(parse_int("x"), None);
+    }
+
+    #[test]
+    fn load_merges_includes() {
+        testutil::isolate();
+        let dir = TempDir::new();
+        fs::write(dir.path().join("config"), "[a]\n\tx = 1\n\tflag\n[include]\n\tpath = other\n[a]\n\ty = 3\n").unwrap();
+        fs::write(dir.path().join("other"), "[a]\n\tx = 2\n").unwrap();
+
+        let config = Config::load(Some(dir.path())).unwrap();
+        assert_eq!(config.get("a.x").unwrap().as_deref(), Some("2"));
+        assert_eq!(config.get_all("A.X").unwrap(), ["1", "2"]);
+        assert_eq!(config.get_int("a.y").unwrap(), Some(3));
+        assert_eq!(config.get_bool("a.flag").unwrap(), Some(true));
+        assert!(config.get_bool("a.y").is_err());
+        assert_eq!(config.get("a.z").unwrap(), None);
+    }
+
+    #[test]
+    fn set_replaces_in_place() {
+        let content = "[core]\n\tbare = true # old\n\tother = 1\n";
+        assert_eq!(edit(content, |p| set(p, "core.Bare", "false", false)).unwrap(), "[core]\n\tBare = false\n\tother = 1\n");
+    }
+
+    #[test]
+    fn set_adds_keys_and_sections() {
+        let content = "[core]\n\tbare = true\n[user]\n\tname = x\n";
+        assert_eq!(edit(content, |p| set(p, "core.editor", "vi", false)).unwrap(), "[core]\n\tbare = true\n\teditor = vi\n[user]\n\tname = x\n");
+        assert_eq!(edit(content, |p| set(p, "core.bare", "false", true)).unwrap(), "[core]\n\tbare = true\n\tbare = false\n[user]\n\tname = x\n");
+        assert_eq!(edit("", |p| set(p, "branch.My\"B.merge", " a#b\n", false)).unwrap(), "[branch \"My\\\"B\"]\n\tmerge = \" a#b\\n\"\n");
+        assert!(edit("[a]\n\tx = 1\n\tx = 2\n", |p| set(p, "a.x", "3", false)).is_err());
+        assert!(edit("", |p| set(p, "nodot", "3", false)).is_err());
+    }
+
+    #[test]
+    fn set_and_read_round_trip() {
+        let dir = TempDir::new();
+        let path = dir.path().join("config");
+        for value in ["plain", " spaces ", "quote \" and \\ backslash", "tab\tnew\nline", "semi;colon#hash", ""].iter() {
+            set(&path, "remote.a \"b\".url", value, false).unwrap();
+            let entries = list_file(&path, Scope::Local).unwrap();
+            assert_eq!(entries.len(), 1);
+            assert_eq!(entries[0].key, "remote.a \"b\".url");
+            assert_eq!(entries[0].value.as_deref(), Some(*value));
+        }
+    }
+
+    #[test]
+    fn unset_removes_keys() {
+        let content = "[a]\n\tx = 1\n\ty = 2\n\tx = 3\n";
+        assert_eq!(edit(content, |p| unset(p, "a.y", false)).unwrap(), "[a]\n\tx = 1\n\tx = 3\n");
+        assert_eq!(edit(content, |p| unset(p, "a.x", true)).unwrap(), "[a]\n\ty = 2\n");
+        assert!(edit(content, |p| unset(p, "a.x", false)).is_err());
+        assert!(edit(content, |p| unset(p, "a.z", false)).is_err());
+        assert_eq!(edit("[a]\n\tx = 1 \\\n  2\n\ty = 2\n", |p| unset(p, "a.x", false)).unwrap(), "[a]\n\ty = 2\n");
+    }
+
+    #[test]
+    fn keys_on_the_header_line_keep_the_header() {
+        let content = "[core] bare = true\n\tother = 1\n";
+        assert_eq!(edit(content, |p| set(p, "core.bare", "false", false)).unwrap(), "[core]\n\tbare = false\n\tother = 1\n");
+        assert_eq!(edit(content, |p| unset(p, "core.bare", false)).unwrap(), "[core]\n\tother = 1\n");
+        assert_eq!(edit(content, |p| rename_section(p, "core", "new")).unwrap(), "[new] bare = true\n\tother = 1\n");
+        assert_eq!(edit("[a \"b\"]x = 1\n", |p| unset(p, "a.b.x", false)).unwrap(), "[a \"b\"]\n");
+    }
+
+    #[test]
+    fn sections_are_removed_and_renamed() {
+        let content = "[branch \"a\"]\n\tremote = o\n[core]\n\tbare = true\n[branch \"a\"]\n\tmerge = m\n";
+        assert_eq!(edit(content, |p| remove_section(p, "branch.a")).unwrap(), "[core]\n\tbare = true\n");
+        assert_eq!(edit(content, |p| rename_section(p, "branch.a", "branch.b\"c")).unwrap(),
+                   "[branch \"b\\\"c\"]\n\tremote = o\n[core]\n\tbare = true\n[branch \"b\\\"c\"]\n\tmerge = m\n");
+        assert!(!remove_section(&TempDir::new().path().join("config"), "branch.x").unwrap());
+    }
+
+    #[test]
+    fn normalize_keys() {
+        assert_eq!(normalize_key("Core.Bare").unwrap(), "core.bare");
+        assert_eq!(normalize_key("Branch.Main.Remote").unwrap(), "branch.Main.remote");
+        assert_eq!(normalize_key("url.https://x/.insteadOf").unwrap(), "url.https://x/.insteadof");
+        assert!(normalize_key("core").is_err());
+        assert!(normalize_key("core.1x").is_err());
+        assert!(normalize_key(".x").is_err());
+    }
+}
@@ -0,0 +1,504 @@
+use std::{convert::TryInto, env, fs, path::Path};
+use crate::util;
+
+use crate::error::R;
+
+const MONTHS: [&str; 12] = ["jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec"];
+
+// A point in time as git records it: seconds since the epoch and the UTC offset in minutes of the
+// time zone it was recorded in.
+#[derive(Clone,Copy,Debug)]
+pub struct Date {
+    pub timestamp: i64,
+    pub offset: i32
+}
+
+impl Date {
+    pub fn now() -> R<Date> {
+        let timestamp = util::timestamp()? as i64;
+        Ok(Date { timestamp, offset: local_offset(timestamp) })
+    }
+
+    // Formats the date as in commit headers, e.g. `1112911993 +0200`.
+    pub fn print(&self) -> String {
+        let sign = if self.offset < 0 { '-' } else { '+' };
+        format!("{} {}{:02}{:02}", self.timestamp, sign, self.offset.abs() / 60, self.offset.abs() % 60)
+    }
+}
+
+// Parses the date formats git accepts in GIT_AUTHOR_DATE and friends: its internal format
+// `<unix> <offset>` (optionally prefixed by `@`), RFC 2822 and ISO 8601. Dates without an offset
+// are taken to be in the local time zone.
+pub fn parse(s: &str) -> R<Date> {
+    let s = s.trim();
+    let invalid = || format!("Invalid date format: {}", s);
+
+    let raw = s.strip_prefix('@').unwrap_or(s);
+    let mut parts = raw.split_whitespace();
+    let first = parts.next().unwrap_or_default();
+    if let Ok(timestamp) = first.parse::<i64>() {
+        if first.len() > 8 || s.starts_with('@') { // short numbers are more likely part of another format
+            let offset = match parts.next() {
+                Some(offset) => parse_offset(offset).ok_or_else(invalid)?,
+                None         => 0
+            };
+            if parts.next().is_some() { return Err(invalid().into()); }
+            return Ok(Date { timestamp, offset });
+        }
+    }
+
+    parse_iso8601(s).or_else(|| parse_rfc2822(s)).ok_or_else(|| invalid().into())
+}
+
+// e.g. `2005-04-07T22:13:13`, `2005-04-07 22:13:13.123+02:00` or `2005-04-07T22:13:13Z`
+fn parse_iso8601(s: &str) -> Option<Date> {
+    let (date, rest) = s.split_at(s.find(['T', ' '])?);
+    let mut date = date.split('-').map(|p| p.parse::<i64>().ok());
+    let (year, month, day) = (date.next()??, date.next()??, date.next()??);
+    if date.next().is_some() { return None; }
+
+    let rest = rest[1..].trim();
+    let time_end = rest.find(['Z', '+', '-', ' ']).unwrap_or(rest.len());
+    let (time, zone) = rest.split_at(time_end);
+    let time = time.split('.').next()?; // fractional seconds are dropped
+    let (hour, min, sec) = parse_time(time)?;
+
+    let zone = zone.trim();
+    let offset = match zone {
+        ""      => None,
+        "Z"     => Some(0),
+        zone    => Some(parse_offset(&zone.replace(':', ""))?)
+    };
+    to_date(year, month, day, hour, min, sec, offset)
+}
+
+// e.g. `Thu, 07 Apr 2005 22:13:13 +0200`
+fn parse_rfc2822(s: &str) -> Option<Date> {
+    let s = match s.split_once(',') {
+        Some((_, rest)) => rest,
+        None            => s
+    };
+    let mut parts = s.split_whitespace();
+    let day = parts.next()?.parse().ok()?;
+    let month = parts.next()?.to_lowercase();
+    let month = MONTHS.iter().position(|m| month.starts_with(m))? as i64 + 1;
+    let year = parts.next()?.parse().ok()?;
+    let (hour, min, sec) = parse_time(parts.next()?)?;
+    let offset = match parts.next() {
+        Some("GMT") | Some("UT") | Some("UTC") => Some(0),
+        Some(offset) => Some(parse_offset(offset)?),
+        None         => None
+    };
+    if parts.next().is_some() { return None; }
+    to_date(year, month, day, hour, min, sec, offset)
+}
+
+fn parse_time(time: &str) -> Option<(i64, i64, i64)> {
+    let mut time = time.split(':').map(|p| p.parse::<i64>().ok());
+    let hour = time.next()??;
+    let min = time.next()??;
+    let sec = time.next().unwrap_or(Some(0))?;
+    if time.next().is_some() || hour > 23 || min > 59 || sec > 60 { return None; }
+    Some((hour, min, sec))
+}
+
+// Parses `+hhmm` or `-hhmm` into minutes.
+fn parse_offset(offset: &str) -> Option<i32> {
+    let sign = match offset.chars().next()? {
+        '+' => 1,
+        '-' => -1,
+        _   => return None
+    };
+    let digits = &offset[1..];
+    if digits.len() != 4 || !digits.chars().all(|c| c.is_ascii_digit()) { return None; }
+    let hours = digits[..2].parse::<i32>().ok()?;
+    let mins = digits[2..].parse::<i32>().ok()?;
+    Some(sign * (hours * 60 + mins))
+}
+
+fn to_date(year: i64, month: i64, day: i64, hour: i64, min: i64, sec: i64, offset: Option<i32>) -> Option<Date> {
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) { return None; }
+    let local = days_from_civil(year, month, day) * 86400 + hour * 3600 + min * 60 + sec;
+    let offset = match offset {
+        Some(offset) => offset,
+        // the offset depends on the instant, which is first approximated with the current offset
+        None => local_offset(local - local_offset(local) as i64 * 60)
+    };
+    Some(Date { timestamp: local - offset as i64 * 60, offset })
+}
+
+// Days since 1970-01-01 of the given date in the proleptic Gregorian calendar.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+// Returns the UTC offset in minutes of the local time zone at the given instant. The zone comes
+// from $TZ or /etc/localtime, falling back to UTC if it can't be determined.
+pub fn local_offset(timestamp: i64) -> i32 {
+    let zone = match env::var("TZ") {
+        Ok(tz) => {
+            let tz = tz.strip_prefix(':').unwrap_or(&tz).to_string();
+            let path = if tz.starts_with('/') { tz.clone() } else { format!("/usr/share/zoneinfo/{}", tz) };
+            match read_tzif(Path::new(&path)) {
+                Some(zone) => Some(zone),
+                None       => parse_posix_tz(&tz).map(|rule| Zone { transitions: Vec::new(), default: rule.std, rule: Some(rule) })
+            }
+        },
+        Err(_) => read_tzif(Path::new("/etc/localtime"))
+    };
+    zone.map_or(0, |zone| zone.offset(timestamp) / 60)
+}
+
+// The contents of a TZif file: transitions with the UTC offset in seconds that applies from then on
+// and an optional POSIX TZ rule for instants after the last transition.
+struct Zone {
+    transitions: Vec<(i64, i32)>,
+    default: i32,
+    rule: Option<Rule>
+}
+
+struct Rule {
+    std: i32,
+    dst: Option<(i32, Transition, Transition)>
+}
+
+// A day of the year and the local time in seconds at which daylight saving time starts or ends.
+enum Transition {
+    MonthWeekDay(i64, i64, i64, i64), // month, week (5 = last), weekday (0 = Sunday), time
+    Julian(i64, i64, bool)            // day, time, whether Feb 29 is skipped when counting days
+}
+
+impl Zone {
+    fn offset(&self, timestamp: i64) -> i32 {
+        let rule = self.rule.as_ref();
+        match self.transitions.iter().rposition(|(at, _)| *at <= timestamp) {
+            Some(i) if i + 1 == self.transitions.len() && rule.is_some() => rule.unwrap().offset(timestamp),
+            Some(i)                                                      => self.transitions[i].1,
+            None if self.transitions.is_empty() && rule.is_some()        => rule.unwrap().offset(timestamp),
+            None                                                         => self.default
+        }
+    }
+}
+
+impl Rule {
+    fn offset(&self, timestamp: i64) -> i32 {
+        let (dst, start, end) = match &self.dst {
+            Some(dst) => dst,
+            None      => return self.std
+        };
+        let year = civil_year(timestamp + self.std as i64);
+        // transition times are given in the local time that is in effect before the transition
+        let start = start.at(year) - self.std as i64;
+        let end = end.at(year) - *dst as i64;
+        let in_dst = if start < end {
+            timestamp >= start && timestamp < end
+        } else {
+            timestamp >= start || timestamp < end // southern hemisphere
+        };
+        if in_dst { *dst } else { self.std }
+    }
+}
+
+impl Transition {
+    // Returns the local time of the transition in the given year as seconds since the epoch.
+    fn at(&self, year: i64) -> i64 {
+        match *self {
+            Transition::MonthWeekDay(month, week, weekday, time) => {
+                let first = days_from_civil(year, month, 1);
+                let first_weekday = (first + 4).rem_euclid(7); // 1970-01-01 was a Thursday
+                let mut day = first + (weekday - first_weekday).rem_euclid(7) + (week - 1) * 7;
+                let next_month = if month == 12 { days_from_civil(year + 1, 1, 1) } else { days_from_civil(year, month + 1, 1) };
+                while day >= next_month { day -= 7; }
+                day * 86400 + time
+            },
+            Transition::Julian(day, time, skip_leap) => {
+                let leap = year % 4 == 0 && (year % 100 != 0 || year % 400 == 0);
+                let day = if skip_leap && leap && day >= 60 { day } else if skip_leap { day - 1 } else { day };
+                (days_from_civil(year, 1, 1) + day) * 86400 + time
+            }
+        }
+    }
+}
+
+fn civil_year(timestamp: i64) -> i64 {
+    // see days_from_civil, this is its inverse restricted to the year
+    let days = timestamp.div_euclid(86400) + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month = (5 * day_of_year + 2) / 153;
+    year_of_era + era * 400 + if month >= 10 { 1 } else { 0 }
+}
+
+fn read_tzif(path: &Path) -> Option<Zone> {
+    fn counts(bytes: &[u8]) -> Option<[usize; 6]> {
+        if !bytes.starts_with(b"TZif") { return None; }
+        let mut counts = [0; 6];
+        for (i, count) in counts.iter_mut().enumerate() {
+            *count = u32::from_be_bytes(bytes.get(20 + i*4..24 + i*4)?.try_into().ok()?) as usize;
+        }
+        Some(counts)
+    }
+
+    let bytes = fs::read(path).ok()?;
+    let [isut, isstd, leap, time, types, chars] = counts(&bytes)?;
+    let v1_len = 44 + time * 5 + types * 6 + chars + leap * 8 + isstd + isut;
+
+    // version 2+ files repeat the data with 64-bit times followed by a POSIX TZ footer
+    let (data, time_size, counts, footer) = if bytes[4] >= b'2' {
+        let v2 = bytes.get(v1_len..)?;
+        let [isut, isstd, leap, time, types, chars] = counts(v2)?;
+        let len = 44 + time * 9 + types * 6 + chars + leap * 12 + isstd + isut;
+        let footer = v2.get(len..).and_then(|f| std::str::from_utf8(f).ok()).map(|f| f.trim().to_string());
+        (v2, 8, (time, types), footer)
+    } else {
+        (&bytes[..], 4, (time, types), None)
+    };
+
+    let (time, types) = counts;
+    let times_start = 44;
+    let indices_start = times_start + time * time_size;
+    let types_start = indices_start + time;
+
+    let utoff = |i: usize| -> Option<i32> {
+        Some(i32::from_be_bytes(data.get(types_start + i*6..types_start + i*6 + 4)?.try_into().ok()?))
+    };
+
+    let mut transitions = Vec::new();
+    for i in 0..time {
+        let at = match time_size {
+            8 => i64::from_be_bytes(data.get(times_start + i*8..times_start + i*8 + 8)?.try_into().ok()?),
+            _ => i32::from_be_bytes(data.get(times_start + i*4..times_start + i*4 + 4)?.try_into().ok()?) as i64
+        };
+        let index = *data.get(indices_start + i)? as usize;
+        if index >= types { return None; }
+        transitions.push((at, utoff(index)?));
+    }
+
+    Some(Zone {
+        transitions,
+        default: utoff(0)?,
+        rule: footer.as_deref().and_then(parse_posix_tz)
+    })
+}
+
+// Parses a POSIX TZ string like `CET-1CEST,M3.5.0,M10.5.0/3`. Offsets in these strings are west of
+// UTC, hence the inverted signs.
+fn parse_posix_tz(tz: &str) -> Option<Rule> {
+    fn name(s: &str) -> Option<&str> {
+        if let Some(rest) = s.strip_prefix('<') {
+            return Some(&rest[rest.find('>')? + 1..]);
+        }
+        let end = s.find(|c: char| !c.is_ascii_alphabetic()).unwrap_or(s.len());
+        if end < 3 { None } else { Some(&s[end..]) }
+    }
+
+    fn time(s: &str) -> Option<(i64, &str)> {
+        let end = s.find(|c: char| !(c.is_ascii_digit() || c == ':' || c == '+' || c == '-')).unwrap_or(s.len());
+        let (time, rest) = s.split_at(end);
+        let (sign, time) = match time.strip_prefix('-') {
+            Some(time) => (-1, time),
+            None       => (1, time.strip_prefix('+').unwrap_or(time))
+        };
+        let mut parts = time.split(':').map(|p| p.parse::<i64>().ok());
+        let hours = parts.next()??;
+        let mins = parts.next().unwrap_or(Some(0))?;
+        let secs = parts.next().unwrap_or(Some(0))?;
+        Some((sign * (hours * 3600 + mins * 60 + secs), rest))
+    }
+
+    fn transition(s: &str) -> Option<Transition> {
+        let (date, time) = match s.split_once('/') {
+            Some((date, t)) => (date, time(t)?.0),
+            None            => (s, 7200)
+        };
+        if let Some(mwd) = date.strip_prefix('M') {
+            let mut parts = mwd.split('.').map(|p| p.parse::<i64>().ok());
+            Some(Transition::MonthWeekDay(parts.next()??, parts.next()??, parts.next()??, time))
+        } else if let Some(day) = date.strip_prefix('J') {
+            Some(Transition::Julian(day.parse().ok()?, time, true))
+        } else {
+            Some(Transition::Julian(date.parse().ok()?, time, false))
+        }
+    }
+
+    let rest = name(tz)?;
+    let (std, rest) = time(rest)?;
+    let std = -std as i32;
+    if rest.is_empty() {
+        return Some(Rule { std, dst: None });
+    }
+
+    let rest = name(rest)?;
+    let (dst, rest) = match rest.chars().next() {
+        Some(',') | None => (std + 3600, rest),
+        Some(_)          => { let (dst, rest) = time(rest)?; (-dst as i32, rest) }
+    };
+
+    // without rules, the US rules are assumed like glibc does
+    let mut rules = if rest.is_empty() { "M3.2.0,M11.1.0" } else { rest.strip_prefix(',')? }.split(',');
+    let start = transition(rules.next()?)?;
+    let end = transition(rules.next()?)?;
+    Some(Rule { std, dst: Some((dst, start, end)) })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testutil::TempDir;
+    use super::*;
+
+    fn date(s: &str) -> (i64, i32) {
+        let date = parse(s).unwrap_or_else(|e| panic!("{}: {}", s, e));
+        (date.timestamp, date.offset)
+    }
+
+    // The offset in minutes the rule gives at the instant.
+    fn rule_offset(tz: &str, timestamp: i64) -> i32 {
+        parse_posix_tz(tz).unwrap_or_else(|| panic!("{}", tz)).offset(timestamp) / 60
+    }
+
+    #[test]
+    fn parse_internal_format() {
+        assert_eq!(date("1112911993 +0200"), (1112911993, 120));
+        assert_eq!(date("1112911993 -0130"), (1112911993, -90));
+        assert_eq!(date("@1112911993"), (1112911993, 0));
+        assert_eq!(date("@0 +0000"), (0, 0));
+        assert_eq!(date("  1112911993 +0000  "), (1112911993, 0));
+    }
+
+    #[test]
+    fn parse_rfc2822_dates() {
+        assert_eq!(date("Thu, 07 Apr 2005 22:13:13 +0200"), (1112904793, 120));
+        assert_eq!(date("7 April 2005 22:13:13 -0130"), (1112917393, -90));
+        assert_eq!(date("Thu, 07 Apr 2005 22:13 GMT"), (1112911980, 0));
+    }
+
+    #[test]
+    fn parse_iso8601_dates() {
+        assert_eq!(date("2005-04-07T22:13:13Z"), (1112911993, 0));
+        assert_eq!(date("2005-04-07 22:13:13.123+02:00"), (1112904793, 120));
+        assert_eq!(date("2005-04-07T22:13:13 -0130"), (1112917393, -90));
+        assert_eq!(date("2000-02-29T00:00Z"), (951782400, 0));
+    }
+
+    #[test]
+    fn parse_invalid_dates() {
+        for s in ["", "yesterday", "12345", "1112911993 +02", "1112911993 +0200 x", "2005-13-01T00:00:00Z",
+                  "2005-04-07T24:00:00Z", "2005-04-07T22:13:13+2", "Thu, 07 Foo 2005 22:13:13 +0200"].iter() {
+            assert!(parse(s).is_err(), "{}", s);
+        }
+    }
+
+    #[test]
+    fn print_dates() {
+        assert_eq!(Date { timestamp: 1112911993, offset: 120 }.print(), "1112911993 +0200");
+        assert_eq!(Date { timestamp: 0, offset: -90 }.print(), "0 -0130");
+    }
+
+    #[test]
+    fn civil_dates() {
+        assert_eq!(days_from_civil(1970, 1, 1), 0);
+        assert_eq!(days_from_civil(2000, 2, 29) * 86400, 951782400);
+        assert_eq!(days_from_civil(1600, 3, 1) * 86400, -11670912000);
+        assert_eq!(civil_year(-1), 1969);
+        assert_eq!(civil_year(951782400), 2000);
+        assert_eq!(civil_year(1609459199), 2020);
+        assert_eq!(civil_year(1609459200), 2021);
+    }
+
+    #[test]
+    fn posix_rules() {
+        let cet = "CET-1CEST,M3.5.0,M10.5.0/3";
+        assert_eq!(rule_offset(cet, 1609459200), 60);
+        assert_eq!(rule_offset(cet, 1625097600), 120);
+        assert_eq!(rule_offset(cet, 1616893200 - 1), 60);
+        assert_eq!(rule_offset(cet, 1616893200), 120);
+        assert_eq!(rule_offset(cet, 1635642000 - 1), 120);
+        assert_eq!(rule_offset(cet, 1635642000), 60);
+
+        // southern hemisphere, where daylight saving time spans the new year
+        let sydney = "AEST-10AEDT,M10.1.0,M4.1.0/3";
+        assert_eq!(rule_offset(sydney, 1609459200), 660);
+        assert_eq!(rule_offset(sydney, 1625097600), 600);
+        assert_eq!(rule_offset(sydney, 1617465600 - 1), 660);
+        assert_eq!(rule_offset(sydney, 1617465600), 600);
+        assert_eq!(rule_offset(sydney, 1633190400), 660);
+
+        // the US rules are the default
+        assert_eq!(rule_offset("EST5EDT", 1615705200 - 1), -300);
+        assert_eq!(rule_offset("EST5EDT", 1615705200), -240);
+        assert_eq!(rule_offset("EST5EDT", 1636264800), -300);
+
+        assert_eq!(rule_offset("UTC0", 1625097600), 0);
+        assert_eq!(rule_offset("<+0330>-3:30", 1625097600), 210);
+        assert_eq!(rule_offset("<-03>3", 1625097600), -180);
+        assert!(parse_posix_tz("X1").is_none());
+        assert!(parse_posix_tz("CET-1CEST,M3.5.0").is_none());
+    }
+
+    // A version 2 TZif file with an empty version 1 block, two transitions and a footer.
+    fn tzif(footer: &str) -> Vec<u8> {
+        fn header(version: u8, time: u32, types: u32, chars: u32) -> Vec<u8> {
+            let mut header = b"TZif".to_vec();
+            header.push(version);
+            header.extend(&[0; 15]);
+            for count in [0, 0, 0, time, types, chars].iter() {
+                header.extend(&count.to_be_bytes());
+            }
+            header
+        }
+
+        let mut bytes = header(b'2', 0, 1, 4);
+        bytes.extend(&[0, 0, 0, 0, 0, 0]);
+        bytes.extend(b"UTC\0");
+
+        bytes.extend(header(b'2', 2, 2, 8));
+        bytes.extend(&1000i64.to_be_bytes());
+        bytes.extend(&2000i64.to_be_bytes());
+        bytes.extend(&[1, 0]);
+        bytes.extend(&3600i32.to_be_bytes());
+        bytes.extend(&[0, 0]);
+        bytes.extend(&7200i32.to_be_bytes());
+        bytes.extend(&[1, 4]);
+        bytes.extend(b"CET\0CEST");
+        bytes.extend(format!("\n{}\n", footer).as_bytes());
+        bytes
+    }
+
+    #[test]
+    fn read_tzif_files() {
+        let dir = TempDir::new();
+        let path = dir.path().join("zone");
+        fs::write(&path, tzif("CET-1CEST,M3.5.0,M10.5.0/3")).unwrap();
+
+        let zone = read_tzif(&path).unwrap();
+        assert_eq!(zone.transitions, [(1000, 7200), (2000, 3600)]);
+        assert_eq!(zone.offset(999), 3600);
+        assert_eq!(zone.offset(1000), 7200);
+        assert_eq!(zone.offset(2000), 3600);
+        assert_eq!(zone.offset(1625097600), 7200); // footer rule after the last transition
+        assert_eq!(zone.offset(1609459200), 3600);
+
+        fs::write(&path, tzif("")).unwrap();
+        assert_eq!(read_tzif(&path).unwrap().offset(1625097600), 3600);
+    }
+
+    #[test]
+    fn read_invalid_tzif_files() {
+        let dir = TempDir::new();
+        let path = dir.path().join("zone");
+        assert!(read_tzif(&path).is_none());
+
+        let bytes = tzif("");
+        fs::write(&path, &bytes[..bytes.len() - 20]).unwrap();
+        assert!(read_tzif(&path).is_none());
+        fs::write(&path, b"not a zone").unwrap();
+        assert!(read_tzif(&path).is_none());
+    }
+}
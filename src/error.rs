@@ -0,0 +1,131 @@
+use std::{fmt::{self, Display, Formatter}, io, path::PathBuf};
+use crate::sha::Sha;
+
+pub type R<T> = std::result::Result<T, Error>;
+
+// The kinds of failure callers may want to tell apart. Everything else ends up in `Other` with a
+// message meant for the user.
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    NotARepository(PathBuf),
+    ObjectNotFound(Sha),
+    CorruptObject(String),
+    BadPack(String),
+    Protocol(String),
+    Http(reqwest::Error),
+    Other(String)
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(e)               => write!(f, "{}", e),
+            Error::NotARepository(dir) => write!(f, "Not a git repository (or any of the parent directories): {:?}.", dir),
+            Error::ObjectNotFound(id)  => write!(f, "Object {} not found.", id),
+            Error::CorruptObject(msg)  |
+            Error::BadPack(msg)        |
+            Error::Protocol(msg)       |
+            Error::Other(msg)          => write!(f, "{}", msg),
+            Error::Http(e)             => write!(f, "{}", e)
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e)   => Some(e),
+            Error::Http(e) => Some(e),
+            _              => None
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::Io(e)
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(e: reqwest::Error) -> Error {
+        Error::Http(e)
+    }
+}
+
+impl From<String> for Error {
+    fn from(msg: String) -> Error {
+        Error::Other(msg)
+    }
+}
+
+impl From<&str> for Error {
+    fn from(msg: &str) -> Error {
+        Error::Other(msg.to_string())
+    }
+}
+
+// Conversion and parse errors carry no context of their own, so they are kept as messages.
+macro_rules! from_display {
+    ($($t:ty),*) => {
+        $(impl From<$t> for Error {
+            fn from(e: $t) -> Error {
+                Error::Other(e.to_string())
+            }
+        })*
+    }
+}
+
+from_display!(
+    std::array::TryFromSliceError,
+    std::num::ParseIntError,
+    std::num::TryFromIntError,
+    std::str::Utf8Error,
+    std::string::FromUtf8Error,
+    std::time::SystemTimeError
+);
+
+#[cfg(test)]
+mod tests {
+    use std::{convert::TryFrom, error::Error as _, fs, str::FromStr};
+    use crate::{obj::{self, ObjType}, testutil};
+    use super::*;
+
+    #[test]
+    fn display_messages() {
+        let id = Sha::from_str(&"a".repeat(40)).unwrap();
+        assert_eq!(Error::ObjectNotFound(id).to_string(), format!("Object {} not found.", "a".repeat(40)));
+        assert_eq!(Error::NotARepository(PathBuf::from("/x")).to_string(), "Not a git repository (or any of the parent directories): \"/x\".");
+        assert_eq!(Error::BadPack("Bad pack.".into()).to_string(), "Bad pack.");
+        assert_eq!(Error::from(io::Error::new(io::ErrorKind::Other, "Broken.")).to_string(), "Broken.");
+    }
+
+    #[test]
+    fn conversions() {
+        assert!(matches!(Error::from("x"), Error::Other(msg) if msg == "x"));
+        assert!(matches!(Error::from(String::from("x")), Error::Other(msg) if msg == "x"));
+        assert!(matches!(Error::from(u8::from_str("x").unwrap_err()), Error::Other(_)));
+        assert!(matches!(Error::from(u8::try_from(300).unwrap_err()), Error::Other(_)));
+
+        let e = Error::from(io::Error::new(io::ErrorKind::NotFound, "Gone."));
+        assert!(matches!(&e, Error::Io(inner) if inner.kind() == io::ErrorKind::NotFound));
+        assert!(e.source().is_some());
+        assert!(Error::from("x").source().is_none());
+    }
+
+    #[test]
+    fn object_errors_are_typed() {
+        let (_dir, repo) = testutil::repo();
+        let missing = obj::hash(ObjType::Blob, b"missing");
+        assert!(matches!(obj::read(&repo, &missing), Err(Error::ObjectNotFound(id)) if id == missing));
+
+        let id = obj::write(&repo, ObjType::Blob, b"corrupt").unwrap();
+        let path = repo.git_dir().join("objects").join(&id.value()[..2]).join(&id.value()[2..]);
+        let mut permissions = fs::metadata(&path).unwrap().permissions();
+        permissions.set_readonly(false);
+        fs::set_permissions(&path, permissions).unwrap();
+        fs::write(&path, b"not zlib").unwrap();
+        assert!(matches!(obj::read(&repo, &id), Err(Error::CorruptObject(_))));
+    }
+}
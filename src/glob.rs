@@ -0,0 +1,207 @@
+// A pattern of an ignore or attributes file. `base` is the dir of the file relative to the work
+// tree, patterns of files outside the work tree apply to all of it.
+#[derive(Clone, Debug)]
+pub struct Pattern {
+    glob: String,
+    base: String,
+    dir_only: bool,
+    anchored: bool
+}
+
+impl Pattern {
+    pub fn parse(pattern: &str, base: &str) -> Pattern {
+        let (dir_only, glob) = match pattern.strip_suffix('/') {
+            Some(rest) => (true, rest),
+            None       => (false, pattern)
+        };
+        // a slash anywhere but at the end ties the pattern to the dir of the file
+        let anchored = glob.contains('/');
+        let glob = glob.strip_prefix('/').unwrap_or(glob);
+        Pattern { glob: glob.to_string(), base: base.to_string(), dir_only, anchored }
+    }
+
+    pub fn matches(&self, path: &str, is_dir: bool) -> bool {
+        let relative = match self.base.as_str() {
+            ""   => path,
+            base => match path.strip_prefix(base).and_then(|p| p.strip_prefix('/')) {
+                Some(relative) => relative,
+                None           => return false
+            }
+        };
+        if self.dir_only && !is_dir {
+            return false;
+        }
+        if self.anchored {
+            wildmatch(self.glob.as_bytes(), relative.as_bytes(), true)
+        } else {
+            let name = relative.rsplit('/').next().unwrap_or(relative);
+            wildmatch(self.glob.as_bytes(), name.as_bytes(), true)
+        }
+    }
+}
+
+// Matches the path against the pattern like git's wildmatch: `?` matches any char, `*` any
+// number of chars and `[...]` a set of chars. With `pathname` they do not match a slash and `**`
+// between slashes matches any number of dirs.
+pub fn wildmatch(pattern: &[u8], path: &[u8], pathname: bool) -> bool {
+    fn at(pattern: &[u8], p: usize, path: &[u8], s: usize, pathname: bool) -> bool {
+        match pattern.get(p) {
+            None => s == path.len(),
+            Some(b'*') if pathname && pattern.get(p + 1) == Some(&b'*')
+                && (p == 0 || pattern[p - 1] == b'/')
+                && matches!(pattern.get(p + 2), None | Some(b'/')) => {
+                if p + 2 == pattern.len() {
+                    return true;
+                }
+                // `**/` also matches no dir at all
+                at(pattern, p + 3, path, s, pathname)
+                    || (s..path.len()).any(|i| path[i] == b'/' && at(pattern, p + 3, path, i + 1, pathname))
+            },
+            Some(b'*') => {
+                let p = p + pattern[p..].iter().take_while(|&&c| c == b'*').count();
+                let end = match path[s..].iter().position(|&c| c == b'/') {
+                    Some(i) if pathname => s + i,
+                    _                   => path.len()
+                };
+                (s..=end).any(|i| at(pattern, p, path, i, pathname))
+            },
+            Some(b'?') => path.get(s).is_some_and(|&c| !pathname || c != b'/') && at(pattern, p + 1, path, s + 1, pathname),
+            Some(b'[') => match class(pattern, p + 1, path.get(s).copied().filter(|&c| !pathname || c != b'/')) {
+                Some((matched, next)) => matched && at(pattern, next, path, s + 1, pathname),
+                None                  => path.get(s) == Some(&b'[') && at(pattern, p + 1, path, s + 1, pathname)
+            },
+            Some(b'\\') if p + 1 < pattern.len() =>
+                path.get(s) == Some(&pattern[p + 1]) && at(pattern, p + 2, path, s + 1, pathname),
+            Some(&c) => path.get(s) == Some(&c) && at(pattern, p + 1, path, s + 1, pathname)
+        }
+    }
+
+    // Whether the char is in the set starting at `p`, and where the pattern continues after it.
+    // `None` if the set is not closed, in which case the bracket is taken literally.
+    fn class(pattern: &[u8], mut p: usize, c: Option<u8>) -> Option<(bool, usize)> {
+        let negated = matches!(pattern.get(p), Some(b'!') | Some(b'^'));
+        if negated {
+            p += 1;
+        }
+        let start = p;
+        let mut matched = false;
+        loop {
+            let first = *pattern.get(p)?;
+            if first == b']' && p > start {
+                return Some((c.is_some() && matched != negated, p + 1));
+            }
+            let first = if first == b'\\' { p += 1; *pattern.get(p)? } else { first };
+            if pattern.get(p + 1) == Some(&b'-') && pattern.get(p + 2).is_some_and(|&l| l != b']') {
+                let last = pattern[p + 2];
+                matched |= c.is_some_and(|c| first <= c && c <= last);
+                p += 3;
+            } else {
+                matched |= c == Some(first);
+                p += 1;
+            }
+        }
+    }
+
+    at(pattern, 0, path, 0, pathname)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // From git's t3070-wildmatch.sh: text, pattern, and whether it matches with and without
+    // `pathname`.
+    const VECTORS: &[(&str, &str, bool, bool)] = &[
+        ("foo", "foo", true, true),
+        ("foo", "bar", false, false),
+        ("", "", true, true),
+        ("foo", "???", true, true),
+        ("foo", "??", false, false),
+        ("foo", "*", true, true),
+        ("foo", "f*", true, true),
+        ("foo", "*f", false, false),
+        ("foo", "*foo*", true, true),
+        ("foobar", "*ob*a*r*", true, true),
+        ("aaaaaaabababab", "*ab", true, true),
+        ("foo*", "foo\\*", true, true),
+        ("foobar", "foo\\*bar", false, false),
+        ("f\\oo", "f\\\\oo", true, true),
+        ("ball", "*[al]?", true, true),
+        ("ten", "[ten]", false, false),
+        ("ten", "**[!te]", true, true),
+        ("ten", "**[!ten]", false, false),
+        ("ten", "t[a-g]n", true, true),
+        ("ten", "t[!a-g]n", false, false),
+        ("ton", "t[!a-g]n", true, true),
+        ("ton", "t[^a-g]n", true, true),
+        ("a]b", "a[]]b", true, true),
+        ("a-b", "a[]-]b", true, true),
+        ("a]b", "a[]-]b", true, true),
+        ("aab", "a[]-]b", false, false),
+        ("aab", "a[]a-]b", true, true),
+        ("]", "]", true, true),
+        ("foo/baz/bar", "foo*bar", false, true),
+        ("foo/baz/bar", "foo**bar", false, true),
+        ("foobazbar", "foo**bar", true, true),
+        ("foo/baz/bar", "foo/**/bar", true, true),
+        ("foo/baz/bar", "foo/**/**/bar", true, false),
+        ("foo/b/a/z/bar", "foo/**/bar", true, true),
+        ("foo/b/a/z/bar", "foo/**/**/bar", true, true),
+        ("foo/bar", "foo/**/bar", true, false),
+        ("foo/bar", "foo/**/**/bar", true, false),
+        ("foo/bar", "foo?bar", false, true),
+        ("foo/bar", "foo[/]bar", false, true),
+        ("foo/bar", "f[^eiu][^eiu][^eiu][^eiu][^eiu]r", false, true),
+        ("foo-bar", "f[^eiu][^eiu][^eiu][^eiu][^eiu]r", true, true),
+        ("foo", "**/foo", true, false),
+        ("bar/baz/foo", "**/foo", true, true),
+        ("bar/baz/foo", "*/foo", false, true),
+        ("foo/bar/baz", "**/bar*", false, true),
+        ("deep/foo/bar/baz", "**/bar/*", true, true),
+        ("deep/foo/bar/baz/", "**/bar/*", false, true),
+        ("deep/foo/bar/baz/", "**/bar/**", true, true),
+        ("deep/foo/bar", "**/bar/*", false, false),
+        ("deep/foo/bar/", "**/bar/**", true, true),
+        ("foo/bar/baz", "**/bar**", false, true),
+        ("foo/bar/baz/x", "*/bar/**", true, true),
+        ("deep/foo/bar/baz/x", "*/bar/**", false, true),
+        ("deep/foo/bar/baz/x", "**/bar/*/*", true, true),
+        ("acrt", "a[c-c]st", false, false),
+        ("acrt", "a[c-c]rt", true, true),
+        ("[ab]", "\\[ab]", true, true),
+        ("[ab]", "[[]ab]", true, true),
+        ("?a?b", "\\??\\?b", true, true),
+        ("abc", "\\a\\b\\c", true, true),
+        ("-adobe-courier-bold-o-normal--12-120-75-75-m-70-iso8859-1", "-*-*-*-*-*-*-12-*-*-*-m-*-*-*", true, true),
+        ("-adobe-courier-bold-o-normal--12-120-75-75-X-70-iso8859-1", "-*-*-*-*-*-*-12-*-*-*-m-*-*-*", false, false),
+        ("XXX/adobe/courier/bold/o/normal//12/120/75/75/m/70/iso8859/1", "XXX/*/*/*/*/*/*/12/*/*/*/m/*/*/*", true, true),
+        ("abcd/abcdefg/abcdefghijk/abcdefghijklmnop.txt", "**/*a*b*g*n*t", true, true),
+        ("abcd/abcdefg/abcdefghijk/abcdefghijklmnop.txtz", "**/*a*b*g*n*t", false, false)
+    ];
+
+    #[test]
+    fn wildmatch_vectors() {
+        for (text, pattern, pathname, plain) in VECTORS {
+            assert_eq!(wildmatch(pattern.as_bytes(), text.as_bytes(), true), *pathname, "'{}' against '{}' with pathname", text, pattern);
+            assert_eq!(wildmatch(pattern.as_bytes(), text.as_bytes(), false), *plain, "'{}' against '{}'", text, pattern);
+        }
+    }
+
+    #[test]
+    fn patterns() {
+        let pattern = Pattern::parse("*.o", "");
+        assert!(pattern.matches("a.o", false));
+        assert!(pattern.matches("dir/sub/a.o", false));
+
+        let pattern = Pattern::parse("/build/", "");
+        assert!(pattern.matches("build", true));
+        assert!(!pattern.matches("build", false));
+        assert!(!pattern.matches("src/build", true));
+
+        let pattern = Pattern::parse("doc/*.txt", "src");
+        assert!(pattern.matches("src/doc/a.txt", false));
+        assert!(!pattern.matches("src/doc/x/a.txt", false));
+        assert!(!pattern.matches("doc/a.txt", false));
+        assert!(!pattern.matches("srcdoc/a.txt", false));
+    }
+}
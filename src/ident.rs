@@ -0,0 +1,83 @@
+use std::env;
+use crate::{config::Config, date::{self, Date}};
+
+use crate::error::R;
+
+#[derive(Clone,Copy,Debug)]
+pub enum Role {
+    Author,
+    Committer
+}
+
+impl Role {
+    fn env_prefix(&self) -> &'static str {
+        match self {
+            Role::Author    => "GIT_AUTHOR",
+            Role::Committer => "GIT_COMMITTER"
+        }
+    }
+
+    fn config_section(&self) -> &'static str {
+        match self {
+            Role::Author    => "author",
+            Role::Committer => "committer"
+        }
+    }
+}
+
+// Returns the identity in the form used by commit headers, e.g. `Jo Doe <jo@example.com>
+// 1112911993 +0200`. Name and email come from GIT_AUTHOR_NAME/EMAIL (or GIT_COMMITTER_*), then
+// author.name/email (or committer.*), then user.name/email. The date comes from GIT_AUTHOR_DATE
+// (or GIT_COMMITTER_DATE) and defaults to now in the local time zone.
+//
+// If `strict` is not set, a missing identity is made up from the login name instead of failing,
+// as git does for reflogs.
+pub fn get(config: &Config, role: Role, strict: bool) -> R<String> {
+    let setting = |name: &str| -> R<Option<String>> {
+        if let Ok(value) = env::var(format!("{}_{}", role.env_prefix(), name.to_uppercase())) {
+            return Ok(Some(value));
+        }
+        match config.get(&format!("{}.{}", role.config_section(), name))? {
+            Some(value) => Ok(Some(value)),
+            None        => config.get(&format!("user.{}", name))
+        }
+    };
+
+    let name = setting("name")?.filter(|n| !n.is_empty());
+    let email = setting("email")?.or_else(|| env::var("EMAIL").ok());
+
+    let (name, email) = match (name, email) {
+        (Some(name), Some(email)) => (name, email),
+        _ if strict => return Err("Identity unknown. Please tell me who you are by setting user.name and user.email, e.g.\n\n  \
+            config set --global user.name \"Your Name\"\n  config set --global user.email \"you@example.com\"".into()),
+        (name, email) => {
+            let login = env::var("USER").unwrap_or_else(|_| "unknown".to_string());
+            (name.unwrap_or(login), email.unwrap_or_default())
+        }
+    };
+
+    let date = match env::var(format!("{}_DATE", role.env_prefix())) {
+        Ok(date) if !date.is_empty() => date::parse(&date)?,
+        _                            => Date::now()?
+    };
+
+    Ok(format(&name, &email, &date))
+}
+
+pub fn format(name: &str, email: &str, date: &Date) -> String {
+    // angle brackets and newlines would corrupt the header
+    let clean = |s: &str| s.chars().filter(|c| !"<>\n".contains(*c)).collect::<String>().trim().to_string();
+    format!("{} <{}> {}", clean(name), clean(email), date.print())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_cleans_name_and_email() {
+        let date = Date { timestamp: 1112911993, offset: -420 };
+        assert_eq!(format("Jo Doe", "jo@example.com", &date), "Jo Doe <jo@example.com> 1112911993 -0700");
+        assert_eq!(format(" <Jo>\n Doe ", "<jo@example.com>", &date), "Jo Doe <jo@example.com> 1112911993 -0700");
+    }
+}
@@ -0,0 +1,192 @@
+use std::{cell::RefCell, collections::HashMap, env, fs, path::{Path, PathBuf}, rc::Rc};
+use crate::{glob, repo::Repository};
+
+use crate::error::R;
+
+// One line of an ignore file.
+#[derive(Clone, Debug)]
+pub struct Pattern {
+    pub source: String,
+    pub line: usize,
+    pub text: String,
+    pub negated: bool,
+    pattern: glob::Pattern
+}
+
+impl Pattern {
+    fn parse(line: &str, source: &str, number: usize, base: &str) -> Option<Pattern> {
+        let line = trim_trailing_spaces(line);
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+
+        let (negated, pattern) = match line.strip_prefix('!') {
+            Some(rest) => (true, rest),
+            None       => (false, line)
+        };
+        let pattern = pattern.strip_prefix('\\').filter(|p| p.starts_with('#') || p.starts_with('!')).unwrap_or(pattern);
+        Some(Pattern {
+            source: source.to_string(), line: number, text: line.to_string(), negated,
+            pattern: glob::Pattern::parse(pattern, base)
+        })
+    }
+
+    fn matches(&self, path: &str, is_dir: bool) -> bool {
+        self.pattern.matches(path, is_dir)
+    }
+}
+
+// The ignore rules of a work tree. The `.gitignore` files of dirs are read when a path in them is
+// first looked up.
+pub struct Ignore {
+    work_tree: PathBuf,
+    global: Vec<Pattern>,
+    dirs: RefCell<HashMap<String, Rc<Vec<Pattern>>>>
+}
+
+impl Ignore {
+    // Reads `core.excludesFile` (by default `$XDG_CONFIG_HOME/git/ignore`) and `info/exclude`.
+    pub fn load(repo: &Repository) -> R<Ignore> {
+        let excludes = match repo.config()?.get_path("core.excludesFile")? {
+            Some(path) => Some(path),
+            None       => env::var_os("XDG_CONFIG_HOME").map(PathBuf::from)
+                .or_else(|| env::var_os("HOME").map(|h| PathBuf::from(h).join(".config")))
+                .map(|x| x.join("git/ignore"))
+        };
+
+        let mut global = Vec::new();
+        if let Some(excludes) = excludes {
+            global.extend(read(&excludes, &excludes.to_string_lossy(), "")?);
+        }
+        global.extend(read(&repo.git_dir().join("info/exclude"), ".git/info/exclude", "")?);
+
+        Ok(Ignore { work_tree: repo.work_tree()?.to_path_buf(), global, dirs: RefCell::new(HashMap::new()) })
+    }
+
+    // The pattern that decides whether the path itself is ignored, not looking at its parent
+    // dirs. Within a file the last matching line wins, and `.gitignore` files of deeper dirs
+    // take precedence over those above them and over the global files.
+    pub fn find(&self, path: &str, is_dir: bool) -> R<Option<Pattern>> {
+        let mut dir = path;
+        while let Some(pos) = dir.rfind('/') {
+            dir = &dir[..pos];
+            if let Some(pattern) = self.patterns(dir)?.iter().rev().find(|p| p.matches(path, is_dir)) {
+                return Ok(Some(pattern.clone()));
+            }
+        }
+        if let Some(pattern) = self.patterns("")?.iter().rev().find(|p| p.matches(path, is_dir)) {
+            return Ok(Some(pattern.clone()));
+        }
+        Ok(self.global.iter().rev().find(|p| p.matches(path, is_dir)).cloned())
+    }
+
+    // Like `find`, but a path in an ignored dir is matched by the pattern of the dir, since git
+    // does not look into ignored dirs and their files cannot be included again.
+    pub fn find_with_parents(&self, path: &str, is_dir: bool) -> R<Option<Pattern>> {
+        for (pos, _) in path.match_indices('/') {
+            match self.find(&path[..pos], true)? {
+                Some(pattern) if !pattern.negated => return Ok(Some(pattern)),
+                _                                 => {}
+            }
+        }
+        self.find(path, is_dir)
+    }
+
+    pub fn is_ignored(&self, path: &str, is_dir: bool) -> R<bool> {
+        Ok(self.find_with_parents(path, is_dir)?.is_some_and(|p| !p.negated))
+    }
+
+    fn patterns(&self, dir: &str) -> R<Rc<Vec<Pattern>>> {
+        if let Some(patterns) = self.dirs.borrow().get(dir) {
+            return Ok(patterns.clone());
+        }
+        let source = if dir.is_empty() { ".gitignore".to_string() } else { format!("{}/.gitignore", dir) };
+        let patterns = Rc::new(read(&self.work_tree.join(&source), &source, dir)?);
+        self.dirs.borrow_mut().insert(dir.to_string(), patterns.clone());
+        Ok(patterns)
+    }
+}
+
+fn read(path: &Path, source: &str, base: &str) -> R<Vec<Pattern>> {
+    if !path.is_file() {
+        return Ok(Vec::new());
+    }
+    let content = fs::read(path)
+        .map_err(|e| format!("Failed to read ignore file {:?}: {}", path, e))?;
+    Ok(String::from_utf8_lossy(&content).lines().enumerate()
+        .filter_map(|(i, line)| Pattern::parse(line, source, i + 1, base))
+        .collect())
+}
+
+// Trailing spaces are dropped unless escaped with a backslash.
+fn trim_trailing_spaces(line: &str) -> &str {
+    let mut end = line.len();
+    while line[..end].ends_with(' ') && !line[..end - 1].ends_with('\\') {
+        end -= 1;
+    }
+    &line[..end]
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testutil;
+    use super::*;
+
+    fn setup(files: &[(&str, &str)]) -> (testutil::TempDir, Ignore) {
+        let (dir, repo) = testutil::repo();
+        let excludes = dir.path().join(".git/excludes");
+        repo.set_config("core.excludesFile", &excludes.to_string_lossy()).unwrap();
+        for (path, content) in files {
+            let file = dir.path().join(path);
+            fs::create_dir_all(file.parent().unwrap()).unwrap();
+            fs::write(file, content).unwrap();
+        }
+        let ignore = Ignore::load(&repo).unwrap();
+        (dir, ignore)
+    }
+
+    fn source(ignore: &Ignore, path: &str, is_dir: bool) -> Option<(String, usize, bool)> {
+        ignore.find_with_parents(path, is_dir).unwrap().map(|p| (p.source, p.line, p.negated))
+    }
+
+    #[test]
+    fn deeper_files_take_precedence() {
+        let (dir, ignore) = setup(&[
+            (".git/excludes", "*.log\n*.tmp\n"),
+            (".git/info/exclude", "!keep.tmp\n"),
+            (".gitignore", "# comment\n*.o\n!important.log\n"),
+            ("src/.gitignore", "!*.o\nlocal.log\n")
+        ]);
+
+        assert_eq!(source(&ignore, "a.log", false), Some((dir.path().join(".git/excludes").to_string_lossy().into_owned(), 1, false)));
+        assert!(ignore.is_ignored("a.tmp", false).unwrap());
+        assert_eq!(source(&ignore, "keep.tmp", false), Some((".git/info/exclude".to_string(), 1, true)));
+        assert_eq!(source(&ignore, "important.log", false), Some((".gitignore".to_string(), 3, true)));
+        assert!(ignore.is_ignored("a.o", false).unwrap());
+        assert_eq!(source(&ignore, "src/a.o", false), Some(("src/.gitignore".to_string(), 1, true)));
+        assert!(!ignore.is_ignored("src/a.o", false).unwrap());
+        assert!(ignore.is_ignored("src/local.log", false).unwrap());
+        assert!(!ignore.is_ignored("a.txt", false).unwrap());
+    }
+
+    #[test]
+    fn files_in_ignored_dirs_stay_ignored() {
+        let (_dir, ignore) = setup(&[(".gitignore", "build/\n!build/keep.txt\n/root.txt\n")]);
+        assert!(ignore.is_ignored("build", true).unwrap());
+        assert!(!ignore.is_ignored("build", false).unwrap());
+        assert!(ignore.is_ignored("build/keep.txt", false).unwrap());
+        assert!(ignore.is_ignored("sub/build/x.txt", false).unwrap());
+        assert!(ignore.is_ignored("root.txt", false).unwrap());
+        assert!(!ignore.is_ignored("sub/root.txt", false).unwrap());
+    }
+
+    #[test]
+    fn escapes_and_trailing_spaces() {
+        let (_dir, ignore) = setup(&[(".gitignore", "\\#hash\n\\!bang\nspace  \nescaped\\ \n")]);
+        assert!(ignore.is_ignored("#hash", false).unwrap());
+        assert!(ignore.is_ignored("!bang", false).unwrap());
+        assert!(ignore.is_ignored("space", false).unwrap());
+        assert!(ignore.is_ignored("escaped ", false).unwrap());
+        assert!(!ignore.is_ignored("escaped", false).unwrap());
+    }
+}
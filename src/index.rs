@@ -0,0 +1,707 @@
+use std::{convert::TryInto, env, fs::{self, Metadata}, os::unix::fs::MetadataExt, path::PathBuf, str};
+use crate::{lock::Lock, obj::{self, Obj, ObjType, TreeEntry}, repo::Repository, sha::Sha};
+
+use crate::error::R;
+
+const SIGNATURE: &[u8] = b"DIRC";
+const CHECKSUM_LEN: usize = 20;
+
+const FLAG_ASSUME_VALID: u16 = 0x8000;
+const FLAG_EXTENDED: u16 = 0x4000;
+const FLAG_STAGE: u16 = 0x3000;
+const FLAG_NAME_LEN: u16 = 0x0fff;
+const EXT_SKIP_WORKTREE: u16 = 0x4000;
+const EXT_INTENT_TO_ADD: u16 = 0x2000;
+
+pub const MODE_FILE: u32 = 0o100644;
+pub const MODE_EXECUTABLE: u32 = 0o100755;
+pub const MODE_SYMLINK: u32 = 0o120000;
+pub const MODE_GITLINK: u32 = 0o160000;
+
+// The staging area as stored in `.git/index`, see gitformat-index(5).
+#[derive(Clone,Debug)]
+pub struct Index {
+    pub version: u32,
+    // sorted by path and stage
+    pub entries: Vec<Entry>,
+    // the TREE extension, which remembers the ids of trees written from the index
+    pub tree: Option<CacheTree>,
+    // when the index file was last written, see `is_racy`
+    timestamp: Option<Time>
+}
+
+#[derive(Clone,Debug,Default,PartialEq,PartialOrd)]
+pub struct Time {
+    pub secs: u32,
+    pub nanos: u32
+}
+
+#[derive(Clone,Debug)]
+pub struct Entry {
+    pub ctime: Time,
+    pub mtime: Time,
+    pub dev: u32,
+    pub ino: u32,
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub size: u32,
+    pub id: Sha,
+    pub assume_valid: bool,
+    // 0 for normal entries, 1 to 3 for the base, ours and theirs version of a conflict
+    pub stage: u8,
+    pub skip_worktree: bool,
+    pub intent_to_add: bool,
+    pub path: String
+}
+
+// A directory whose tree id is known. `entry_count` is the number of index entries below it, or
+// -1 if the directory changed since the tree was written.
+#[derive(Clone,Debug)]
+pub struct CacheTree {
+    pub name: String,
+    pub entry_count: i32,
+    pub id: Option<Sha>,
+    pub children: Vec<CacheTree>
+}
+
+impl Entry {
+    pub fn new(path: &str, id: Sha, mode: u32) -> Entry {
+        Entry {
+            ctime: Time::default(), mtime: Time::default(), dev: 0, ino: 0, mode, uid: 0, gid: 0, size: 0, id,
+            assume_valid: false, stage: 0, skip_worktree: false, intent_to_add: false, path: path.to_string()
+        }
+    }
+
+    // Takes the stat data from the metadata of the file in the work tree, which is later used to
+    // tell whether the file changed without hashing it. Fields are truncated to 32 bits like git
+    // does.
+    pub fn from_metadata(path: &str, id: Sha, mode: u32, meta: &Metadata) -> Entry {
+        Entry {
+            ctime: Time { secs: meta.ctime() as u32, nanos: meta.ctime_nsec() as u32 },
+            mtime: Time { secs: meta.mtime() as u32, nanos: meta.mtime_nsec() as u32 },
+            dev: meta.dev() as u32,
+            ino: meta.ino() as u32,
+            uid: meta.uid(),
+            gid: meta.gid(),
+            size: meta.size() as u32,
+            ..Entry::new(path, id, mode)
+        }
+    }
+
+    // Whether the stat data still matches the file, in which case its content is assumed to be
+    // unchanged.
+    pub fn matches(&self, meta: &Metadata) -> bool {
+        self.mtime == Time { secs: meta.mtime() as u32, nanos: meta.mtime_nsec() as u32 }
+            && self.ctime == Time { secs: meta.ctime() as u32, nanos: meta.ctime_nsec() as u32 }
+            && self.ino == meta.ino() as u32
+            && self.dev == meta.dev() as u32
+            && self.uid == meta.uid()
+            && self.gid == meta.gid()
+            && self.size == meta.size() as u32
+            && self.mode == mode_of(meta)
+    }
+
+    fn is_extended(&self) -> bool {
+        self.skip_worktree || self.intent_to_add
+    }
+}
+
+// The mode an index entry gets for the file with this metadata.
+pub fn mode_of(meta: &Metadata) -> u32 {
+    if meta.file_type().is_symlink() {
+        MODE_SYMLINK
+    } else if meta.is_dir() {
+        MODE_GITLINK
+    } else if meta.mode() & 0o100 != 0 {
+        MODE_EXECUTABLE
+    } else {
+        MODE_FILE
+    }
+}
+
+impl CacheTree {
+    fn invalid(name: &str) -> CacheTree {
+        CacheTree { name: name.to_string(), entry_count: -1, id: None, children: Vec::new() }
+    }
+
+    pub fn valid(name: &str, id: Sha, entry_count: usize, children: Vec<CacheTree>) -> CacheTree {
+        let mut tree = CacheTree { name: name.to_string(), entry_count: entry_count as i32, id: Some(id), children };
+        tree.sort_children();
+        tree
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.entry_count >= 0 && self.id.is_some()
+    }
+
+    // Git keeps subtrees ordered by name length first and looks them up by binary search.
+    fn sort_children(&mut self) {
+        self.children.sort_by(|a, b| a.name.len().cmp(&b.name.len()).then_with(|| a.name.cmp(&b.name)));
+    }
+}
+
+impl Default for Index {
+    fn default() -> Index {
+        Index { version: 2, entries: Vec::new(), tree: None, timestamp: None }
+    }
+}
+
+// Honors GIT_INDEX_FILE like git does.
+pub fn path(repo: &Repository) -> PathBuf {
+    match env::var_os("GIT_INDEX_FILE") {
+        Some(path) => PathBuf::from(path),
+        None       => repo.git_dir().join("index")
+    }
+}
+
+// A missing index file is the same as an empty one.
+pub fn read(repo: &Repository) -> R<Index> {
+    let path = path(repo);
+    if !path.is_file() {
+        return Ok(Index::default());
+    }
+    let bytes = fs::read(&path)
+        .map_err(|e| format!("Failed to read index {:?}: {}", path, e))?;
+    let mut index = Index::parse(&bytes)
+        .map_err(|e| format!("Index file {:?} is corrupt: {}", path, e))?;
+    let meta = fs::metadata(&path)?;
+    index.timestamp = Some(Time { secs: meta.mtime() as u32, nanos: meta.mtime_nsec() as u32 });
+    Ok(index)
+}
+
+impl Index {
+    pub fn parse(bytes: &[u8]) -> R<Index> {
+        if bytes.len() < 12 + CHECKSUM_LEN || !bytes.starts_with(SIGNATURE) {
+            return Err("Bad signature.".into());
+        }
+        let (content, checksum) = bytes.split_at(bytes.len() - CHECKSUM_LEN);
+        if Sha::generate_raw(content) != checksum {
+            return Err("Checksum mismatch.".into());
+        }
+
+        let mut reader = Reader { bytes: content, pos: 4 };
+        let version = reader.u32()?;
+        if !(2..=4).contains(&version) {
+            return Err(format!("Unsupported index version {}.", version).into());
+        }
+
+        let count = reader.u32()?;
+        let mut entries: Vec<Entry> = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            let previous = entries.last().map(|e| e.path.as_str()).unwrap_or("");
+            let entry = parse_entry(&mut reader, version, previous)?;
+            entries.push(entry);
+        }
+
+        let mut tree = None;
+        while reader.pos < content.len() {
+            let signature = reader.take(4)?;
+            let len = reader.u32()? as usize;
+            let data = reader.take(len)?;
+            match signature {
+                b"TREE" => tree = Some(parse_tree(&mut Reader { bytes: data, pos: 0 })?),
+                // extensions starting with an upper case letter are optional
+                _ if signature[0].is_ascii_uppercase() => {},
+                _ => return Err(format!("Unsupported extension '{}'.", String::from_utf8_lossy(signature)).into())
+            }
+        }
+
+        Ok(Index { version, entries, tree, timestamp: None })
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        // extended flags need at least version 3
+        let version = match self.version {
+            2 if self.entries.iter().any(Entry::is_extended) => 3,
+            version => version
+        };
+
+        let mut bytes = SIGNATURE.to_vec();
+        bytes.extend_from_slice(&version.to_be_bytes());
+        bytes.extend_from_slice(&(self.entries.len() as u32).to_be_bytes());
+
+        let mut previous = "";
+        for entry in &self.entries {
+            encode_entry(&mut bytes, entry, version, previous);
+            previous = &entry.path;
+        }
+
+        if let Some(tree) = &self.tree {
+            let mut data = Vec::new();
+            encode_tree(&mut data, tree);
+            bytes.extend_from_slice(b"TREE");
+            bytes.extend_from_slice(&(data.len() as u32).to_be_bytes());
+            bytes.extend(data);
+        }
+
+        let checksum = Sha::generate_raw(&bytes);
+        bytes.extend_from_slice(&checksum);
+        bytes
+    }
+
+    // Creates an index with the content of the tree and no stat data.
+    pub fn from_tree(repo: &Repository, tree: &Sha) -> R<Index> {
+        fn add_tree(repo: &Repository, name: &str, prefix: &str, id: &Sha, entries: &mut Vec<Entry>) -> R<CacheTree> {
+            let tree = match obj::read(repo, id)? {
+                Obj::Tree { entries } => entries,
+                _ => return Err(format!("Object {} is not a tree.", id).into())
+            };
+            let start = entries.len();
+            let mut children = Vec::new();
+            for e in tree {
+                let path = format!("{}{}", prefix, e.name);
+                match index_mode(e.mode)? {
+                    0o040000 => children.push(add_tree(repo, &e.name, &format!("{}/", path), &e.id, entries)?),
+                    mode     => entries.push(Entry::new(&path, e.id, mode))
+                }
+            }
+            Ok(CacheTree::valid(name, id.clone(), entries.len() - start, children))
+        }
+
+        let mut entries = Vec::new();
+        let tree = add_tree(repo, "", "", tree, &mut entries)?;
+        Ok(Index { entries, tree: Some(tree), ..Index::default() })
+    }
+
+    // The stage 0 entry of the path.
+    pub fn find(&self, path: &str) -> Option<&Entry> {
+        self.position(path, 0).ok().map(|i| &self.entries[i])
+    }
+
+    // Adds the entry, replacing the one for the same path as well as any conflict stages of it.
+    // Entries that would turn into a parent dir of the path or have it as a parent dir are
+    // removed, as a path cannot be a file and a dir at the same time.
+    pub fn add(&mut self, entry: Entry) {
+        self.remove(&entry.path);
+        let dir_prefix = format!("{}/", entry.path);
+        let clashing = self.entries.iter()
+            .filter(|e| e.path.starts_with(&dir_prefix) || entry.path.starts_with(&format!("{}/", e.path)))
+            .map(|e| e.path.clone())
+            .collect::<Vec<_>>();
+        for path in clashing {
+            self.remove(&path);
+        }
+
+        self.invalidate(&entry.path);
+        let pos = self.position(&entry.path, entry.stage).unwrap_or_else(|pos| pos);
+        self.entries.insert(pos, entry);
+    }
+
+    // Removes all stages of the path and tells whether there were any.
+    pub fn remove(&mut self, path: &str) -> bool {
+        let len = self.entries.len();
+        self.entries.retain(|e| e.path != path);
+        if self.entries.len() == len {
+            return false;
+        }
+        self.invalidate(path);
+        true
+    }
+
+    // Entries whose stat data is not older than the index file may have been modified in the same
+    // second (or nanosecond) they were added, so their stat data cannot be trusted.
+    pub fn is_racy(&self, entry: &Entry) -> bool {
+        self.timestamp.as_ref().is_none_or(|timestamp| entry.mtime >= *timestamp)
+    }
+
+    fn position(&self, path: &str, stage: u8) -> Result<usize, usize> {
+        self.entries.binary_search_by(|e| e.path.as_bytes().cmp(path.as_bytes()).then(e.stage.cmp(&stage)))
+    }
+
+    // Marks the trees containing the path as changed.
+    fn invalidate(&mut self, path: &str) {
+        let mut tree = match self.tree.as_mut() {
+            Some(tree) => tree,
+            None       => return
+        };
+        tree.entry_count = -1;
+        let mut dirs = path.split('/').collect::<Vec<_>>();
+        dirs.pop();
+        for dir in dirs {
+            match tree.children.iter_mut().find(|c| c.name == dir) {
+                Some(child) => {
+                    child.entry_count = -1;
+                    tree = child;
+                },
+                None => return
+            }
+        }
+    }
+
+    pub fn write(&self, repo: &Repository) -> R<()> {
+        Lock::acquire(path(repo))?.commit(self.encode())
+    }
+
+    // Writes the trees for the staged content and returns the id of the root tree. Trees that
+    // are still valid in the TREE extension are reused, and the ids of new ones are recorded
+    // there, so the index should be written afterwards.
+    pub fn write_tree(&mut self, repo: &Repository) -> R<Sha> {
+        if let Some(entry) = self.entries.iter().find(|e| e.stage != 0) {
+            return Err(format!("Cannot write a tree with unmerged entries, like '{}'.", entry.path).into());
+        }
+        let mut root = self.tree.take().unwrap_or_else(|| CacheTree::invalid(""));
+        let id = write_dir(repo, &self.entries, "", &mut root);
+        self.tree = Some(root);
+        id
+    }
+}
+
+fn write_dir(repo: &Repository, entries: &[Entry], prefix: &str, cache: &mut CacheTree) -> R<Sha> {
+    if let (true, Some(id)) = (cache.entry_count == entries.len() as i32, &cache.id) {
+        return Ok(id.clone());
+    }
+
+    let mut tree = Vec::new();
+    let mut children = Vec::new();
+    let mut i = 0;
+    while i < entries.len() {
+        let name = &entries[i].path[prefix.len()..];
+        match name.split_once('/') {
+            Some((dir, _)) => {
+                let dir_prefix = format!("{}{}/", prefix, dir);
+                let end = i + entries[i..].iter().take_while(|e| e.path.starts_with(&dir_prefix)).count();
+                let mut child = cache.children.iter()
+                    .position(|c| c.name == dir)
+                    .map(|pos| cache.children.swap_remove(pos))
+                    .unwrap_or_else(|| CacheTree::invalid(dir));
+                let id = write_dir(repo, &entries[i..end], &dir_prefix, &mut child)?;
+                // a directory holding only intent-to-add entries is left out along with them
+                if id != obj::hash(ObjType::Tree, &[]) {
+                    tree.push(TreeEntry { mode: tree_mode(0o040000), name: dir.to_string(), id });
+                }
+                children.push(child);
+                i = end;
+            },
+            None => {
+                // entries added with `--intent-to-add` are not part of the tree yet
+                if !entries[i].intent_to_add {
+                    tree.push(TreeEntry { mode: tree_mode(entries[i].mode), name: name.to_string(), id: entries[i].id.clone() });
+                }
+                i += 1;
+            }
+        }
+    }
+
+    let id = obj::write(repo, ObjType::Tree, &obj::encode_tree(&tree))?;
+    cache.children = children;
+    cache.sort_children();
+    cache.entry_count = entries.len() as i32;
+    cache.id = Some(id.clone());
+    Ok(id)
+}
+
+// Tree entries store modes as octal digits, which `TreeEntry` keeps as a decimal number.
+pub fn tree_mode(mode: u32) -> u32 {
+    format!("{:o}", mode).parse().unwrap()
+}
+
+pub fn index_mode(tree_mode: u32) -> R<u32> {
+    u32::from_str_radix(&tree_mode.to_string(), 8)
+        .map_err(|_| format!("Invalid file mode {}.", tree_mode).into())
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> R<&'a [u8]> {
+        let bytes = self.bytes.get(self.pos..self.pos + len).ok_or("Unexpected end of data.")?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn u16(&mut self) -> R<u16> {
+        Ok(u16::from_be_bytes(self.take(2)?.try_into()?))
+    }
+
+    fn u32(&mut self) -> R<u32> {
+        Ok(u32::from_be_bytes(self.take(4)?.try_into()?))
+    }
+
+    // Reads up to the next NUL, which is skipped.
+    fn until_nul(&mut self) -> R<&'a str> {
+        let len = self.bytes[self.pos..].iter().position(|&b| b == 0).ok_or("Missing NUL terminator.")?;
+        let s = str::from_utf8(self.take(len)?)?;
+        self.pos += 1;
+        Ok(s)
+    }
+
+    // The variable length integer used by v4 path compression, with the same encoding as the
+    // offsets of deltas in pack files.
+    fn varint(&mut self) -> R<usize> {
+        let mut byte = self.take(1)?[0];
+        let mut value = (byte & 0x7f) as usize;
+        while byte & 0x80 != 0 {
+            byte = self.take(1)?[0];
+            value = ((value + 1) << 7) | (byte & 0x7f) as usize;
+        }
+        Ok(value)
+    }
+}
+
+fn parse_entry(reader: &mut Reader, version: u32, previous: &str) -> R<Entry> {
+    let start = reader.pos;
+    let ctime = Time { secs: reader.u32()?, nanos: reader.u32()? };
+    let mtime = Time { secs: reader.u32()?, nanos: reader.u32()? };
+    let (dev, ino, mode, uid, gid, size) = (reader.u32()?, reader.u32()?, reader.u32()?, reader.u32()?, reader.u32()?, reader.u32()?);
+    let id = Sha::from_bytes(reader.take(20)?)?;
+    let flags = reader.u16()?;
+
+    let extended = match flags & FLAG_EXTENDED {
+        0                 => 0,
+        _ if version == 2 => return Err("Extended flags in a version 2 index.".into()),
+        _                 => reader.u16()?
+    };
+
+    let path = match version {
+        4 => {
+            let strip = reader.varint()?;
+            let keep = previous.len().checked_sub(strip)
+                .ok_or_else(|| format!("Invalid path compression after '{}'.", previous))?;
+            format!("{}{}", previous.get(..keep).ok_or("Invalid path compression.")?, reader.until_nul()?)
+        },
+        _ => {
+            let path = reader.until_nul()?.to_string();
+            // entries are padded with NULs to a multiple of eight bytes
+            let len = reader.pos - start;
+            reader.take((8 - len % 8) % 8)?;
+            path
+        }
+    };
+
+    Ok(Entry {
+        ctime, mtime, dev, ino, mode, uid, gid, size, id,
+        assume_valid: flags & FLAG_ASSUME_VALID != 0,
+        stage: ((flags & FLAG_STAGE) >> 12) as u8,
+        skip_worktree: extended & EXT_SKIP_WORKTREE != 0,
+        intent_to_add: extended & EXT_INTENT_TO_ADD != 0,
+        path
+    })
+}
+
+fn encode_entry(bytes: &mut Vec<u8>, entry: &Entry, version: u32, previous: &str) {
+    let start = bytes.len();
+    for field in &[entry.ctime.secs, entry.ctime.nanos, entry.mtime.secs, entry.mtime.nanos,
+                   entry.dev, entry.ino, entry.mode, entry.uid, entry.gid, entry.size] {
+        bytes.extend_from_slice(&field.to_be_bytes());
+    }
+    bytes.extend_from_slice(&entry.id.to_bytes());
+
+    let mut flags = (entry.path.len().min(FLAG_NAME_LEN as usize) as u16) | (((entry.stage as u16) << 12) & FLAG_STAGE);
+    if entry.assume_valid { flags |= FLAG_ASSUME_VALID; }
+    if entry.is_extended() { flags |= FLAG_EXTENDED; }
+    bytes.extend_from_slice(&flags.to_be_bytes());
+
+    if entry.is_extended() {
+        let mut extended = 0;
+        if entry.skip_worktree { extended |= EXT_SKIP_WORKTREE; }
+        if entry.intent_to_add { extended |= EXT_INTENT_TO_ADD; }
+        bytes.extend_from_slice(&extended.to_be_bytes());
+    }
+
+    match version {
+        4 => {
+            let common = previous.bytes().zip(entry.path.bytes()).take_while(|(a, b)| a == b).count();
+            encode_varint(bytes, previous.len() - common);
+            bytes.extend_from_slice(&entry.path.as_bytes()[common..]);
+            bytes.push(0);
+        },
+        _ => {
+            bytes.extend_from_slice(entry.path.as_bytes());
+            let len = bytes.len() - start;
+            bytes.extend(std::iter::repeat_n(0, 8 - len % 8));
+        }
+    }
+}
+
+fn encode_varint(bytes: &mut Vec<u8>, mut value: usize) {
+    let mut varint = vec![(value & 0x7f) as u8];
+    value >>= 7;
+    while value != 0 {
+        value -= 1;
+        varint.push(0x80 | (value & 0x7f) as u8);
+        value >>= 7;
+    }
+    varint.reverse();
+    bytes.extend(varint);
+}
+
+// Each directory is stored as `<name> NUL <entry count> SP <subtree count> LF <id>`, with the id
+// omitted for invalid ones, followed by its subtrees.
+fn parse_tree(reader: &mut Reader) -> R<CacheTree> {
+    let name = reader.until_nul()?.to_string();
+    let len = reader.bytes[reader.pos..].iter().position(|&b| b == b'\n').ok_or("Missing newline in TREE extension.")?;
+    let counts = str::from_utf8(reader.take(len)?)?;
+    reader.pos += 1;
+
+    let (entry_count, subtrees) = counts.split_once(' ').ok_or("Invalid TREE extension.")?;
+    let entry_count = entry_count.parse::<i32>()?;
+    let subtrees = subtrees.parse::<usize>()?;
+    let id = match entry_count {
+        -1 => None,
+        _  => Some(Sha::from_bytes(reader.take(20)?)?)
+    };
+
+    let children = (0..subtrees).map(|_| parse_tree(reader)).collect::<R<Vec<_>>>()?;
+    Ok(CacheTree { name, entry_count, id, children })
+}
+
+fn encode_tree(bytes: &mut Vec<u8>, tree: &CacheTree) {
+    bytes.extend_from_slice(tree.name.as_bytes());
+    bytes.push(0);
+    let entry_count = if tree.id.is_some() { tree.entry_count } else { -1 };
+    bytes.extend_from_slice(format!("{} {}\n", entry_count, tree.children.len()).as_bytes());
+    if let (true, Some(id)) = (entry_count >= 0, &tree.id) {
+        bytes.extend_from_slice(&id.to_bytes());
+    }
+    for child in &tree.children {
+        encode_tree(bytes, child);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testutil;
+    use super::*;
+
+    fn id(hex: &str) -> Sha {
+        Sha::from_str(hex).unwrap()
+    }
+
+    fn entries() -> Vec<Entry> {
+        let blob = id("e69de29bb2d1d6434b8b29ae775ad8c2e48c5391");
+        vec![
+            Entry { ctime: Time { secs: 1, nanos: 2 }, mtime: Time { secs: 3, nanos: 4 }, dev: 5, ino: 6, uid: 7, gid: 8, size: 9,
+                    ..Entry::new("a.txt", blob.clone(), MODE_FILE) },
+            Entry { assume_valid: true, ..Entry::new("dir/run.sh", blob.clone(), MODE_EXECUTABLE) },
+            Entry { stage: 2, ..Entry::new("dir/sub/conflict.txt", blob.clone(), MODE_FILE) },
+            Entry { stage: 3, ..Entry::new("dir/sub/conflict.txt", blob.clone(), MODE_FILE) },
+            Entry::new("dir/sub/link", blob, MODE_SYMLINK)
+        ]
+    }
+
+    fn round_trip(index: &Index) -> Index {
+        let parsed = Index::parse(&index.encode()).unwrap();
+        assert_eq!(format!("{:?}", parsed.entries), format!("{:?}", index.entries));
+        assert_eq!(format!("{:?}", parsed.tree), format!("{:?}", index.tree));
+        parsed
+    }
+
+    #[test]
+    fn round_trip_versions() {
+        for version in 2..=4 {
+            let index = Index { version, entries: entries(), ..Index::default() };
+            assert_eq!(round_trip(&index).version, version);
+        }
+    }
+
+    #[test]
+    fn extended_flags_need_version_3() {
+        let mut entries = entries();
+        entries[0].skip_worktree = true;
+        entries[1].intent_to_add = true;
+        let parsed = round_trip(&Index { entries: entries.clone(), ..Index::default() });
+        assert_eq!(parsed.version, 3);
+        assert_eq!(round_trip(&Index { version: 4, entries, ..Index::default() }).version, 4);
+    }
+
+    #[test]
+    fn version_4_compresses_paths() {
+        let index = Index { version: 4, entries: entries(), ..Index::default() };
+        let bytes = index.encode();
+        // "dir/sub/link" follows "dir/sub/conflict.txt", so 12 bytes are stripped and "link" added
+        let entry = [&[12u8][..], b"link\0"].concat();
+        assert!(bytes.windows(entry.len()).any(|w| w == entry.as_slice()));
+        assert!(bytes.len() < Index { version: 2, ..index }.encode().len());
+    }
+
+    #[test]
+    fn round_trip_tree_extension() {
+        let tree = CacheTree {
+            name: String::new(), entry_count: -1, id: None,
+            children: vec![
+                CacheTree::valid("dir", id("65a457425a679cbe9adf0d2741785d3ceabb44a7"), 4, vec![
+                    CacheTree::invalid("sub")
+                ])
+            ]
+        };
+        round_trip(&Index { entries: entries(), tree: Some(tree), ..Index::default() });
+    }
+
+    #[test]
+    fn parse_rejects_corrupt_indexes() {
+        let mut bytes = Index { entries: entries(), ..Index::default() }.encode();
+        assert!(Index::parse(&bytes[..bytes.len() - 1]).is_err());
+        let last = bytes.len() - 1;
+        bytes[last] ^= 1;
+        assert!(Index::parse(&bytes).is_err());
+        assert!(Index::parse(b"DIRC").is_err());
+    }
+
+    #[test]
+    fn add_replaces_clashing_entries_and_invalidates_trees() {
+        let (_dir, repo) = testutil::repo();
+        let mut index = Index { entries: entries(), ..Index::default() };
+        index.entries.retain(|e| e.stage == 0);
+        index.write_tree(&repo).unwrap();
+        assert!(index.tree.as_ref().unwrap().is_valid());
+
+        let blob = id("e69de29bb2d1d6434b8b29ae775ad8c2e48c5391");
+        index.add(Entry::new("dir/sub", blob.clone(), MODE_FILE));
+        assert_eq!(index.entries.iter().map(|e| e.path.as_str()).collect::<Vec<_>>(), ["a.txt", "dir/run.sh", "dir/sub"]);
+        let tree = index.tree.as_ref().unwrap();
+        assert!(!tree.is_valid());
+        assert!(!tree.children[0].is_valid());
+
+        index.add(Entry::new("dir", blob, MODE_FILE));
+        assert_eq!(index.entries.iter().map(|e| e.path.as_str()).collect::<Vec<_>>(), ["a.txt", "dir"]);
+        assert!(index.remove("dir"));
+        assert!(!index.remove("dir"));
+    }
+
+    #[test]
+    fn write_tree_leaves_out_intent_to_add_entries() {
+        let (_dir, repo) = testutil::repo();
+        let empty = obj::write(&repo, ObjType::Blob, b"").unwrap();
+        let x = obj::write(&repo, ObjType::Blob, b"x\n").unwrap();
+        let mut index = Index::default();
+        index.add(Entry::new("dir/a.txt", empty.clone(), MODE_FILE));
+        index.add(Entry::new("top.txt", x, MODE_FILE));
+        index.add(Entry { intent_to_add: true, ..Entry::new("dir/sub/ita.txt", empty.clone(), MODE_FILE) });
+        index.add(Entry { intent_to_add: true, ..Entry::new("only/ita.txt", empty, MODE_FILE) });
+
+        // the tree git writes for the same index
+        assert_eq!(index.write_tree(&repo).unwrap().to_string(), "e93f5fa1b0947a9099ed1a52b9ddc15439503077");
+        assert_eq!(index.write_tree(&repo).unwrap().to_string(), "e93f5fa1b0947a9099ed1a52b9ddc15439503077");
+    }
+
+    #[test]
+    fn write_tree_and_from_tree() {
+        let (_dir, repo) = testutil::repo();
+        let blob = obj::write(&repo, ObjType::Blob, b"").unwrap();
+        let mut index = Index::default();
+        index.add(Entry::new("dir/a.txt", blob.clone(), MODE_FILE));
+        index.add(Entry::new("dir/b/c.sh", blob.clone(), MODE_EXECUTABLE));
+        index.add(Entry::new("z", blob, MODE_FILE));
+        let id = index.write_tree(&repo).unwrap();
+
+        let read = Index::from_tree(&repo, &id).unwrap();
+        assert_eq!(format!("{:?}", read.entries), format!("{:?}", index.entries));
+        let tree = read.tree.as_ref().unwrap();
+        assert_eq!((tree.id.as_ref(), tree.entry_count), (Some(&id), 3));
+        assert_eq!(tree.children[0].entry_count, 2);
+
+        index.add(Entry { stage: 1, ..index.entries[0].clone() });
+        assert!(index.write_tree(&repo).is_err());
+    }
+
+    #[test]
+    fn modes() {
+        assert_eq!(tree_mode(MODE_EXECUTABLE), 100755);
+        assert_eq!(index_mode(40000).unwrap(), 0o040000);
+        assert!(index_mode(100855).is_err());
+    }
+}
@@ -0,0 +1,30 @@
+pub mod attr;
+pub mod config;
+pub mod date;
+pub mod error;
+pub mod glob;
+pub mod ident;
+pub mod ignore;
+pub mod index;
+pub mod obj;
+pub mod pack;
+pub mod pathspec;
+pub mod reflog;
+pub mod refs;
+pub mod repo;
+pub mod rev;
+pub mod serve;
+pub mod sha;
+pub mod status;
+pub mod wtree;
+
+mod cred;
+mod lock;
+mod util;
+mod zlib;
+
+#[cfg(test)]
+mod testutil;
+
+pub use error::{Error, R};
+pub use repo::Repository;
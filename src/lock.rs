@@ -0,0 +1,87 @@
+use std::{fs::{self, File, OpenOptions}, io::{ErrorKind, Write}, path::PathBuf};
+
+use crate::error::R;
+
+// Guards an update of `path` by creating `path.lock` exclusively. The new content is written to
+// the lock file which then replaces `path` atomically. Dropping an uncommitted lock releases it.
+pub struct Lock {
+    path: PathBuf,
+    lock_path: PathBuf,
+    file: Option<File>
+}
+
+impl Lock {
+    pub fn acquire(path: PathBuf) -> R<Lock> {
+        let mut lock_path = path.clone().into_os_string();
+        lock_path.push(".lock");
+        let lock_path = PathBuf::from(lock_path);
+
+        fs::create_dir_all(path.parent().unwrap())?;
+        let file = OpenOptions::new().write(true).create_new(true).open(&lock_path)
+            .map_err(|e| match e.kind() {
+                ErrorKind::AlreadyExists => format!("Unable to create {:?}: File exists. Another git process seems to be running in this repository.", lock_path),
+                _                        => format!("Unable to create {:?}: {}", lock_path, e)
+            })?;
+
+        Ok(Lock { path, lock_path, file: Some(file) })
+    }
+
+    // The lock is only given up once the rename succeeded. If anything fails before, dropping the
+    // lock removes the lock file again.
+    pub fn commit<T: AsRef<[u8]>>(mut self, content: T) -> R<()> {
+        let file = self.file.as_mut().unwrap();
+        file.write_all(content.as_ref())?;
+        file.sync_all()?;
+        fs::rename(&self.lock_path, &self.path)
+            .map_err(|e| format!("Failed to rename {:?} to {:?}: {}", self.lock_path, self.path, e))?;
+        self.file = None;
+        Ok(())
+    }
+}
+
+impl Drop for Lock {
+    fn drop(&mut self) {
+        if self.file.is_some() {
+            let _ = fs::remove_file(&self.lock_path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testutil::TempDir;
+    use super::*;
+
+    #[test]
+    fn commit_replaces_file() {
+        let dir = TempDir::new();
+        let path = dir.path().join("a/file");
+        let lock = Lock::acquire(path.clone()).unwrap();
+        assert!(Lock::acquire(path.clone()).is_err());
+        lock.commit("content").unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "content");
+        assert!(!dir.path().join("a/file.lock").exists());
+    }
+
+    #[test]
+    fn dropped_lock_is_released() {
+        let dir = TempDir::new();
+        let path = dir.path().join("file");
+        drop(Lock::acquire(path.clone()).unwrap());
+        assert!(!dir.path().join("file.lock").exists());
+        assert!(!path.exists());
+        Lock::acquire(path).unwrap();
+    }
+
+    #[test]
+    fn failed_commit_is_released() {
+        let dir = TempDir::new();
+        let path = dir.path().join("file");
+        fs::create_dir_all(path.join("in-the-way")).unwrap(); // a non-empty dir can't be replaced
+
+        assert!(Lock::acquire(path.clone()).unwrap().commit("content").is_err());
+        assert!(!dir.path().join("file.lock").exists());
+        assert!(Lock::acquire(path).is_ok());
+    }
+}
@@ -1,32 +1,36 @@
 mod arg;
-mod obj;
-mod pack;
-mod repo;
-mod sha;
-mod util;
-mod wtree;
-mod zlib;
-
-use std::{env::Args, iter::Peekable, path::Path};
-use reqwest::blocking::Client;
-use obj::{Obj, ObjType};
-use pack::http::Ref;
-use sha::Sha;
-
-type R<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+use std::{env::{self, Args}, fs, io::{self, Read}, iter::Peekable, path::Path, process::Command, rc::Rc};
+use bytes::Bytes;
+use git_starter_rust::{config::{self, Config}, glob, ident::{self, Role}, ignore::Ignore, index::{self, Entry, Index}, obj::{self, Obj, ObjType, TreeEntry}, pack::{self, http::{Session, Transfer}}, pathspec::Pathspec,
+    reflog, refs::{self, Ref, Value}, rev, serve, sha::Sha, status, wtree, Repository, R};
 
 fn main() {
     fn run(args: &mut Peekable<Args>) -> R<String> {
         let cmd = args.next().ok_or("No command provided.")?;
         match cmd.as_str() {
+            "add"         => add(args),
+            "branch"      => branch(args),
             "cat-file"    => cat_file(args),
+            "check-ignore"=> check_ignore(args),
             "checkout"    => checkout(args),
             "clone"       => clone(args),
+            "commit"      => commit(args),
             "commit-tree" => commit_tree(args),
+            "config"      => config(args),
             "hash-object" => hash_object(args),
-            "init"        => init(),
+            "init"        => init(args),
             "ls-remote"   => ls_remote(args),
             "ls-tree"     => ls_tree(args),
+            "reflog"      => reflog(args),
+            "rev-parse"   => rev_parse(args),
+            "rm"          => rm(args),
+            "serve"       => serve(args),
+            "show-ref"    => show_ref(args),
+            "status"      => status(args),
+            "symbolic-ref"=> symbolic_ref(args),
+            "tag"         => tag(args),
+            "update-ref"  => update_ref(args),
             "write-tree"  => write_tree(),
             _ => Err(format!("Unknown command '{}'.", cmd).into())
         }
@@ -47,100 +51,1383 @@ fn main() {
     std::process::exit(exit_code);
 }
 
+fn add(args: &mut Peekable<Args>) -> R<String> {
+    let force = arg::opt::flag(args, "-f");
+    let all = arg::opt::flag(args, "-A");
+    let update = arg::opt::flag(args, "-u");
+    let intent_to_add = arg::opt::flag(args, "-N") || arg::opt::flag(args, "--intent-to-add");
+    arg::opt::flag(args, "--");
+    let repo = Repository::find()?;
+    let work_tree = repo.work_tree()?;
+    let args = args.collect::<Vec<_>>();
+    if args.is_empty() && !all && !update {
+        return Err("Nothing specified, nothing added.".into());
+    }
+    let pathspec = Pathspec::parse(&repo, &args)?;
+
+    let ignore = if force { None } else { Some(Ignore::load(&repo)?) };
+    let mut index = index::read(&repo)?;
+    let mut tracked = tracked_paths(&index, &pathspec)?;
+    let files = if update { Vec::new() } else { wtree::walk(work_tree, &pathspec, ignore.as_ref())? };
+    let matched = tracked.iter().chain(&files).map(String::as_str).collect::<Vec<_>>();
+    if let Some(unmatched) = pathspec.unmatched(&matched)? {
+        // a pathspec that only matches ignored files was most likely meant to add them
+        if !update && ignore.is_some() && !wtree::walk(work_tree, &Pathspec::parse(&repo, &[unmatched.to_string()])?, None)?.is_empty() {
+            return Err(format!("The following paths are ignored by one of your .gitignore files:\n{}\nUse -f if you really want to add them.", unmatched).into());
+        }
+        return Err(format!("pathspec '{}' did not match any files", unmatched).into());
+    }
+
+    for file in files {
+        if intent_to_add && index.find(&file).is_none() {
+            let meta = fs::symlink_metadata(work_tree.join(&file))?;
+            let mode = index::mode_of(&meta);
+            let entry = Entry { intent_to_add: true, ..Entry::from_metadata(&file, obj::hash(ObjType::Blob, &[]), mode, &meta) };
+            index.add(entry);
+        } else if !tracked.contains(&file) {
+            tracked.push(file);
+        }
+    }
+    // tracked files that are gone are removed from the index as well
+    for file in tracked {
+        wtree::stage(&repo, work_tree, &mut index, &file)?;
+    }
+
+    index.write(&repo)?;
+    Ok(String::new())
+}
+
+// The paths of the index entries selected by the pathspec, once for all stages.
+fn tracked_paths(index: &Index, pathspec: &Pathspec) -> R<Vec<String>> {
+    let mut paths = Vec::new();
+    for e in &index.entries {
+        if paths.last() != Some(&e.path) && pathspec.matches(&e.path)? {
+            paths.push(e.path.clone());
+        }
+    }
+    Ok(paths)
+}
+
+fn branch(args: &mut Peekable<Args>) -> R<String> {
+    let (mut delete, mut force_delete, mut rename, mut force_rename) = (false, false, false, false);
+    let (mut unset_upstream, mut all, mut merged) = (false, false, false);
+    let mut upstream = None;
+    let mut verbosity = 0;
+    let mut names = Vec::new();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| format!("Not enough arguments provided: missing value for '{}'.", arg));
+        match arg.as_str() {
+            "-d" | "--delete"  => delete = true,
+            "-D"               => force_delete = true,
+            "-m" | "--move"    => rename = true,
+            "-M"               => force_rename = true,
+            "-u"               => upstream = Some(value()?),
+            "--unset-upstream" => unset_upstream = true,
+            "-a" | "--all"     => all = true,
+            "-v" | "--verbose" => verbosity += 1,
+            "-vv"              => verbosity += 2,
+            "--merged"         => merged = true,
+            _ if arg.starts_with("--set-upstream-to=") => upstream = Some(arg["--set-upstream-to=".len()..].to_string()),
+            _ if arg.starts_with('-') => return Err(format!("Unknown option '{}'.", arg).into()),
+            _ => names.push(arg)
+        }
+    }
+    let args = names;
+    let repo = Repository::find()?;
+
+    if delete || force_delete {
+        if args.is_empty() {
+            return Err("Not enough arguments provided: missing branch name.".into());
+        }
+        return delete_branches(&repo, &args, force_delete);
+    }
+    if rename || force_rename {
+        return match args.as_slice() {
+            [new]      => rename_branch(&repo, &current_branch(&repo)?, new, force_rename),
+            [old, new] => rename_branch(&repo, old, new, force_rename),
+            _          => Err("Expecting the new name of the branch, optionally preceded by the old one.".into())
+        };
+    }
+    if let Some(upstream) = upstream {
+        let name = match args.first() { Some(name) => name.clone(), None => current_branch(&repo)? };
+        return set_upstream(&repo, &name, &upstream);
+    }
+    if unset_upstream {
+        let name = match args.first() { Some(name) => name.clone(), None => current_branch(&repo)? };
+        if !repo.unset_upstream(&name)? {
+            return Err(format!("Branch '{}' has no upstream information.", name).into());
+        }
+        return Ok(String::new());
+    }
+    if merged || args.is_empty() {
+        let merged = if merged { Some(args.first().map_or("HEAD", String::as_str)) } else { None };
+        return list_branches(&repo, all, verbosity, merged);
+    }
+
+    let (name, start) = match args.as_slice() {
+        [name]        => (name, "HEAD"),
+        [name, start] => (name, start.as_str()),
+        _             => return Err("Expecting a branch name and an optional start point.".into())
+    };
+    let branch = format!("refs/heads/{}", name);
+    refs::check_name(&branch)?;
+    if refs::read(&repo, &branch)?.is_some() {
+        return Err(format!("A branch named '{}' already exists.", name).into());
+    }
+    create_branch(&repo, name, start, &rev::parse_as(&repo, start, ObjType::Commit)?)
+}
+
+// Lists the local branches, with `all` also the remote-tracking ones, marking the current one.
+// `merged` restricts the list to branches whose tip is reachable from that commit.
+fn list_branches(repo: &Repository, all: bool, verbosity: usize, merged: Option<&str>) -> R<String> {
+    let merged = match merged {
+        Some(commit) => Some(rev::ancestors(repo, &rev::parse_as(repo, commit, ObjType::Commit)?)?),
+        None         => None
+    };
+    let head = refs::read(repo, "HEAD")?;
+
+    // the current marker, whether it is a local branch, the name shown, the tip and the branch it
+    // points to, if symbolic
+    let mut rows = Vec::new();
+    if let Some(Value::Direct(id)) = &head {
+        rows.push((true, false, detached_name(repo, id)?, id.clone(), None));
+    }
+    let prefixes: &[&str] = if all { &["refs/heads/", "refs/remotes/"] } else { &["refs/heads/"] };
+    for prefix in prefixes {
+        for r in refs::list(repo, prefix)? {
+            let current = matches!(&head, Some(Value::Symbolic(target)) if *target == r.name);
+            let name = if r.name.starts_with("refs/remotes/") { &r.name["refs/".len()..] } else { refs::shorten(&r.name) };
+            let target = match refs::read(repo, &r.name)? {
+                Some(Value::Symbolic(target)) => Some(refs::shorten(&target).to_string()),
+                _                             => None
+            };
+            rows.push((current, *prefix == "refs/heads/", name.to_string(), r.id, target));
+        }
+    }
+    if let Some(merged) = &merged {
+        rows.retain(|(_, _, _, id, _)| merged.contains(id));
+    }
+
+    let width = rows.iter().map(|(_, _, name, _, _)| name.len()).max().unwrap_or(0);
+    let mut output = String::new();
+    for (current, local, name, id, target) in rows {
+        let marker = if current { '*' } else { ' ' };
+        match target {
+            Some(target) if verbosity == 0 => output.push_str(&format!("{} {} -> {}\n", marker, name, target)),
+            Some(target)                   => output.push_str(&format!("{} {:<width$} -> {}\n", marker, name, target, width = width)),
+            None if verbosity == 0         => output.push_str(&format!("{} {}\n", marker, name)),
+            None => {
+                let tracking = if local { tracking(repo, &name, &id, verbosity > 1)? } else { String::new() };
+                output.push_str(&format!("{} {:<width$} {} {}{}\n", marker, name, obj::abbreviate(repo, &id, obj::DEFAULT_ABBREV)?,
+                    tracking, subject(repo, &id)?, width = width));
+            }
+        }
+    }
+    Ok(output)
+}
+
+// How a detached HEAD is listed: by the ref last checked out if it still points to the commit it
+// was checked out at, by the abbreviated commit otherwise. It is "at" that commit while HEAD still
+// points to it and "from" it once HEAD moved on.
+fn detached_name(repo: &Repository, head: &Sha) -> R<String> {
+    let checkout = reflog::read(repo, "HEAD")?.into_iter()
+        .find(|e| e.message.starts_with("checkout: moving from "));
+    let checkout = match checkout {
+        Some(checkout) => checkout,
+        None           => return Ok(format!("(HEAD detached at {})", obj::abbreviate(repo, head, obj::DEFAULT_ABBREV)?))
+    };
+    let target = checkout.message.rsplit(" to ").next().unwrap_or_default();
+    let name = match refs::expand(repo, target)? {
+        Some(name) if rev::parse_as(repo, &name, ObjType::Commit).ok().as_ref() == Some(&checkout.new) =>
+            ["refs/tags/", "refs/remotes/"].iter().find_map(|p| name.strip_prefix(p)).unwrap_or(&name).to_string(),
+        _ => obj::abbreviate(repo, &checkout.new, obj::DEFAULT_ABBREV)?
+    };
+    let at = if checkout.new == *head { "at" } else { "from" };
+    Ok(format!("(HEAD detached {} {})", at, name))
+}
+
+// How far the branch is ahead of and behind its upstream, like `[ahead 1, behind 2] `. With
+// `name` the upstream is named too, and shown even when the branch is up to date.
+fn tracking(repo: &Repository, branch: &str, id: &Sha, name: bool) -> R<String> {
+    let full = format!("refs/heads/{}", branch);
+    if repo.config()?.get(&format!("branch.{}.merge", branch))?.is_none() {
+        return Ok(String::new());
+    }
+    let upstream = rev::upstream(repo, &full).ok()
+        .and_then(|u| refs::resolve(repo, &u).ok().flatten().map(|id| (u, id)));
+    let (state, upstream) = match upstream {
+        Some((upstream, upstream_id)) => {
+            let ours = rev::ancestors(repo, id)?;
+            let theirs = rev::ancestors(repo, &upstream_id)?;
+            let ahead = ours.difference(&theirs).count();
+            let behind = theirs.difference(&ours).count();
+            let state = match (ahead, behind) {
+                (0, 0) => String::new(),
+                (a, 0) => format!("ahead {}", a),
+                (0, b) => format!("behind {}", b),
+                (a, b) => format!("ahead {}, behind {}", a, b)
+            };
+            (state, refs::shorten(&upstream).to_string())
+        },
+        None => {
+            let config = repo.config()?;
+            let remote = config.get(&format!("branch.{}.remote", branch))?.unwrap_or_default();
+            let merge = config.get(&format!("branch.{}.merge", branch))?.unwrap_or_default();
+            let merge = refs::shorten(&merge).to_string();
+            ("gone".to_string(), if remote == "." { merge } else { format!("{}/{}", remote, merge) })
+        }
+    };
+    Ok(match (name, state.is_empty()) {
+        (true, true)   => format!("[{}] ", upstream),
+        (true, false)  => format!("[{}: {}] ", upstream, state),
+        (false, true)  => String::new(),
+        (false, false) => format!("[{}] ", state)
+    })
+}
+
+// Deletes the branches, which without `force` must be merged into their upstream or, if they
+// have none or it is gone, into HEAD.
+fn delete_branches(repo: &Repository, names: &[String], force: bool) -> R<String> {
+    let head = refs::read(repo, "HEAD")?;
+    let mut output = String::new();
+    for name in names {
+        let branch = format!("refs/heads/{}", name);
+        let id = match refs::read(repo, &branch)? {
+            Some(Value::Direct(id)) => id,
+            _                       => return Err(format!("Branch '{}' not found.", name).into())
+        };
+        if matches!(&head, Some(Value::Symbolic(target)) if *target == branch) && !repo.is_bare()? {
+            return Err(format!("Cannot delete branch '{}' checked out at '{}'.", name, repo.work_tree()?.display()).into());
+        }
+        if !force {
+            let upstream = match rev::upstream(repo, &branch) {
+                Ok(upstream) => refs::resolve(repo, &upstream)?,
+                Err(_)       => None
+            };
+            let base = match upstream {
+                Some(upstream) => Some(upstream),
+                None           => refs::resolve(repo, "HEAD")?
+            };
+            if !base.map(|base| rev::ancestors(repo, &base)).transpose()?.is_some_and(|a| a.contains(&id)) {
+                return Err(format!("The branch '{}' is not fully merged. Use -D to delete it anyway.", name).into());
+            }
+        }
+        refs::delete(repo, &branch, Some(&id))?;
+        repo.remove_branch_config(name)?;
+        output.push_str(&format!("Deleted branch {} (was {}).\n", name, obj::abbreviate(repo, &id, obj::DEFAULT_ABBREV)?));
+    }
+    Ok(output)
+}
+
+// Renames the branch together with its reflog and config. HEAD follows if it points to it, even
+// if the branch has no commits yet.
+fn rename_branch(repo: &Repository, old: &str, new: &str, force: bool) -> R<String> {
+    let (old_ref, new_ref) = (format!("refs/heads/{}", old), format!("refs/heads/{}", new));
+    refs::check_name(&new_ref)?;
+    if old != new && !force && refs::read(repo, &new_ref)?.is_some() {
+        return Err(format!("A branch named '{}' already exists.", new).into());
+    }
+    let current = matches!(refs::read(repo, "HEAD")?, Some(Value::Symbolic(target)) if target == old_ref);
+    let message = format!("Branch: renamed {} to {}", old_ref, new_ref);
+
+    match refs::read(repo, &old_ref)? {
+        Some(Value::Direct(id)) => {
+            let (old_log, new_log) = (reflog::path(repo, &old_ref), reflog::path(repo, &new_ref));
+            if old_log.is_file() {
+                fs::create_dir_all(new_log.parent().unwrap())?;
+                fs::rename(&old_log, &new_log)
+                    .map_err(|e| format!("Failed to move reflog of {}: {}", old_ref, e))?;
+            }
+            refs::delete(repo, &old_ref, Some(&id))?;
+            refs::update(repo, &new_ref, &id, None, &message)?;
+        },
+        _ if current => {},
+        _            => return Err(format!("No branch named '{}'.", old).into())
+    }
+    if current {
+        refs::write_symbolic(repo, "HEAD", &new_ref, &message)?;
+    }
+
+    if old != new {
+        repo.remove_branch_config(new)?;
+        repo.rename_branch_config(old, new)?;
+    }
+    Ok(String::new())
+}
+
+// Makes the local or remote-tracking branch `upstream` the upstream of the branch.
+fn set_upstream(repo: &Repository, name: &str, upstream: &str) -> R<String> {
+    if refs::read(repo, &format!("refs/heads/{}", name))?.is_none() {
+        return Err(format!("Branch '{}' does not exist.", name).into());
+    }
+    let upstream = refs::expand(repo, upstream)?
+        .filter(|u| u.starts_with("refs/heads/") || u.starts_with("refs/remotes/"))
+        .ok_or_else(|| format!("The requested upstream branch '{}' does not exist.", upstream))?;
+    match upstream.strip_prefix("refs/remotes/").and_then(|u| u.split_once('/')) {
+        Some((remote, branch)) => repo.set_upstream(name, remote, &format!("refs/heads/{}", branch))?,
+        None                   => repo.set_upstream(name, ".", &upstream)?
+    }
+    Ok(format!("branch '{}' set up to track '{}'.\n", name, refs::shorten(&upstream)))
+}
+
+// The branch HEAD points to.
+fn current_branch(repo: &Repository) -> R<String> {
+    match refs::read(repo, "HEAD")? {
+        Some(Value::Symbolic(target)) if target.starts_with("refs/heads/") => Ok(refs::shorten(&target).to_string()),
+        _ => Err("HEAD is not on a branch; name the branch explicitly.".into())
+    }
+}
+
 fn cat_file(args: &mut Peekable<Args>) -> R<String> {
     arg::flag(args, "-p")?;
-    let id = Sha::from_string(arg::unnamed(args, "object id")?)?;
-    let obj = obj::read(&repo::git_dir()?, &id)?;
+    let repo = Repository::find()?;
+    let id = rev::parse(&repo, &arg::unnamed(args, "object")?)?;
+    let obj = obj::read(&repo, &id)?;
     let output = obj::print(&obj);
     Ok(output)
 }
 
+fn check_ignore(args: &mut Peekable<Args>) -> R<String> {
+    let verbose = arg::opt::flag(args, "-v");
+    arg::opt::flag(args, "--");
+    let repo = Repository::find()?;
+    let work_tree = repo.work_tree()?;
+    let args = args.collect::<Vec<_>>();
+    if args.is_empty() {
+        return Err("No path specified.".into());
+    }
+
+    let ignore = Ignore::load(&repo)?;
+    let index = index::read(&repo)?;
+    let mut output = String::new();
+    for arg in args {
+        let path = wtree::relative_path(work_tree, &arg)?;
+        // tracked files are never ignored
+        if index.entries.iter().any(|e| e.path == path) {
+            continue;
+        }
+        match ignore.find_with_parents(&path, work_tree.join(&path).is_dir())? {
+            Some(p) if verbose => output.push_str(&format!("{}:{}:{}\t{}\n", p.source, p.line, p.text, arg)),
+            Some(p) if !p.negated => output.push_str(&format!("{}\n", arg)),
+            _ => {}
+        }
+    }
+    Ok(output)
+}
+
 fn checkout(args: &mut Peekable<Args>) -> R<String> {
-    let commit = Sha::from_string(arg::unnamed(args, "commit id")?)?;
-    wtree::checkout(&repo::git_dir()?, &commit)?;
-    Ok(format!("HEAD is now at {}.", commit))
+    let (mut force, mut detach) = (false, false);
+    let mut new_branch = None;
+    let mut names = Vec::new();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| format!("Not enough arguments provided: missing value for '{}'.", arg));
+        match arg.as_str() {
+            "-f" | "--force" => force = true,
+            "-b"             => new_branch = Some(value()?),
+            "--detach"       => detach = true,
+            "--" => {
+                // everything after it is a path, even if it looks like an option
+                names.push(arg);
+                names.extend(args.by_ref());
+            },
+            _ if arg.starts_with('-') => return Err(format!("Unknown option '{}'.", arg).into()),
+            _ => names.push(arg)
+        }
+    }
+    let args = names;
+    let repo = Repository::find()?;
+    if let Some(pos) = args.iter().position(|a| a == "--") {
+        let tree_ish = match &args[..pos] {
+            []         => None,
+            [tree_ish] => Some(tree_ish.as_str()),
+            _          => return Err("Only one tree-ish can be checked out.".into())
+        };
+        return checkout_paths(&repo, tree_ish, &args[pos + 1..], force);
+    }
+
+    if let Some(branch) = new_branch {
+        let start = args.first().map_or("HEAD", String::as_str);
+        return checkout_new_branch(&repo, &branch, start, force);
+    }
+    let target = match args.first() {
+        Some(target) => target,
+        // like git, detaching without a commit detaches at the current one
+        None if detach => "HEAD",
+        None => return Err("Not enough arguments provided: missing branch or commit.".into())
+    };
+    if args.len() > 1 {
+        // like git, a leading tree-ish is told apart from paths by whether it resolves
+        return match rev::parse_as(&repo, target, ObjType::Tree) {
+            Ok(_)  => checkout_paths(&repo, Some(target), &args[1..], force),
+            Err(_) => checkout_paths(&repo, None, &args, force)
+        };
+    }
+
+    let branch = format!("refs/heads/{}", target);
+    if !detach && refs::check_name(&branch).is_ok() && refs::read(&repo, &branch)?.is_some() {
+        return switch_branch(&repo, &branch, force);
+    }
+    if let Ok(commit) = rev::parse_as(&repo, target, ObjType::Commit) {
+        return detach_head(&repo, target, &commit, force);
+    }
+    if detach {
+        return Err(format!("'{}' is not a commit.", target).into());
+    }
+    // a branch of exactly one remote is taken as the start of a new local branch of that name
+    let remote_branches = refs::list(&repo, "refs/remotes/")?.into_iter()
+        .filter(|r| r.name.splitn(4, '/').nth(3) == Some(target))
+        .collect::<Vec<_>>();
+    if let [remote_branch] = remote_branches.as_slice() {
+        return checkout_new_branch(&repo, target, &remote_branch.name, force);
+    }
+    checkout_paths(&repo, None, &args, force)
+}
+
+// Moves the work tree and the index to the commit and makes HEAD point to the branch.
+fn switch_branch(repo: &Repository, branch: &str, force: bool) -> R<String> {
+    let name = refs::shorten(branch);
+    let from = refs::read(repo, "HEAD")?;
+    if matches!(&from, Some(Value::Symbolic(target)) if target == branch) && !force {
+        return Ok(format!("Already on '{}'\n", name));
+    }
+    let commit = refs::resolve(repo, branch)?.ok_or_else(|| format!("Branch '{}' does not point to a commit.", name))?;
+    wtree::checkout(repo, repo.work_tree()?, refs::resolve(repo, "HEAD")?.as_ref(), &commit, force)?;
+
+    let message = format!("checkout: moving from {} to {}", head_name(from.as_ref()), name);
+    refs::write_symbolic(repo, "HEAD", branch, &message)?;
+    Ok(format!("Switched to branch '{}'\n", name))
+}
+
+// Creates the branch at the start point, which may be a remote-tracking branch to set up as its
+// upstream, and switches to it. Unlike other commits, an unborn HEAD can be used as start point.
+fn checkout_new_branch(repo: &Repository, name: &str, start: &str, force: bool) -> R<String> {
+    let branch = format!("refs/heads/{}", name);
+    refs::check_name(&branch)?;
+    if refs::read(repo, &branch)?.is_some() {
+        return Err(format!("A branch named '{}' already exists.", name).into());
+    }
+    let head = refs::resolve(repo, "HEAD")?;
+    let from = refs::read(repo, "HEAD")?;
+    let message = format!("checkout: moving from {} to {}", head_name(from.as_ref()), name);
+    let commit = match (start, &head) {
+        ("HEAD", None) => {
+            refs::write_symbolic(repo, "HEAD", &branch, &message)?;
+            return Ok(format!("Switched to a new branch '{}'\n", name));
+        },
+        _ => rev::parse_as(repo, start, ObjType::Commit)?
+    };
+
+    wtree::checkout(repo, repo.work_tree()?, head.as_ref(), &commit, force)?;
+    let mut output = create_branch(repo, name, start, &commit)?;
+    refs::write_symbolic(repo, "HEAD", &branch, &message)?;
+    output.push_str(&format!("Switched to a new branch '{}'\n", name));
+    Ok(output)
+}
+
+// Creates the branch at the commit of the start point. A remote-tracking branch as start point
+// becomes the upstream of the new branch.
+fn create_branch(repo: &Repository, name: &str, start: &str, commit: &Sha) -> R<String> {
+    let branch = format!("refs/heads/{}", name);
+    refs::update(repo, &branch, commit, Some(&Sha::from_str(refs::NULL_ID)?), &format!("branch: Created from {}", start))?;
+    match refs::expand(repo, start)? {
+        Some(start) if start.starts_with("refs/remotes/") => set_upstream(repo, name, &start),
+        _                                                 => Ok(String::new())
+    }
+}
+
+// Moves the work tree and the index to the commit and points HEAD directly at it.
+fn detach_head(repo: &Repository, target: &str, commit: &Sha, force: bool) -> R<String> {
+    let from = refs::read(repo, "HEAD")?;
+    wtree::checkout(repo, repo.work_tree()?, refs::resolve(repo, "HEAD")?.as_ref(), commit, force)?;
+    refs::update(repo, "HEAD", commit, None, &format!("checkout: moving from {} to {}", head_name(from.as_ref()), target))?;
+    Ok(format!("HEAD is now at {}\n", describe(repo, commit)?))
+}
+
+// The name of the branch HEAD points to, or its commit if detached, as used in reflog messages.
+fn head_name(head: Option<&Value>) -> String {
+    match head {
+        Some(Value::Symbolic(target)) => refs::shorten(target).to_string(),
+        Some(Value::Direct(id))       => id.to_string(),
+        None                          => String::new()
+    }
+}
+
+// The abbreviated id of the commit followed by the first line of its message.
+fn describe(repo: &Repository, commit: &Sha) -> R<String> {
+    Ok(format!("{} {}", obj::abbreviate(repo, commit, obj::DEFAULT_ABBREV)?, subject(repo, commit)?))
+}
+
+fn subject(repo: &Repository, commit: &Sha) -> R<String> {
+    match obj::read(repo, commit)? {
+        Obj::Commit { message, .. } => Ok(message.lines().next().unwrap_or_default().to_string()),
+        _                           => Ok(String::new())
+    }
+}
+
+// Restores the files selected by the pathspec from the index, or from the tree-ish into both the
+// index and the work tree. A dir where a file goes is only replaced if it is empty, unless forced,
+// as it may hold untracked work.
+fn checkout_paths(repo: &Repository, tree_ish: Option<&str>, args: &[String], force: bool) -> R<String> {
+    let work_tree = repo.work_tree()?;
+    let pathspec = Pathspec::parse(repo, args)?;
+    if pathspec.is_empty() {
+        return Err("No paths given to check out.".into());
+    }
+
+    let mut index = index::read(repo)?;
+    let source = match tree_ish {
+        Some(tree_ish) => Index::from_tree(repo, &rev::parse_as(repo, tree_ish, ObjType::Tree)?)?.entries,
+        None           => index.entries.clone()
+    };
+    let mut entries = Vec::new();
+    for e in source {
+        // intent-to-add entries have no content to restore
+        if !e.intent_to_add && pathspec.matches(&e.path)? {
+            entries.push(e);
+        }
+    }
+    let paths = entries.iter().map(|e| e.path.as_str()).collect::<Vec<_>>();
+    if let Some(unmatched) = pathspec.unmatched(&paths)? {
+        return Err(format!("pathspec '{}' did not match any file(s) known to git", unmatched).into());
+    }
+    if let Some(e) = entries.iter().find(|e| e.stage > 0) {
+        return Err(format!("path '{}' is unmerged", e.path).into());
+    }
+    if !force {
+        for e in &entries {
+            let file = work_tree.join(&e.path);
+            let is_dir = fs::symlink_metadata(&file).is_ok_and(|meta| meta.is_dir());
+            if is_dir && !(e.mode == index::MODE_GITLINK && file.join(".git").exists()) && file.read_dir()?.next().is_some() {
+                return Err(format!("Cannot check out '{}' in place of a directory that is not empty. Use -f to replace it.", e.path).into());
+            }
+        }
+    }
+
+    for e in entries {
+        index.add(wtree::write_entry(repo, work_tree, &e)?);
+    }
+    index.write(repo)?;
+    Ok(String::new())
 }
 
 fn clone(args: &mut Peekable<Args>) -> R<String> {
+    let bare = arg::opt::flag(args, "--bare");
     let url = arg::unnamed(args, "repository URL")?;
     let dir = arg::unnamed(args, "target directory")?;
 
-    println!("Cloning into '{}'...", dir);
-    let git_dir = repo::init(Path::new(&dir))?;
+    println!("Cloning into {}'{}'...", if bare { "bare repository " } else { "" }, dir);
+    let repo = Repository::init(Path::new(&dir), bare)?;
 
     println!("Receiving objects...");
-    let http = Client::new();
-    let (head, mut pack) = pack::http::clone(&http, &url)?;
+    let (head, refs) = if pack::git::is_git_url(&url) {
+        let (head, refs, pack) = pack::git::clone(&url)?;
+        unpack(&repo, pack)?;
+        (head, refs)
+    } else {
+        let mut http = Session::new(&url, repo.config()?)?;
+        let (head, refs, transfer) = pack::http::clone(&mut http)?;
+        match transfer {
+            Transfer::Pack(pack) => unpack(&repo, pack)?,
+            Transfer::Dumb => {
+                println!("Server does not support the smart protocol. Fetching objects one by one...");
+                let objs = pack::dumb::fetch(&mut http, &repo, &refs)?;
+                println!("Fetched {} objects.", objs);
+            }
+        }
+        (head, refs)
+    };
+
+    write_remote_refs(&repo, "origin", &url, &head, &refs, bare)?;
+
+    if !bare {
+        println!("Checking out HEAD {}...", head.name);
+        wtree::checkout(&repo, repo.work_tree()?, None, &head.id, false)?;
+    }
+
+    Ok("...done.".to_string())
+}
+
+fn unpack(repo: &Repository, mut pack: Bytes) -> R<()> {
     let expected_objs = pack::fmt::parse_header(&mut pack)? as usize;
 
     println!("Unpacking {} objects...", expected_objs);
-    let objs = pack::fmt::unpack_objects(&git_dir, &mut pack)?;
+    let objs = pack::fmt::unpack_objects(repo, &mut pack)?;
     if objs != expected_objs {
         return Err(format!("Expected {} objects in pack file but found {}.", expected_objs, objs).into());
     }
 
-    println!("Checking out HEAD {}...", head.name);
-    wtree::checkout(&git_dir, &head.id)?;
+    Ok(())
+}
 
-    Ok("...done.".to_string())
+// Bare clones mirror the remote's branches as local branches instead of remote-tracking ones.
+fn write_remote_refs(repo: &Repository, remote: &str, url: &str, head: &Ref, refs: &[Ref], bare: bool) -> R<()> {
+    let message = format!("clone: from {}", url);
+    repo.add_remote(remote, url)?;
+
+    for Ref { id, name } in refs {
+        if bare && name.starts_with("refs/heads/") {
+            refs::update(repo, name, id, None, &message)?;
+        } else if let Some(branch) = name.strip_prefix("refs/heads/") {
+            refs::update(repo, &format!("refs/remotes/{}/{}", remote, branch), id, None, &message)?;
+        } else if name.starts_with("refs/tags/") && !name.ends_with("^{}") {
+            refs::update(repo, name, id, None, &message)?;
+        }
+    }
+
+    match head.name.strip_prefix("refs/heads/") {
+        Some(_) if bare => refs::write_symbolic(repo, "HEAD", &head.name, &message),
+        Some(branch) => {
+            refs::write_symbolic(repo, "HEAD", &head.name, &message)?;
+            refs::update(repo, &head.name, &head.id, None, &message)?;
+            refs::write_symbolic(repo, &format!("refs/remotes/{}/HEAD", remote), &format!("refs/remotes/{}/{}", remote, branch), "")?;
+            repo.set_upstream(branch, remote, &head.name)
+        },
+        None => refs::update(repo, "HEAD", &head.id, None, &message) // remote HEAD is detached
+    }
+}
+
+fn commit(args: &mut Peekable<Args>) -> R<String> {
+    let (mut amend, mut allow_empty) = (false, false);
+    let (mut author, mut message, mut file): (Option<String>, Option<String>, Option<String>) = (None, None, None);
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| format!("Not enough arguments provided: missing value for '{}'.", arg));
+        match arg.as_str() {
+            "--amend"       => amend = true,
+            "--allow-empty" => allow_empty = true,
+            "--author"      => author = Some(value()?),
+            "-F" | "--file" => file = Some(value()?),
+            "-m" | "--message" => add_message(&mut message, &value()?),
+            _ if arg.starts_with("--author=") => author = Some(arg["--author=".len()..].to_string()),
+            _ => return Err(format!("Unexpected argument '{}'.", arg).into())
+        }
+    }
+    let repo = Repository::find()?;
+    let git_dir = repo.git_dir();
+
+    let mut index = index::read(&repo)?;
+    if index.entries.iter().any(|e| e.stage > 0) {
+        return Err("Committing is not possible because you have unmerged files.".into());
+    }
+    let tree = index.write_tree(&repo)?;
+
+    let (branch, head) = refs::follow(&repo, "HEAD")?;
+    let amended = match (&head, amend) {
+        (Some(head), true) => match obj::read(&repo, head)? {
+            Obj::Commit { parents, author, message, .. } => Some((parents, author, message)),
+            _ => return Err(format!("HEAD {} is not a commit.", head).into())
+        },
+        (None, true) => return Err("You have nothing to amend.".into()),
+        _            => None
+    };
+    let merge_heads = match fs::read_to_string(git_dir.join("MERGE_HEAD")) {
+        Ok(content) => content.lines().map(Sha::from_str).collect::<R<Vec<_>>>()?,
+        Err(_)      => Vec::new()
+    };
+    let parents = match &amended {
+        Some((parents, _, _)) => parents.clone(),
+        None => {
+            let parents = head.iter().cloned().chain(merge_heads.iter().cloned()).collect::<Vec<_>>();
+            let no_ff = fs::read_to_string(git_dir.join("MERGE_MODE")).is_ok_and(|mode| mode == "no-ff");
+            if no_ff { parents } else { reduce_parents(&repo, parents)? }
+        }
+    };
+
+    let parent_tree = match parents.first() {
+        Some(parent) => rev::peel(&repo, parent, ObjType::Tree)?,
+        None         => obj::hash(ObjType::Tree, &[])
+    };
+    if tree == parent_tree && merge_heads.is_empty() && !allow_empty {
+        return Err(match amended {
+            Some(_) => "You asked to amend the most recent commit, but doing so would make it empty.".to_string(),
+            None    => status::print_long(&status::get(&repo, &Pathspec::parse(&repo, &[])?)?, "").trim_end().to_string()
+        }.into());
+    }
+
+    let message = match (message, file) {
+        (Some(_), Some(_))    => return Err("Options -m and -F cannot be used together.".into()),
+        (Some(message), None) => clean_message(&message, false),
+        (None, Some(file))    => clean_message(&read_message_file(&file)?, false),
+        (None, None) => {
+            let initial = match &amended {
+                Some((_, _, message)) => message.clone(),
+                None                  => fs::read_to_string(git_dir.join("MERGE_MSG")).unwrap_or_default()
+            };
+            clean_message(&edit_message(&repo, &initial)?, true)
+        }
+    };
+    if message.is_empty() {
+        return Err("Aborting commit due to empty commit message.".into());
+    }
+
+    let config = repo.config()?;
+    let author = match (author, &amended) {
+        (Some(author), _) => {
+            if !(author.contains('<') && author.ends_with('>')) {
+                return Err(format!("--author '{}' is not 'Name <email>'.", author).into());
+            }
+            // only name and email are replaced, the date is the one the commit would get anyway
+            let ident = ident::get(&config, Role::Author, false)?;
+            format!("{}{}", author, &ident[ident.rfind('>').map_or(0, |i| i + 1)..])
+        },
+        (None, Some((_, author, _))) => author.clone(),
+        (None, None)                 => ident::get(&config, Role::Author, true)?
+    };
+    let committer = ident::get(&config, Role::Committer, true)?;
+    let subject = message.lines().next().unwrap_or_default().to_string();
+    let commit = Obj::Commit { tree, parents, author, committer, message };
+    let id = obj::write(&repo, ObjType::Commit, obj::print(&commit).as_bytes())?;
+
+    let kind = if amended.is_some() {
+        " (amend)"
+    } else if head.is_none() {
+        " (initial)"
+    } else if !merge_heads.is_empty() {
+        " (merge)"
+    } else {
+        ""
+    };
+    let old = match &head {
+        Some(head) => head.clone(),
+        None       => Sha::from_str(refs::NULL_ID)?
+    };
+    // like git, the index with its refreshed TREE extension is written before the ref is updated
+    index.write(&repo)?;
+    refs::update(&repo, &branch, &id, Some(&old), &format!("commit{}: {}", kind, subject))?;
+    for name in ["MERGE_HEAD", "MERGE_MSG", "MERGE_MODE"].iter() {
+        if git_dir.join(name).is_file() {
+            fs::remove_file(git_dir.join(name))?;
+        }
+    }
+
+    let name = if branch == "HEAD" { "detached HEAD" } else { refs::shorten(&branch) };
+    let root = if head.is_none() { " (root-commit)" } else { "" };
+    Ok(format!("[{}{} {}] {}\n", name, root, obj::abbreviate(&repo, &id, obj::DEFAULT_ABBREV)?, subject))
+}
+
+// Drops duplicate parents and those reachable from another one, which add nothing to the history.
+fn reduce_parents(repo: &Repository, parents: Vec<Sha>) -> R<Vec<Sha>> {
+    let mut unique = Vec::new();
+    for parent in parents {
+        if !unique.contains(&parent) {
+            unique.push(parent);
+        }
+    }
+    let ancestors = unique.iter().map(|p| rev::ancestors(repo, p)).collect::<R<Vec<_>>>()?;
+    Ok(unique.iter().enumerate()
+        .filter(|(i, p)| !ancestors.iter().enumerate().any(|(j, a)| *i != j && a.contains(p)))
+        .map(|(_, p)| p.clone())
+        .collect())
+}
+
+// Lets the user write the message in COMMIT_EDITMSG with the editor, starting from `initial` and
+// a commented out status as template.
+fn edit_message(repo: &Repository, initial: &str) -> R<String> {
+    let mut template = initial.to_string();
+    template.push_str("\n# Please enter the commit message for your changes. Lines starting\n\
+        # with '#' will be ignored, and an empty message aborts the commit.\n#\n");
+    let status = status::print_long(&status::get(repo, &Pathspec::parse(repo, &[])?)?, "");
+    for line in status.lines() {
+        template.push_str(&match line {
+            ""                          => "#\n".to_string(),
+            _ if line.starts_with('\t') => format!("#{}\n", line),
+            _                           => format!("# {}\n", line)
+        });
+    }
+    let path = repo.git_dir().join("COMMIT_EDITMSG");
+    fs::write(&path, template)?;
+
+    let config = repo.config()?;
+    let editor = match env::var("GIT_EDITOR") {
+        Ok(editor) => editor,
+        Err(_)     => match config.get("core.editor")? {
+            Some(editor) => editor,
+            None         => env::var("VISUAL").or_else(|_| env::var("EDITOR")).unwrap_or_else(|_| "vi".to_string())
+        }
+    };
+    // like git, the editor is run by the shell so that it may come with arguments
+    let status = Command::new("sh").arg("-c").arg(format!("{} \"$@\"", editor)).arg(&editor).arg(&path).status()
+        .map_err(|e| format!("Failed to start editor '{}': {}", editor, e))?;
+    if !status.success() {
+        return Err(format!("There was a problem with the editor '{}'.", editor).into());
+    }
+    fs::read_to_string(&path).map_err(|e| format!("Failed to read {:?}: {}", path, e).into())
+}
+
+// Adds the text of a `-m` option to the message of the ones before it.
+fn add_message(message: &mut Option<String>, text: &str) {
+    // like git, several messages become separate paragraphs
+    let message = message.get_or_insert_with(String::new);
+    if !message.is_empty() {
+        message.push_str("\n\n");
+    }
+    message.push_str(text);
 }
 
+// Reads a message from the file, or from stdin for `-`.
+fn read_message_file(file: &str) -> R<String> {
+    if file == "-" {
+        let mut message = String::new();
+        io::stdin().read_to_string(&mut message)?;
+        return Ok(message);
+    }
+    fs::read_to_string(file).map_err(|e| format!("Failed to read message from {}: {}", file, e).into())
+}
+
+// Drops trailing whitespace, leading and trailing blank lines and repeated blank lines, and with
+// `comments` also lines starting with `#`, like git's default cleanup of commit messages.
+fn clean_message(message: &str, comments: bool) -> String {
+    let mut lines = Vec::new();
+    let mut blank = false;
+    for line in message.lines() {
+        if comments && line.starts_with('#') {
+            continue;
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            blank = !lines.is_empty();
+        } else {
+            if blank {
+                lines.push("");
+                blank = false;
+            }
+            lines.push(line);
+        }
+    }
+    lines.join("\n")
+}
+
+// Like git's plumbing, the options may come in any order. Messages of several `-m` and `-F` are
+// separated by blank lines and used verbatim; without any, the message is read from stdin.
 fn commit_tree(args: &mut Peekable<Args>) -> R<String> {
-    let tree = Sha::from_string(arg::unnamed(args, "SHA")?)?;
-    let parent = arg::opt::named(args, "-p")?.map(Sha::from_string).transpose()?;
-    let message = arg::named(args, "-m")?;
+    let repo = Repository::find()?;
+    let mut tree = None;
+    let mut parents: Vec<Sha> = Vec::new();
+    let mut message: Option<String> = None;
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| format!("Not enough arguments provided: missing value for '{}'.", arg));
+        match arg.as_str() {
+            "-p" => {
+                let parent = rev::parse_as(&repo, &value()?, ObjType::Commit)?;
+                // like git, a parent given twice is only recorded once
+                if !parents.contains(&parent) {
+                    parents.push(parent);
+                }
+            },
+            "-m" | "-F" => {
+                let text = if arg == "-m" { value()? } else { read_message_file(&value()?)? };
+                let message = message.get_or_insert_with(String::new);
+                if !message.is_empty() {
+                    message.push('\n');
+                }
+                message.push_str(&text);
+                // unlike files, each `-m` is a complete line
+                if arg == "-m" && !message.ends_with('\n') {
+                    message.push('\n');
+                }
+            },
+            _ if tree.is_none() => tree = Some(rev::parse_as(&repo, &arg, ObjType::Tree)?),
+            _ => return Err(format!("Unexpected argument '{}'.", arg).into())
+        }
+    }
+    let tree = tree.ok_or("Not enough arguments provided: missing tree.")?;
+    let message = match message {
+        Some(message) => message,
+        None          => read_message_file("-")?
+    };
 
-    let author = obj::print_commit_author("bert2", "shuairan@gmail.com")?;
-    let committer = author.clone();
-    let commit = Obj::Commit { tree, parent, author, committer, message };
-    let commit = obj::print(&commit);
-    let id = obj::write(&repo::git_dir()?, ObjType::Commit, commit.as_bytes())?;
+    let config = repo.config()?;
+    let author = ident::get(&config, Role::Author, true)?;
+    let committer = ident::get(&config, Role::Committer, true)?;
+    let commit = Obj::Commit { tree, parents, author, committer, message: message.strip_suffix('\n').unwrap_or(&message).to_string() };
+    let mut commit = obj::print(&commit);
+    // the message is kept as is, so one without a final newline does not get one
+    if !message.ends_with('\n') {
+        commit.pop();
+    }
+    let id = obj::write(&repo, ObjType::Commit, commit.as_bytes())?;
 
     Ok(id.into())
 }
 
+fn config(args: &mut Peekable<Args>) -> R<String> {
+    fn scope(args: &mut Peekable<Args>) -> Option<config::Scope> {
+        if arg::opt::flag(args, "--system") {
+            Some(config::Scope::System)
+        } else if arg::opt::flag(args, "--global") {
+            Some(config::Scope::Global)
+        } else if arg::opt::flag(args, "--local") {
+            Some(config::Scope::Local)
+        } else {
+            None
+        }
+    }
+
+    // the scope may be given before or after the subcommand
+    let scope_before = scope(args);
+    let subcommand = arg::unnamed(args, "subcommand")?;
+    let scope = scope_before.or_else(|| scope(args));
+    let entries = || match scope {
+        Some(scope) => config::list_scope(scope),
+        None        => config::list()
+    };
+    let file = || config::file(scope.unwrap_or(config::Scope::Local));
+
+    match subcommand.as_str() {
+        "get" => {
+            let all = arg::opt::flag(args, "--all");
+            let key = config::normalize_key(&arg::unnamed(args, "key")?)?;
+            let values = entries()?.into_iter()
+                .filter(|e| e.key == key)
+                .map(|e| e.value.unwrap_or_default())
+                .collect::<Vec<_>>();
+            match values.last() {
+                Some(_) if all => Ok(values.join("\n")),
+                Some(value)    => Ok(value.clone()),
+                None           => Err(format!("Key '{}' is not set.", key).into())
+            }
+        },
+        "set" => {
+            let add = arg::opt::flag(args, "--add");
+            let key = arg::unnamed(args, "key")?;
+            let value = arg::unnamed(args, "value")?;
+            config::set(&file()?, &key, &value, add)?;
+            Ok(String::new())
+        },
+        "unset" => {
+            let all = arg::opt::flag(args, "--all");
+            let key = arg::unnamed(args, "key")?;
+            config::unset(&file()?, &key, all)?;
+            Ok(String::new())
+        },
+        "list" => {
+            let show_scope = arg::opt::flag(args, "--show-scope");
+            Ok(entries()?.into_iter()
+                .map(|e| {
+                    let entry = match e.value {
+                        Some(value) => format!("{}={}", e.key, value),
+                        None        => e.key
+                    };
+                    if show_scope { format!("{}\t{}", e.scope.as_str(), entry) } else { entry }
+                })
+                .collect::<Vec<_>>()
+                .join("\n"))
+        },
+        cmd => Err(format!("Unknown config subcommand '{}'. Expected get, set, unset or list.", cmd).into())
+    }
+}
+
 fn hash_object(args: &mut Peekable<Args>) -> R<String> {
     arg::flag(args, "-w")?;
     let file = arg::unnamed(args, "file")?;
     let content = wtree::read_file(Path::new(&file))?;
-    let id = obj::write(&repo::git_dir()?, ObjType::Blob, &content)?;
+    let id = obj::write(&Repository::find()?, ObjType::Blob, &content)?;
     Ok(id.into())
 }
 
-fn init() -> R<String> {
-    let git_dir = repo::init(Path::new("."))?;
-    Ok(format!("Initialized empty Git repository in {:?}.", git_dir))
+fn init(args: &mut Peekable<Args>) -> R<String> {
+    let bare = arg::opt::flag(args, "--bare");
+    let dir = args.next().unwrap_or(".".to_string());
+    let repo = Repository::init(Path::new(&dir), bare)?;
+    Ok(format!("Initialized empty Git repository in {:?}.", repo.git_dir()))
 }
 
 fn ls_remote(args: &mut Peekable<Args>) -> R<String> {
     let refs_only = arg::opt::flag(args, "--refs");
     let url = arg::unnamed(args, "repository URL")?;
-    let http = Client::new();
-    let refs = pack::http::get_advertised_refs(&http, &url, refs_only)?
-        .iter()
+    let refs = if pack::git::is_git_url(&url) {
+        pack::git::get_advertised_refs(&url, refs_only)?
+    } else {
+        pack::http::get_advertised_refs(&mut Session::new(&url, Rc::new(Config::load(None)?))?, refs_only)?
+    };
+    let refs = refs.iter()
         .map(|Ref {id, name}| format!("{}\t{}", id, name))
         .collect::<Vec<_>>();
     Ok(refs.join("\n"))
 }
 
 fn ls_tree(args: &mut Peekable<Args>) -> R<String> {
+    // Lists the entries of the tree selected by the pathspec, looking into the subtrees that may
+    // contain selected entries. With `recursive` only blobs and submodules are listed.
+    fn list(repo: &Repository, id: &Sha, prefix: &str, pathspec: &Pathspec, recursive: bool, entries: &mut Vec<(String, TreeEntry)>) -> R<()> {
+        let tree = match obj::read(repo, id)? {
+            Obj::Tree { entries } => entries,
+            _ => return Err(format!("Object {} is not a tree.", id).into())
+        };
+        for e in tree {
+            let path = format!("{}{}", prefix, e.name);
+            if e.mode == 40000 && (recursive || !pathspec.matches(&path)?) {
+                if pathspec.may_contain(&path) {
+                    list(repo, &e.id, &format!("{}/", path), pathspec, recursive, entries)?;
+                }
+            } else if pathspec.matches(&path)? {
+                entries.push((path, e));
+            }
+        }
+        Ok(())
+    }
+
+    let recursive = arg::opt::flag(args, "-r");
     let name_only = arg::opt::flag(args, "--name-only");
-    let id = Sha::from_string(arg::unnamed(args, "SHA")?)?;
-    let obj = obj::read(&repo::git_dir()?, &id)?;
+    let abbrev = match args.peek().and_then(|a| a.strip_prefix("--abbrev")) {
+        Some(len) => {
+            let len = match len.strip_prefix('=') {
+                Some(len) => len.parse().map_err(|_| format!("Invalid abbreviation length '{}'.", len))?,
+                None      => obj::DEFAULT_ABBREV
+            };
+            args.next();
+            Some(len)
+        },
+        None => None
+    };
+    let repo = Repository::find()?;
+    let id = rev::parse_as(&repo, &arg::unnamed(args, "tree-ish")?, ObjType::Tree)?;
+    let pathspec = Pathspec::parse(&repo, &args.collect::<Vec<_>>())?;
 
-    match obj {
-        Obj::Tree { entries } if name_only =>
-            Ok(entries.iter()
-                .map(|e| e.name.clone() + "\n")
-                .collect::<Vec<_>>()
-                .concat()),
-        Obj::Tree {..} =>
-            Ok(obj::print(&obj)),
-        _ => Err(format!("Object {} is not a tree.", id).into())
+    let mut entries = Vec::new();
+    list(&repo, &id, "", &pathspec, recursive, &mut entries)?;
+    if name_only {
+        return Ok(entries.iter().map(|(path, _)| format!("{}\n", path)).collect());
     }
+    entries.iter()
+        .map(|(path, e)| {
+            let id = match abbrev {
+                Some(len) => obj::abbreviate(&repo, &e.id, len)?,
+                None      => e.id.to_string()
+            };
+            Ok(format!("{:06} {} {}    {}\n", e.mode, e.print_type(), id, path))
+        })
+        .collect::<R<Vec<_>>>()
+        .map(|lines| lines.concat())
+}
+
+fn reflog(args: &mut Peekable<Args>) -> R<String> {
+    arg::opt::flag(args, "show");
+    let spec = args.next().unwrap_or_else(|| "HEAD".to_string());
+    let repo = Repository::find()?;
+
+    let (name, start) = match spec.strip_suffix('}').and_then(|s| s.rsplit_once("@{")) {
+        Some((name, n)) => (name, n.parse::<usize>().map_err(|_| format!("Invalid reflog index in '{}'.", spec))?),
+        None            => (spec.as_str(), 0)
+    };
+    let full_name = refs::expand(&repo, name)?
+        .ok_or(format!("Unknown ref '{}'.", name))?;
+    if start > 0 {
+        reflog::nth(&repo, &full_name, start)?;
+    }
+
+    let lines = reflog::read(&repo, &full_name)?.iter()
+        .enumerate()
+        .skip(start)
+        .map(|(i, entry)| Ok(format!("{} {}@{{{}}}: {}",
+            obj::abbreviate(&repo, &entry.new, obj::DEFAULT_ABBREV)?, name, i, entry.message)))
+        .collect::<R<Vec<_>>>()?;
+
+    Ok(lines.join("\n"))
+}
+
+fn rev_parse(args: &mut Peekable<Args>) -> R<String> {
+    let verify = arg::opt::flag(args, "--verify");
+    let short = arg::opt::flag(args, "--short");
+    let revs = args.collect::<Vec<_>>();
+    let repo = Repository::find()?;
+
+    if verify && revs.len() != 1 {
+        return Err("Exactly one revision expected with '--verify'.".into());
+    }
+
+    let ids = revs.iter()
+        .map(|spec| {
+            let id = rev::parse(&repo, spec)?;
+            if short { obj::abbreviate(&repo, &id, obj::DEFAULT_ABBREV) } else { Ok(id.to_string()) }
+        })
+        .collect::<R<Vec<_>>>()?;
+
+    Ok(ids.join("\n"))
+}
+
+fn rm(args: &mut Peekable<Args>) -> R<String> {
+    let cached = arg::opt::flag(args, "--cached");
+    let force = arg::opt::flag(args, "-f");
+    let recursive = arg::opt::flag(args, "-r");
+    let quiet = arg::opt::flag(args, "-q");
+    arg::opt::flag(args, "--");
+    let repo = Repository::find()?;
+    let work_tree = repo.work_tree()?;
+    let args = args.collect::<Vec<_>>();
+    if args.is_empty() {
+        return Err("No pathspec was given. Which files should I remove?".into());
+    }
+
+    let pathspec = Pathspec::parse(&repo, &args)?;
+
+    let mut index = index::read(&repo)?;
+    let removed = tracked_paths(&index, &pathspec)?;
+    let matched = removed.iter().map(String::as_str).collect::<Vec<_>>();
+    if let Some(unmatched) = pathspec.unmatched(&matched)? {
+        return Err(format!("pathspec '{}' did not match any files", unmatched).into());
+    }
+    if !recursive {
+        if let Some(dir) = pathspec.matched_as_dir(&matched)? {
+            return Err(format!("not removing '{}' recursively without -r", dir).into());
+        }
+    }
+
+    // nothing is removed unless all files can be removed without losing changes
+    if !force {
+        let head = match refs::resolve(&repo, "HEAD")? {
+            Some(commit) => Index::from_tree(&repo, &rev::peel(&repo, &commit, ObjType::Tree)?)?,
+            None         => Index::default()
+        };
+        for path in &removed {
+            let entry = match index.find(path) {
+                Some(entry) => entry,
+                None        => continue // unmerged
+            };
+            let staged = head.find(path).is_none_or(|h| h.id != entry.id || h.mode != entry.mode);
+            let local = work_tree.join(path).symlink_metadata().is_ok() && wtree::is_modified(&repo, work_tree, &index, entry)?;
+            let problem = if staged && local {
+                "has staged content different from both the file and the HEAD"
+            } else if staged && !cached {
+                "has changes staged in the index"
+            } else if local && !cached {
+                "has local modifications"
+            } else {
+                continue;
+            };
+            return Err(format!("'{}' {} (use --cached to keep the file, or -f to force removal).", path, problem).into());
+        }
+    }
+
+    for path in &removed {
+        index.remove(path);
+    }
+    index.write(&repo)?;
+
+    if !cached {
+        for path in &removed {
+            wtree::remove_file(work_tree, path)?;
+        }
+    }
+
+    Ok(if quiet { String::new() } else { removed.iter().map(|p| format!("rm '{}'\n", p)).collect() })
+}
+
+fn serve(args: &mut Peekable<Args>) -> R<String> {
+    let addr = arg::opt::named(args, "--listen")?.unwrap_or("127.0.0.1:8080".to_string());
+    let dir = args.next().unwrap_or(".".to_string());
+    serve::run(&Repository::discover(Path::new(&dir))?, &addr)?;
+    Ok(String::new())
+}
+
+fn show_ref(args: &mut Peekable<Args>) -> R<String> {
+    let heads = arg::opt::flag(args, "--heads");
+    let tags = arg::opt::flag(args, "--tags");
+    let dereference = arg::opt::flag(args, "-d") || arg::opt::flag(args, "--dereference");
+    let hash_only = arg::opt::flag(args, "--hash");
+    let verify = arg::opt::flag(args, "--verify");
+    let patterns = args.collect::<Vec<_>>();
+    let repo = Repository::find()?;
+
+    let refs = if verify {
+        if patterns.is_empty() {
+            return Err("Not enough arguments provided: missing ref to verify.".into());
+        }
+        patterns.iter()
+            .map(|name| match refs::read(&repo, name)? {
+                Some(Value::Direct(id)) if name.starts_with("refs/") => Ok(Ref { id, name: name.clone() }),
+                _ => Err(format!("'{}' - not a valid ref.", name).into())
+            })
+            .collect::<R<Vec<_>>>()?
+    } else {
+        refs::list(&repo, "refs/")?.into_iter()
+            .filter(|r| !(heads || tags)
+                || (heads && r.name.starts_with("refs/heads/"))
+                || (tags && r.name.starts_with("refs/tags/")))
+            .filter(|r| patterns.is_empty()
+                || patterns.iter().any(|p| r.name == *p || r.name.ends_with(&format!("/{}", p))))
+            .collect::<Vec<_>>()
+    };
+
+    let mut lines = Vec::new();
+    for r in refs {
+        lines.push(if hash_only { r.id.to_string() } else { format!("{} {}", r.id, r.name) });
+        if dereference {
+            if let Some(peeled) = refs::peel(&repo, &r)? {
+                lines.push(if hash_only { peeled.to_string() } else { format!("{} {}^{{}}", peeled, r.name) });
+            }
+        }
+    }
+
+    if lines.is_empty() && !patterns.is_empty() {
+        return Err(format!("No refs matching {}.", patterns.join(", ")).into());
+    }
+    Ok(lines.join("\n"))
+}
+
+fn status(args: &mut Peekable<Args>) -> R<String> {
+    let short = arg::opt::flag(args, "--short") || arg::opt::flag(args, "-s");
+    let porcelain = if arg::opt::flag(args, "--porcelain") || arg::opt::flag(args, "--porcelain=v1") {
+        Some(1)
+    } else if arg::opt::flag(args, "--porcelain=v2") {
+        Some(2)
+    } else {
+        None
+    };
+    arg::opt::flag(args, "--");
+    let repo = Repository::find()?;
+    let prefix = wtree::relative_path(repo.work_tree()?, ".")?;
+    let status = status::get(&repo, &Pathspec::parse(&repo, &args.collect::<Vec<_>>())?)?;
+
+    Ok(match porcelain {
+        Some(2)       => status::print_porcelain_v2(&status, &prefix),
+        Some(_)       => status::print_short(&status, ""),
+        None if short => status::print_short(&status, &prefix),
+        None          => status::print_long(&status, &prefix)
+    })
+}
+
+fn symbolic_ref(args: &mut Peekable<Args>) -> R<String> {
+    let delete = arg::opt::flag(args, "-d");
+    let short = arg::opt::flag(args, "--short");
+    let message = arg::opt::named(args, "-m")?.unwrap_or_default();
+    let name = arg::unnamed(args, "symbolic ref name")?;
+    let repo = Repository::find()?;
+
+    if delete {
+        refs::delete_symbolic(&repo, &name)?;
+        return Ok(String::new());
+    }
+
+    match args.next() {
+        Some(target) => {
+            refs::write_symbolic(&repo, &name, &target, &message)?;
+            Ok(String::new())
+        },
+        None => match refs::read(&repo, &name)? {
+            Some(Value::Symbolic(target)) if short => Ok(refs::shorten(&target).to_string()),
+            Some(Value::Symbolic(target))          => Ok(target),
+            Some(Value::Direct(_))                 => Err(format!("Ref {} is not a symbolic ref.", name).into()),
+            None                                   => Err(format!("No such ref: {}.", name).into())
+        }
+    }
+}
+
+fn tag(args: &mut Peekable<Args>) -> R<String> {
+    let (mut list, mut delete, mut force, mut annotate) = (false, false, false, false);
+    let mut message: Option<String> = None;
+    let mut names = Vec::new();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| format!("Not enough arguments provided: missing value for '{}'.", arg));
+        match arg.as_str() {
+            "-l" | "--list"     => list = true,
+            "-d" | "--delete"   => delete = true,
+            "-f" | "--force"    => force = true,
+            "-a" | "--annotate" => annotate = true,
+            "-m" | "--message"  => add_message(&mut message, &value()?),
+            _ if arg.starts_with('-') => return Err(format!("Unknown option '{}'.", arg).into()),
+            _ => names.push(arg)
+        }
+    }
+    let args = names;
+    let repo = Repository::find()?;
+
+    if list || args.is_empty() && !delete && !annotate && message.is_none() {
+        let mut output = String::new();
+        for r in refs::list(&repo, "refs/tags/")? {
+            let name = refs::shorten(&r.name);
+            if args.is_empty() || args.iter().any(|p| glob::wildmatch(p.as_bytes(), name.as_bytes(), false)) {
+                output.push_str(&format!("{}\n", name));
+            }
+        }
+        return Ok(output);
+    }
+
+    if delete {
+        let mut output = String::new();
+        for name in &args {
+            let tag = format!("refs/tags/{}", name);
+            let id = match refs::read(&repo, &tag)? {
+                Some(Value::Direct(id)) => id,
+                _                       => return Err(format!("Tag '{}' not found.", name).into())
+            };
+            refs::delete(&repo, &tag, Some(&id))?;
+            output.push_str(&format!("Deleted tag '{}' (was {})\n", name, obj::abbreviate(&repo, &id, obj::DEFAULT_ABBREV)?));
+        }
+        return Ok(output);
+    }
+
+    let (name, target) = match args.as_slice() {
+        [name]         => (name, "HEAD"),
+        [name, target] => (name, target.as_str()),
+        _              => return Err("Expecting a tag name and an optional object to tag.".into())
+    };
+    let tag = format!("refs/tags/{}", name);
+    refs::check_name(&tag)?;
+    let previous = refs::resolve(&repo, &tag)?;
+    if previous.is_some() && !force {
+        return Err(format!("Tag '{}' already exists.", name).into());
+    }
+
+    let mut id = rev::parse(&repo, target)?;
+    if annotate || message.is_some() {
+        let message = message.ok_or("No tag message given, use -m.")?;
+        let (obj_type, _) = obj::read_raw(&repo, &id)?;
+        let tagger = ident::get(&*repo.config()?, Role::Committer, true)?;
+        let content = format!("object {}\ntype {}\ntag {}\ntagger {}\n\n{}\n", id, obj_type.as_str(), name, tagger, message.trim_end());
+        id = obj::write(&repo, ObjType::Tag, content.as_bytes())?;
+    }
+    refs::update(&repo, &tag, &id, previous.as_ref(), "")?;
+
+    match previous {
+        Some(previous) if previous != id =>
+            Ok(format!("Updated tag '{}' (was {})\n", name, obj::abbreviate(&repo, &previous, obj::DEFAULT_ABBREV)?)),
+        _ => Ok(String::new())
+    }
+}
+
+fn update_ref(args: &mut Peekable<Args>) -> R<String> {
+    let message = arg::opt::named(args, "-m")?.unwrap_or_default();
+    let delete = arg::opt::flag(args, "-d");
+    let no_deref = arg::opt::flag(args, "--no-deref");
+    let name = arg::unnamed(args, "ref name")?;
+    let repo = Repository::find()?;
+
+    let name = if no_deref { name } else { refs::follow(&repo, &name)?.0 };
+
+    if delete {
+        let old = args.next().map(Sha::from_string).transpose()?;
+        refs::delete(&repo, &name, old.as_ref())?;
+    } else {
+        let new = Sha::from_string(arg::unnamed(args, "new value")?)?;
+        let old = args.next().map(Sha::from_string).transpose()?;
+        if !obj::exists(&repo, &new)? {
+            return Err(format!("Object {} does not exist.", new).into());
+        }
+        refs::update(&repo, &name, &new, old.as_ref(), &message)?;
+    }
+
+    Ok(String::new())
 }
 
 fn write_tree() -> R<String> {
-    let id = wtree::write_tree(&repo::git_dir()?, Path::new("."))?;
+    let repo = Repository::find()?;
+    let mut index = index::read(&repo)?;
+    let id = index.write_tree(&repo)?;
+    index.write(&repo)?;
     Ok(id.into())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clean_messages() {
+        assert_eq!(clean_message("subject", false), "subject");
+        assert_eq!(clean_message("\n\n  \nsubject  \n\n\n\nbody\t\nmore\n\n", false), "subject\n\nbody\nmore");
+        assert_eq!(clean_message("subject\n# comment\n\n#\nbody\n", true), "subject\n\nbody");
+        assert_eq!(clean_message("# only a comment\n", true), "");
+        assert_eq!(clean_message("#1 is kept\n", false), "#1 is kept");
+        assert_eq!(clean_message("  indented\n", false), "  indented");
+    }
+}
@@ -1,236 +1,439 @@
-use std::{convert::{TryInto, TryFrom}, fs::{self, File}, iter, path::Path, str::{self, FromStr}};
-use crate::{util, sha::Sha, zlib};
-
-type R<T> = std::result::Result<T, Box<dyn std::error::Error>>;
-
-pub enum Obj {
-    Commit {
-        tree: Sha,
-        parent: Option<Sha>,
-        author: String,
-        committer: String,
-        message: String
-    },
-    Tree { entries: Vec<TreeEntry> },
-    Blob { content: Vec<u8> }
-}
-
-#[derive(Clone,Copy,Debug)]
-pub enum ObjType {
-    Commit,
-    Tree,
-    Blob,
-    Tag
-}
-
-impl ObjType {
-    pub fn as_str(&self) -> &'static str {
-        match self {
-            ObjType::Commit => "commit",
-            ObjType::Tree   => "tree",
-            ObjType::Blob   => "blob",
-            ObjType::Tag    => panic!("Unsupported object type {:?}.", self),
-        }
-    }
-}
-
-#[derive(Clone,Debug)]
-pub struct TreeEntry {
-    pub mode: u32,
-    pub name: String,
-    pub id: Sha,
-}
-
-impl TreeEntry {
-    pub fn print_type(&self) -> &'static str {
-        match self.mode {
-            040000 => "tree",
-            100644 |
-            100755 => "blob",
-            mode   => panic!("Unsupported file mode: {}", mode)
-        }
-    }
-}
-
-impl TryFrom<&str> for ObjType {
-    type Error = String;
-    fn try_from(obj_type: &str) -> Result<Self, Self::Error> {
-        match obj_type {
-            "commit" => Ok(ObjType::Commit),
-            "tree"   => Ok(ObjType::Tree),
-            "blob"   => Ok(ObjType::Blob),
-            "tag"    => Ok(ObjType::Tag),
-            _        => Err(format!("Unkown object type {:?}.", obj_type).into())
-        }
-    }
-}
-
-pub fn create(git_dir: &Path, id: &Sha) -> R<File> {
-    let (dir, filename) = id.value().split_at(2);
-    let obj_dir = git_dir.join("objects").join(dir);
-    fs::create_dir_all(&obj_dir)?;
-    let path = obj_dir.join(filename);
-    let file = fs::File::create(&path)
-        .map_err(|e| format!("Failed to create object {:?}. {}", path, e))?;
-    Ok(file)
-}
-
-pub fn open(git_dir: &Path, id: &Sha) -> R<File> {
-    let (dir, filename) = id.value().split_at(2);
-    let path = git_dir.join("objects").join(dir).join(filename);
-    let file = File::open(&path)
-        .map_err(|e| format!("Failed to read object {:?}. {}", path, e))?;
-    Ok(file)
-}
-
-pub fn read(git_dir: &Path, id: &Sha) -> R<Obj> {
-    fn parse_commit(bytes: &mut Vec<u8>) -> R<Obj> {
-        fn parse_line(label: &str, bytes: &mut Vec<u8>) -> R<String> {
-            let end = bytes.iter().position(|&b| b == b'\n').ok_or("Unexpected EOF.")?;
-            let line = &bytes[..end];
-            let mut line = line.splitn(2, |&b| b == b' ');
-
-            let line_lbl = str::from_utf8(line.next().unwrap())
-                .map_err(|e| format!("Failed to parse label of commit {}: {}", label, e))?;
-            if line_lbl != label {
-                return Err(format!("Expected label '{}'. Got '{}' instead.", label, line_lbl).into());
-            }
-
-            let data = line.next().ok_or(format!("Missing data for commit {}.", label))?;
-            let data = str::from_utf8(data)
-                .map_err(|e| format!("Invalid data in commit {}: {}", label, e))?
-                .to_string();
-
-            bytes.drain(..end+1);
-
-            Ok(data)
-        }
-
-        let tree = Sha::from_string(parse_line("tree", bytes)?)?;
-        let parent = Some(Sha::from_string(parse_line("parent", bytes)?)?);
-        let author = parse_line("author", bytes)?;
-        let committer = parse_line("committer", bytes)?;
-        let message = str::from_utf8(&bytes[1 .. bytes.len()-1])
-            .map_err(|e| format!("Invalid data in commit message: {}", e))?
-            .to_string();
-
-        Ok(Obj::Commit { tree, parent, author, committer, message })
-    }
-
-    fn parse_tree(bytes: &[u8]) -> R<Obj> {
-        fn iterate_tree(mut bytes: &[u8]) -> impl Iterator<Item = R<TreeEntry>> + '_ {
-            let id_len = 20;
-
-            iter::from_fn(move || {
-                let utf8_end = bytes.iter().position(|&b| b == 0)?;
-
-                let mode_name = &bytes[..utf8_end];
-                let mut mode_name = mode_name.split(|&b| b == b' ');
-
-                let mode = mode_name.next().unwrap();
-                let mode = str::from_utf8(mode);
-                if let Err(e) = mode { return Some(Err(format!("Invalid mode in tree entry: {}", e).into())) }
-                let mode = u32::from_str(mode.unwrap());
-                if let Err(e) = mode { return Some(Err(format!("Invalid mode in tree entry: {}", e).into())) }
-                let mode = mode.unwrap();
-
-                let name = mode_name.next();
-                if let None = name { return Some(Err("Missing filename in tree entry.".into())); }
-                let name = str::from_utf8(name.unwrap());
-                if let Err(e) = name { return Some(Err(format!("Invalid filename in tree entry: {}", e).into())) }
-                let name = name.unwrap().to_string();
-
-                let id = &bytes[utf8_end+1 .. utf8_end+1+id_len];
-                let id = Sha::from_bytes(id);
-                if let Err(e) = id { return Some(Err(format!("Invalid SHA in tree entry: {}", e).into())) }
-
-                bytes = &bytes[utf8_end+1+id_len ..];
-                Some(Ok(TreeEntry { id: id.unwrap(), mode, name }))
-            })
-        }
-
-        let entries = iterate_tree(bytes).collect::<R<Vec<_>>>()?;
-
-        Ok(Obj::Tree { entries })
-    }
-
-    let (mut bytes, _) = zlib::inflate(open(git_dir, id)?)?;
-    let header_end = bytes.iter().position(|&b| b == 0)
-        .ok_or(format!("Object {} has no header.", id))?;
-
-    let header = &bytes[..header_end];
-    let mut header = header.splitn(2, |&b| b == b' ');
-
-    let obj_type = str::from_utf8(header.next().unwrap())
-        .map_err(|e| format!("Invalid type spec in header of object {}: {}", id, e))?;
-    let obj_type: ObjType = obj_type.try_into()?;
-
-    let obj_size = header.next().ok_or(format!("Missing size spec in header of object {}.", id))?;
-    let obj_size = str::from_utf8(obj_size)
-        .map_err(|e| format!("Invalid size spec in header of object {}: {}", id, e))?;
-    let obj_size = usize::from_str(obj_size)?;
-
-    bytes.drain(..header_end+1);
-
-    if bytes.len() != obj_size {
-        return Err(format!("Expected ({}) and actual ({}) size of object {} don't match.", bytes.len(), obj_size, id).into());
-    }
-
-    match obj_type {
-        ObjType::Commit => parse_commit(&mut bytes),
-        ObjType::Tree   => parse_tree(&bytes),
-        ObjType::Blob   => Ok(Obj::Blob { content: bytes }),
-        _               => Err(format!("Unsupported object type {:?}", obj_type).into())
-    }
-}
-
-pub fn write(git_dir: &Path, obj_type: ObjType, content: &[u8]) -> R<Sha> {
-    let mut bytes: Vec<u8> = Vec::new();
-    bytes.extend_from_slice(obj_type.as_str().as_bytes());
-    bytes.push(b' ');
-    bytes.extend_from_slice(format!("{}", content.len()).as_bytes());
-    bytes.push(b'\0');
-    bytes.extend_from_slice(content);
-
-    let id = Sha::generate(&bytes);
-    let file = create(git_dir, &id)?;
-    zlib::deflate(&bytes, file)?;
-
-    Ok(id)
-}
-
-pub fn print(obj: &Obj) -> String {
-    fn print_commit(tree: &Sha, parent: &Option<Sha>, author: &str, committer: &str, message: &str) -> String {
-        let mut commit = String::new();
-
-        commit.push_str(&format!("tree {}\n", tree));
-        if let Some(p) = parent { commit.push_str(&format!("parent {}\n", p)); }
-        commit.push_str(&format!("author {}\n", author));
-        commit.push_str(&format!("committer {}\n", committer));
-        commit.push_str("\n");
-        commit.push_str(message);
-        commit.push_str("\n");
-
-        commit
-    }
-
-    fn print_tree(entries: &Vec<TreeEntry>) -> String {
-        entries.iter()
-            .map(|e| format!("{:06} {} {}    {}\n", e.mode, e.print_type(), e.id, e.name))
-            .collect::<Vec<_>>()
-            .concat()
-    }
-
-    match obj {
-        Obj::Blob { content }
-            => String::from_utf8_lossy(&content).to_string(),
-        Obj::Commit { tree, parent, author, committer, message }
-            => print_commit(tree, parent, author, committer, message),
-        Obj::Tree { entries }
-            => print_tree(&entries)
-    }
-}
-
-pub fn print_commit_author(user: &str, email: &str) -> R<String> {
-    Ok(format!("{} <{}> {} +0000", user, email, util::timestamp()?))
-}
+use std::{collections::HashSet, convert::{TryInto, TryFrom}, fs::{self, File}, io, iter, path::PathBuf, str::{self, FromStr}};
+use crate::{error::Error, pack, repo::Repository, util, sha::Sha, zlib};
+
+use crate::error::R;
+
+pub const DEFAULT_ABBREV: usize = 7;
+
+pub enum Obj {
+    Commit {
+        tree: Sha,
+        parents: Vec<Sha>,
+        author: String,
+        committer: String,
+        message: String
+    },
+    Tree { entries: Vec<TreeEntry> },
+    Blob { content: Vec<u8> },
+    Tag { content: Vec<u8> }
+}
+
+#[derive(Clone,Copy,Debug)]
+pub enum ObjType {
+    Commit,
+    Tree,
+    Blob,
+    Tag
+}
+
+impl ObjType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ObjType::Commit => "commit",
+            ObjType::Tree   => "tree",
+            ObjType::Blob   => "blob",
+            ObjType::Tag    => "tag",
+        }
+    }
+}
+
+#[derive(Clone,Debug)]
+pub struct TreeEntry {
+    pub mode: u32,
+    pub name: String,
+    pub id: Sha,
+}
+
+impl TreeEntry {
+    pub fn print_type(&self) -> &'static str {
+        match self.mode {
+            040000 => "tree",
+            100644 |
+            100755 |
+            120000 => "blob",
+            160000 => "commit",
+            mode   => panic!("Unsupported file mode: {}", mode)
+        }
+    }
+}
+
+impl TryFrom<&str> for ObjType {
+    type Error = String;
+    fn try_from(obj_type: &str) -> Result<Self, Self::Error> {
+        match obj_type {
+            "commit" => Ok(ObjType::Commit),
+            "tree"   => Ok(ObjType::Tree),
+            "blob"   => Ok(ObjType::Blob),
+            "tag"    => Ok(ObjType::Tag),
+            _        => Err(format!("Unkown object type {:?}.", obj_type).into())
+        }
+    }
+}
+
+pub fn path(repo: &Repository, id: &Sha) -> PathBuf {
+    let (dir, filename) = id.value().split_at(2);
+    repo.git_dir().join("objects").join(dir).join(filename)
+}
+
+pub fn create(repo: &Repository, id: &Sha) -> R<File> {
+    let path = path(repo, id);
+    fs::create_dir_all(path.parent().unwrap())?;
+    let file = fs::File::create(&path)
+        .map_err(|e| format!("Failed to create object {:?}. {}", path, e))?;
+    Ok(file)
+}
+
+pub fn open(repo: &Repository, id: &Sha) -> R<File> {
+    let path = path(repo, id);
+    let file = File::open(&path)
+        .map_err(|e| match e.kind() {
+            io::ErrorKind::NotFound => Error::ObjectNotFound(id.clone()),
+            _                       => format!("Failed to read object {:?}. {}", path, e).into()
+        })?;
+    Ok(file)
+}
+
+pub fn exists(repo: &Repository, id: &Sha) -> R<bool> {
+    Ok(path(repo, id).is_file() || pack::store::contains(repo, id)?)
+}
+
+// Returns the ids of all loose and packed objects starting with the given hex prefix.
+pub fn find_prefix(repo: &Repository, prefix: &str) -> R<Vec<Sha>> {
+    let prefix = prefix.to_lowercase();
+    let mut ids = pack::store::find_prefix(repo, &prefix)?;
+
+    // loose objects are spread over fan-out dirs named after the first two hex digits
+    let (fan_out, rest) = prefix.split_at(2.min(prefix.len()));
+    let fan_outs = match fan_out.len() {
+        2 => vec![fan_out.to_string()],
+        _ => (0..=0xf).map(|i| format!("{}{:x}", fan_out, i)).collect()
+    };
+    for fan_out in fan_outs {
+        let dir = repo.git_dir().join("objects").join(&fan_out);
+        if !dir.is_dir() { continue; }
+        for entry in dir.read_dir()? {
+            let name = util::name(&entry?);
+            if name.len() == 38 && name.starts_with(rest) {
+                ids.push(Sha::from_string(format!("{}{}", fan_out, name))?);
+            }
+        }
+    }
+
+    ids.sort_by(|a, b| a.value().cmp(b.value()));
+    ids.dedup();
+    Ok(ids)
+}
+
+// Shortens the id to the fewest characters, but at least `min_len`, that still uniquely identify
+// the object in the repository.
+pub fn abbreviate(repo: &Repository, id: &Sha, min_len: usize) -> R<String> {
+    for len in min_len.max(4)..40 {
+        let prefix = &id.value()[..len];
+        if find_prefix(repo, prefix)?.iter().all(|other| other == id) {
+            return Ok(prefix.to_string());
+        }
+    }
+    Ok(id.to_string())
+}
+
+pub fn read(repo: &Repository, id: &Sha) -> R<Obj> {
+    fn parse_commit(bytes: &[u8]) -> R<Obj> {
+        let (header, message) = match bytes.windows(2).position(|w| w == b"\n\n") {
+            Some(end) => (&bytes[..end], &bytes[end+2..]),
+            None      => (bytes, &[][..])
+        };
+        let header = str::from_utf8(header)
+            .map_err(|e| format!("Invalid data in commit header: {}", e))?;
+
+        let mut tree = None;
+        let mut parents = Vec::new();
+        let mut author = None;
+        let mut committer = None;
+
+        // continuation lines of multi-line headers like gpgsig start with a space
+        for line in header.lines().filter(|l| !l.starts_with(' ')) {
+            let (label, data) = line.split_once(' ').unwrap_or((line, ""));
+            match label {
+                "tree"      => tree = Some(Sha::from_str(data)?),
+                "parent"    => parents.push(Sha::from_str(data)?),
+                "author"    => author = Some(data.to_string()),
+                "committer" => committer = Some(data.to_string()),
+                _           => {}
+            }
+        }
+
+        let message = str::from_utf8(message)
+            .map_err(|e| format!("Invalid data in commit message: {}", e))?;
+
+        Ok(Obj::Commit {
+            tree: tree.ok_or("Missing tree in commit.")?,
+            parents,
+            author: author.ok_or("Missing author in commit.")?,
+            committer: committer.ok_or("Missing committer in commit.")?,
+            message: message.strip_suffix('\n').unwrap_or(message).to_string()
+        })
+    }
+
+    fn parse_tree(bytes: &[u8]) -> R<Obj> {
+        let entries = iterate_tree(bytes).collect::<R<Vec<_>>>()?;
+        Ok(Obj::Tree { entries })
+    }
+
+    let (obj_type, bytes) = read_raw(repo, id)?;
+
+    match obj_type {
+        ObjType::Commit => parse_commit(&bytes),
+        ObjType::Tree   => parse_tree(&bytes),
+        ObjType::Blob   => Ok(Obj::Blob { content: bytes }),
+        ObjType::Tag    => Ok(Obj::Tag { content: bytes })
+    }.map_err(|e| Error::CorruptObject(format!("Object {} is corrupt: {}", id, e)))
+}
+
+pub fn read_raw(repo: &Repository, id: &Sha) -> R<(ObjType, Vec<u8>)> {
+    if !path(repo, id).is_file() {
+        if let Some(obj) = pack::store::read(repo, id)? {
+            return Ok(obj);
+        }
+    }
+
+    let (bytes, _) = zlib::inflate(open(repo, id)?)
+        .map_err(|e| Error::CorruptObject(format!("Object {} is corrupt: {}", id, e)))?;
+    parse_loose(id, bytes)
+}
+
+pub fn parse_loose(id: &Sha, bytes: Vec<u8>) -> R<(ObjType, Vec<u8>)> {
+    parse_header(id, bytes).map_err(|e| Error::CorruptObject(e.to_string()))
+}
+
+fn parse_header(id: &Sha, mut bytes: Vec<u8>) -> R<(ObjType, Vec<u8>)> {
+    let header_end = bytes.iter().position(|&b| b == 0)
+        .ok_or(format!("Object {} has no header.", id))?;
+
+    let header = &bytes[..header_end];
+    let mut header = header.splitn(2, |&b| b == b' ');
+
+    let obj_type = str::from_utf8(header.next().unwrap())
+        .map_err(|e| format!("Invalid type spec in header of object {}: {}", id, e))?;
+    let obj_type: ObjType = obj_type.try_into()?;
+
+    let obj_size = header.next().ok_or(format!("Missing size spec in header of object {}.", id))?;
+    let obj_size = str::from_utf8(obj_size)
+        .map_err(|e| format!("Invalid size spec in header of object {}: {}", id, e))?;
+    let obj_size = usize::from_str(obj_size)?;
+
+    bytes.drain(..header_end+1);
+
+    if bytes.len() != obj_size {
+        return Err(format!("Expected ({}) and actual ({}) size of object {} don't match.", bytes.len(), obj_size, id).into());
+    }
+
+    Ok((obj_type, bytes))
+}
+
+pub fn references(obj_type: ObjType, content: &[u8]) -> R<Vec<Sha>> {
+    fn header_refs(content: &[u8], labels: &[&str]) -> R<Vec<Sha>> {
+        content.split(|&b| b == b'\n')
+            .take_while(|line| !line.is_empty())
+            .filter_map(|line| {
+                let mut parts = line.splitn(2, |&b| b == b' ');
+                let label = parts.next().unwrap();
+                let data = parts.next()?;
+                if labels.iter().any(|l| l.as_bytes() == label) { Some(data) } else { None }
+            })
+            .map(|data| Sha::from_str(&String::from_utf8_lossy(data)))
+            .collect()
+    }
+
+    match obj_type {
+        ObjType::Commit => header_refs(content, &["tree", "parent"]),
+        ObjType::Tag    => header_refs(content, &["object"]),
+        ObjType::Tree   => Ok(iterate_tree(content)
+            .collect::<R<Vec<_>>>()?
+            .into_iter()
+            .filter(|e| e.mode != 160000) // submodule commits live in another repository
+            .map(|e| e.id)
+            .collect()),
+        ObjType::Blob   => Ok(Vec::new())
+    }
+}
+
+// Walks the object graph starting at `roots` without descending into objects from `known`.
+pub fn reachable(repo: &Repository, roots: &[Sha], known: &HashSet<Sha>) -> R<Vec<Sha>> {
+    let mut pending = roots.to_vec();
+    let mut visited = HashSet::new();
+    let mut found = Vec::new();
+
+    while let Some(id) = pending.pop() {
+        if known.contains(&id) || !visited.insert(id.clone()) { continue; }
+        let (obj_type, content) = read_raw(repo, &id)?;
+        pending.extend(references(obj_type, &content)?);
+        found.push(id);
+    }
+
+    Ok(found)
+}
+
+fn iterate_tree(mut bytes: &[u8]) -> impl Iterator<Item = R<TreeEntry>> + '_ {
+    let id_len = 20;
+
+    iter::from_fn(move || {
+        let utf8_end = bytes.iter().position(|&b| b == 0)?;
+
+        let mode_name = &bytes[..utf8_end];
+        let mut mode_name = mode_name.split(|&b| b == b' ');
+
+        let mode = mode_name.next().unwrap();
+        let mode = str::from_utf8(mode);
+        if let Err(e) = mode { return Some(Err(format!("Invalid mode in tree entry: {}", e).into())) }
+        let mode = u32::from_str(mode.unwrap());
+        if let Err(e) = mode { return Some(Err(format!("Invalid mode in tree entry: {}", e).into())) }
+        let mode = mode.unwrap();
+
+        let name = mode_name.next();
+        if let None = name { return Some(Err("Missing filename in tree entry.".into())); }
+        let name = str::from_utf8(name.unwrap());
+        if let Err(e) = name { return Some(Err(format!("Invalid filename in tree entry: {}", e).into())) }
+        let name = name.unwrap().to_string();
+
+        let id = &bytes[utf8_end+1 .. utf8_end+1+id_len];
+        let id = Sha::from_bytes(id);
+        if let Err(e) = id { return Some(Err(format!("Invalid SHA in tree entry: {}", e).into())) }
+
+        bytes = &bytes[utf8_end+1+id_len ..];
+        Some(Ok(TreeEntry { id: id.unwrap(), mode, name }))
+    })
+}
+
+pub fn encode_tree(entries: &[TreeEntry]) -> Vec<u8> {
+    let mut content = Vec::new();
+    for entry in entries {
+        content.extend_from_slice(format!("{} {}\0", entry.mode, entry.name).as_bytes());
+        content.extend_from_slice(&entry.id.to_bytes());
+    }
+    content
+}
+
+pub fn write(repo: &Repository, obj_type: ObjType, content: &[u8]) -> R<Sha> {
+    let bytes = with_header(obj_type, content);
+    let id = Sha::generate(&bytes);
+    let file = create(repo, &id)?;
+    zlib::deflate(&bytes, file)?;
+
+    Ok(id)
+}
+
+// The id the object would get, without writing it.
+pub fn hash(obj_type: ObjType, content: &[u8]) -> Sha {
+    Sha::generate(&with_header(obj_type, content))
+}
+
+fn with_header(obj_type: ObjType, content: &[u8]) -> Vec<u8> {
+    let mut bytes: Vec<u8> = Vec::new();
+    bytes.extend_from_slice(obj_type.as_str().as_bytes());
+    bytes.push(b' ');
+    bytes.extend_from_slice(format!("{}", content.len()).as_bytes());
+    bytes.push(b'\0');
+    bytes.extend_from_slice(content);
+    bytes
+}
+
+pub fn print(obj: &Obj) -> String {
+    fn print_commit(tree: &Sha, parents: &[Sha], author: &str, committer: &str, message: &str) -> String {
+        let mut commit = String::new();
+
+        commit.push_str(&format!("tree {}\n", tree));
+        for p in parents { commit.push_str(&format!("parent {}\n", p)); }
+        commit.push_str(&format!("author {}\n", author));
+        commit.push_str(&format!("committer {}\n", committer));
+        commit.push_str("\n");
+        commit.push_str(message);
+        commit.push_str("\n");
+
+        commit
+    }
+
+    fn print_tree(entries: &Vec<TreeEntry>) -> String {
+        entries.iter()
+            .map(|e| format!("{:06} {} {}    {}\n", e.mode, e.print_type(), e.id, e.name))
+            .collect::<Vec<_>>()
+            .concat()
+    }
+
+    match obj {
+        Obj::Blob { content } |
+        Obj::Tag { content }
+            => String::from_utf8_lossy(&content).to_string(),
+        Obj::Commit { tree, parents, author, committer, message }
+            => print_commit(tree, parents, author, committer, message),
+        Obj::Tree { entries }
+            => print_tree(&entries)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, HashMap};
+    use crate::{pack::store, testutil};
+    use super::*;
+
+    // Two blobs whose ids share their first five hex digits.
+    fn similar_blobs() -> (Vec<u8>, Vec<u8>) {
+        let mut seen = HashMap::new();
+        let mut i = 0;
+        loop {
+            let content = i.to_string().into_bytes();
+            let id = hash(ObjType::Blob, &content);
+            if let Some(other) = seen.insert(id.value()[..5].to_string(), content.clone()) {
+                return (other, content);
+            }
+            i += 1;
+        }
+    }
+
+    #[test]
+    fn find_loose_objects_by_prefix() {
+        let (_dir, repo) = testutil::repo();
+        let (a, b) = similar_blobs();
+        let a = write(&repo, ObjType::Blob, &a).unwrap();
+        let b = write(&repo, ObjType::Blob, &b).unwrap();
+        let mut both = vec![a.clone(), b.clone()];
+        both.sort_by(|x, y| x.value().cmp(y.value()));
+
+        assert_eq!(find_prefix(&repo, &a.value()[..5]).unwrap(), both);
+        assert_eq!(find_prefix(&repo, &a.value()[..1]).unwrap(), both);
+        assert_eq!(find_prefix(&repo, &a.value().to_uppercase()).unwrap(), [a.clone()]);
+        assert!(find_prefix(&repo, "0000000").unwrap().iter().all(|id| id.value().starts_with("0000000")));
+    }
+
+    #[test]
+    fn abbreviations_are_unique() {
+        let (_dir, repo) = testutil::repo();
+        let (a, b) = similar_blobs();
+        let a = write(&repo, ObjType::Blob, &a).unwrap();
+        let b = write(&repo, ObjType::Blob, &b).unwrap();
+        let common = a.value().chars().zip(b.value().chars()).take_while(|(x, y)| x == y).count();
+
+        assert_eq!(abbreviate(&repo, &a, 4).unwrap(), a.value()[..common + 1]);
+        assert_eq!(abbreviate(&repo, &b, 4).unwrap(), b.value()[..common + 1]);
+        assert_eq!(abbreviate(&repo, &a, 12).unwrap(), a.value()[..12.max(common + 1)]);
+        assert_eq!(abbreviate(&repo, &a, 0).unwrap().len(), common + 1);
+    }
+
+    #[test]
+    fn packed_and_loose_ids_are_merged() {
+        let (_dir, repo) = testutil::repo();
+        let id = write(&repo, ObjType::Blob, b"loose").unwrap();
+        let other = Sha::from_string(format!("{}{}", &id.value()[..6], "0".repeat(34))).unwrap();
+
+        let pack_dir = repo.git_dir().join("objects/pack");
+        let entries = [(id.to_bytes(), 12), (other.to_bytes(), 34)].iter().cloned().collect::<BTreeMap<_, _>>();
+        fs::write(pack_dir.join("pack-a.idx"), store::tests::index_v2(&entries)).unwrap();
+        fs::write(pack_dir.join("pack-a.pack"), b"").unwrap();
+
+        let mut expected = vec![id.clone(), other];
+        expected.sort_by(|x, y| x.value().cmp(y.value()));
+        assert_eq!(find_prefix(&repo, &id.value()[..6]).unwrap(), expected);
+        let common = 6 + id.value()[6..].chars().take_while(|c| *c == '0').count();
+        assert_eq!(abbreviate(&repo, &id, 4).unwrap(), id.value()[..common + 1]);
+    }
+}
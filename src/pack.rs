@@ -1,347 +1,1452 @@
-pub mod http {
-    use std::{iter, str};
-    use bytes::Bytes;
-    use reqwest::{blocking::{Client, RequestBuilder, Response}, StatusCode};
-    use crate::sha::Sha;
-
-    type R<T> = std::result::Result<T, Box<dyn std::error::Error>>;
-
-    #[derive(Clone,Debug)]
-    pub struct Ref {
-        pub id: Sha,
-        pub name: String
-    }
-
-    pub fn get_advertised_refs(http: &Client, url: &str, refs_only: bool) -> R<Vec<Ref>> {
-        fn pkt_lines(bytes: Bytes) -> impl Iterator<Item = String> {
-            let mut bytes = bytes;
-            iter::from_fn(move || {
-                if bytes.len() == 0 { return None; }
-                let pkt_data = parse_pkt_line(&mut bytes)
-                    .expect("Failed to parse pkt-line.");
-                Some(pkt_data)
-            })
-        }
-
-        fn parse_ref(pkt_line: String) -> R<Ref> {
-            let _ref = pkt_line.split('\0').next().unwrap();
-            let mut ref_parts = _ref.split(' ');
-            let id = Sha::from_str(ref_parts.next().unwrap())?;
-            let name = ref_parts.next().ok_or("Failed to parse ref pkt-line.")?.to_string();
-            Ok(Ref { id, name })
-        }
-
-        let bytes = query_repo(url, "/info/refs?service=git-upload-pack", |url| http.get(url))?
-            .bytes()?;
-
-        let refs = pkt_lines(bytes)
-            .skip(2) // skip command & flush-pkt
-            .take_while(|pkt_line| pkt_line != "")
-            .map(parse_ref)
-            .filter(|r| !refs_only || r.is_err() || r.as_ref().unwrap().name != "HEAD")
-            .collect::<R<Vec<_>>>()?;
-
-        Ok(refs)
-    }
-
-    pub fn clone(http: &Client, url: &str) -> R<(Ref, Bytes)> {
-        let refs = get_advertised_refs(http, url, true)?;
-        let head = refs.iter()
-            .find(|r| r.name == "refs/heads/master")
-            .ok_or("Remote did not advertise HEAD.")?;
-        let wants = refs.iter()
-            .map(|Ref {id, name: _}| format!("0032want {}\n", id))
-            .collect::<Vec<_>>()
-            .concat();
-
-        let mut bytes = query_repo(url, "/git-upload-pack", |url| http
-            .post(url)
-            .header("Content-Type", "application/x-git-upload-pack-request")
-            .body(format!("{}00000009done\n", wants)))?
-            .bytes()?;
-
-        match parse_pkt_line(&mut bytes)?.as_str() {
-            "NAK" => Ok((head.clone(), bytes)),
-            _     => Err("Missing 'NAK'.".into())
-        }
-    }
-
-    fn query_repo<F>(repo_url: &str, resource_path: &str, request: F) -> R<Response>
-    where F: Fn(&str) -> RequestBuilder {
-        let repo_url = repo_url.trim_end_matches('/').to_string();
-
-        request(&format!("{}{}", repo_url, resource_path))
-            .send()?
-            .error_for_status()
-            .or_else(|e| match e.status() {
-                Some(StatusCode::NOT_FOUND) |
-                Some(StatusCode::FORBIDDEN) =>
-                    request(&format!("{}.git{}", repo_url, resource_path)).send(),
-                _ => Err(e)
-            })
-            .map_err(|e| format!("Request to repo failed: {}", e).into())
-    }
-
-    fn parse_pkt_line(bytes: &mut Bytes) -> R<String> {
-        let len = bytes.split_to(4);
-        let len = str::from_utf8(&len)
-            .map_err(|e| format!("Failed to read pkt-len. {}", e))?;
-        let len = usize::from_str_radix(len, 16)
-            .map_err(|e| format!("Failed to parse pkt-len. {}", e))?;
-
-        let data = match len {
-            0 => String::new(),
-            _ => str::from_utf8(&bytes.split_to(len - 4))
-                    .map_err(|e| format!("Failed to parse pkt-line data. {}", e))?
-                    .trim_end_matches('\n')
-                    .to_string()
-        };
-
-        Ok(data)
-    }
-}
-
-pub mod fmt {
-    use std::{convert::{TryInto, TryFrom}, iter, path::{Path}};
-    use bytes::{buf::Buf, Bytes};
-    use crate::{obj::{self, ObjType}, sha::Sha, zlib};
-
-    type R<T> = std::result::Result<T, Box<dyn std::error::Error>>;
-
-    #[derive(Debug)]
-    pub enum EntryType {
-        ObjCommit   = 1,
-        ObjTree     = 2,
-        ObjBlob     = 3,
-        ObjTag      = 4,
-        ObjOfsDelta = 6,
-        ObjRefDelta = 7
-    }
-
-    impl TryFrom<u8> for EntryType {
-        type Error = String;
-        fn try_from(val: u8) -> Result<Self, Self::Error> {
-            match val {
-                1 => Ok(EntryType::ObjCommit),
-                2 => Ok(EntryType::ObjTree),
-                3 => Ok(EntryType::ObjBlob),
-                4 => Ok(EntryType::ObjTag),
-                6 => Ok(EntryType::ObjOfsDelta),
-                7 => Ok(EntryType::ObjRefDelta),
-                _ => Err(format!("Unknown entry type {}.", val).into())
-            }
-        }
-    }
-
-    impl TryFrom<EntryType> for ObjType {
-        type Error = String;
-        fn try_from(entry_type: EntryType) -> Result<Self, Self::Error> {
-            match entry_type {
-                EntryType::ObjCommit   => Ok(ObjType::Commit),
-                EntryType::ObjTree     => Ok(ObjType::Tree),
-                EntryType::ObjBlob     => Ok(ObjType::Blob),
-                EntryType::ObjTag      => Ok(ObjType::Tag),
-                EntryType::ObjOfsDelta |
-                EntryType::ObjRefDelta => Err(format!("Entry type {:?} is not a proper object type.", entry_type).into())
-            }
-        }
-    }
-
-    pub struct RawObj {
-        pub obj_type: ObjType,
-        pub content: Vec<u8>
-    }
-
-    #[derive(Debug)]
-    enum Instr {
-        Copy { start: usize, end: usize },
-        Insert { data: Bytes }
-    }
-
-    pub fn unpack_objects(git_dir: &Path, pack: &mut Bytes) -> R<usize> {
-        fn has_no_cont_bit(byte: &u8) -> bool { (byte & 0b10000000) == 0 }
-        let mut objs = std::collections::HashMap::new();
-
-        loop {
-            let first = pack.first();
-            if let None = first { break; }
-
-            let obj_type: EntryType = ((first.unwrap() & 0b01110000) >> 4).try_into()?;
-            let obj_start = pack.iter().position(has_no_cont_bit).ok_or("Never ending variable sized integer.")?;
-            let _obj_props = pack.split_to(obj_start + 1);
-
-            let deflated_len = match obj_type {
-                EntryType::ObjCommit |
-                EntryType::ObjTree |
-                EntryType::ObjBlob => {
-                    let (content, deflated_len) = zlib::inflate(pack.as_ref())?;
-                    let obj = RawObj { obj_type: obj_type.try_into()?, content };
-                    let id = obj::write(&git_dir, obj.obj_type, &obj.content)?;
-                    objs.insert(id, obj);
-                    Ok(deflated_len)
-                },
-                EntryType::ObjRefDelta => {
-                    let base_id = Sha::from_bytes(&pack.split_to(20))?;
-                    let (delta, deflated_len) = zlib::inflate(pack.as_ref())?;
-
-                    match objs.get(&base_id) {
-                        Some(base) => {
-                            let content = undeltify(delta, &base.content)?;
-                            let obj = RawObj { obj_type: base.obj_type, content };
-                            let id = obj::write(&git_dir, obj.obj_type, &obj.content)?;
-                            objs.insert(id, obj);
-                        },
-                        None => return Err(format!("Found delta referencing unknown base object {}.", base_id).into())
-                    }
-
-                    Ok(deflated_len)
-                },
-                _ => Err(format!("Unsupported entry type {:?}", obj_type))
-            }?;
-
-            pack.advance(deflated_len.try_into()?);
-        }
-
-        Ok(objs.len())
-    }
-
-    pub fn undeltify(delta: Vec<u8>, base: &[u8]) -> R<Vec<u8>> {
-        let mut delta = Bytes::from(delta);
-        let source_len = parse_var_int(&mut delta)? as usize;
-        let target_len = parse_var_int(&mut delta)? as usize;
-
-        if source_len != base.len() {
-            return Err(format!("Delta source length ({}) did not match length of base data ({}).", source_len, base.len()).into());
-        }
-
-        let mut content = Vec::new();
-
-        loop {
-            if delta.len() == 0 { break; }
-
-            let instr = parse_instr(&mut delta)?;
-
-            match instr {
-                Instr::Copy { start, end } => content.extend_from_slice(&base[start..end]),
-                Instr::Insert { data }     => content.extend_from_slice(&data)
-            };
-        }
-
-        if target_len != content.len() {
-            return Err(format!("Delta target length ({}) did not match length of undeltified data ({}).", source_len, content.len()).into());
-        }
-
-        Ok(content)
-    }
-
-    fn parse_instr(bytes: &mut Bytes) -> R<Instr> {
-        let instr = bytes.first().unwrap();
-        if *instr == 0 {
-            Err("Encountered reserved delta instruction 0x0.".into())
-        } else if *instr & 0b10000000 != 0 {
-            Ok(parse_copy_instr(bytes))
-        } else {
-            Ok(parse_insert_instr(bytes))
-        }
-    }
-
-    fn parse_copy_instr(bytes: &mut Bytes) -> Instr {
-        let instr = bytes.split_to(1);
-        let instr = instr.first().unwrap();
-
-        let offset = (0..4)
-            .map(|i| *instr & (1 << i) != 0)
-            .map(|read| {
-                if read {
-                    let byte = bytes.split_to(1);
-                    *byte.first().unwrap()
-                } else {
-                    0
-                }
-            })
-            .collect::<Vec<_>>();
-        let offset = u32::from_le_bytes(offset.as_slice().try_into().unwrap()) as usize;
-
-        let len = (4..7)
-            .map(|i| *instr & (1 << i) != 0)
-            .map(|read| {
-                if read {
-                    let byte = bytes.split_to(1);
-                    *byte.first().unwrap()
-                } else {
-                    0
-                }
-            })
-            .chain(iter::once(0))
-            .collect::<Vec<_>>();
-        let len = u32::from_le_bytes(len.as_slice().try_into().unwrap()) as usize;
-
-        Instr::Copy { start: offset, end: offset + len }
-    }
-
-    fn parse_insert_instr(bytes: &mut Bytes) -> Instr {
-        let instr = bytes.split_to(1);
-        let instr = instr.first().unwrap();
-        let len = instr & 0b01111111;
-        let data = bytes.split_to(len as usize);
-        Instr::Insert { data }
-    }
-
-    fn parse_var_int(bytes: &mut Bytes) -> R<u64> {
-        fn has_no_cont_bit(byte: &u8) -> bool { (byte & 0b10000000) == 0 }
-
-        let var_int_end = bytes.iter()
-            .position(has_no_cont_bit)
-            .ok_or("Never ending variable sized integer.")?;
-
-        let var_int = bytes.split_to(var_int_end + 1).iter()
-            .map(|b| (b & 0b01111111) as u64)
-            .enumerate()
-            .map(|(i,b)| b << 7*i)
-            .fold(0, |n,b| n | b);
-
-        Ok(var_int)
-    }
-
-    pub fn parse_header(pack: &mut Bytes) -> R<u32> {
-        parse_checksum(pack)?;
-        parse_signature(pack)?;
-        parse_version(pack)?;
-        let n = parse_obj_count(pack)?;
-        Ok(n)
-    }
-
-    fn parse_checksum(pack: &mut Bytes) -> R<()> {
-        let checksum = Sha::from_bytes(&pack.split_off(pack.len() - 20))?;
-        let packsum = Sha::generate(&pack);
-
-        if packsum != checksum {
-            Err("Pack file corrupted: checksum mismatch.".into())
-        } else {
-            Ok(())
-        }
-    }
-
-    fn parse_signature(pack: &mut Bytes) -> R<()> {
-        let sig = pack.split_to(4);
-        match sig.as_ref() {
-            b"PACK" => Ok(()),
-            _       => Err("Missing signature 'PACK'.".into())
-        }
-    }
-
-    fn parse_version(pack: &mut Bytes) -> R<()> {
-        let ver = pack.split_to(4);
-        let ver = u32::from_be_bytes(ver.as_ref().try_into()?);
-        match ver {
-            2 => Ok(()),
-            _ => Err(format!("Unsupported pack version {}. Expected version 2.", ver).into())
-        }
-    }
-
-    fn parse_obj_count(pack: &mut Bytes) -> R<u32> {
-        let cnt = pack.split_to(4);
-        let cnt = u32::from_be_bytes(cnt.as_ref().try_into()?);
-        Ok(cnt)
-    }
-}
+pub mod proto {
+    use std::{io::{ErrorKind, Read}, iter, str};
+    use bytes::Bytes;
+    use crate::{error::Error, sha::Sha};
+
+    pub use crate::refs::Ref;
+
+    use crate::error::R;
+
+    // Also returns the branch HEAD points to, if the remote told us via the `symref` capability.
+    pub fn parse_advertised_refs(bytes: Bytes) -> R<(Vec<Ref>, Option<String>)> {
+        fn parse_ref(pkt_line: String) -> R<(Ref, Option<String>)> {
+            if let Some(msg) = pkt_line.strip_prefix("ERR ") {
+                return Err(Error::Protocol(format!("Remote responded with error: {}", msg)));
+            }
+            let mut pkt_line = pkt_line.split('\0');
+            let mut ref_parts = pkt_line.next().unwrap().split(' ');
+            let id = Sha::from_str(ref_parts.next().unwrap())?;
+            let name = ref_parts.next().ok_or_else(|| Error::Protocol("Failed to parse ref pkt-line.".into()))?.to_string();
+            let symref = pkt_line.next()
+                .and_then(|caps| caps.split(' ').find_map(|cap| cap.strip_prefix("symref=HEAD:")))
+                .map(String::from);
+            Ok((Ref { id, name }, symref))
+        }
+
+        let mut head = None;
+        let mut refs = Vec::new();
+
+        for pkt_line in pkt_lines(bytes).take_while(|pkt_line| !pkt_line.is_empty()) {
+            let (r, symref) = parse_ref(pkt_line)?;
+            head = head.or(symref);
+            if r.name != "capabilities^{}" { refs.push(r); } // placeholder sent by empty repos
+        }
+
+        Ok((refs, head))
+    }
+
+    // Determines the branch the remote HEAD points to. Without a symref we have to guess from
+    // the branches pointing to the same commit, just like older versions of git did. If HEAD is
+    // detached, HEAD itself is returned.
+    pub fn find_head(refs: &[Ref], symref: Option<&str>) -> R<Ref> {
+        let head = refs.iter().find(|r| r.name == "HEAD");
+
+        match (symref, head) {
+            (Some(target), _) => refs.iter()
+                .find(|r| r.name == target)
+                .cloned()
+                .ok_or_else(|| Error::Protocol(format!("Remote HEAD points to {}, which was not advertised.", target))),
+            (None, Some(head)) => Ok(refs.iter()
+                .filter(|r| r.name.starts_with("refs/heads/") && r.id == head.id)
+                .min_by_key(|r| r.name != "refs/heads/master")
+                .unwrap_or(head)
+                .clone()),
+            (None, None) => refs.iter()
+                .find(|r| r.name == "refs/heads/master")
+                .cloned()
+                .ok_or_else(|| Error::Protocol("Remote did not advertise HEAD.".into()))
+        }
+    }
+
+    pub fn without_head(mut refs: Vec<Ref>) -> Vec<Ref> {
+        refs.retain(|r| r.name != "HEAD");
+        refs
+    }
+
+    pub fn upload_request(refs: &[Ref]) -> String {
+        let wants = refs.iter()
+            .map(|Ref {id, name: _}| pkt_line(&format!("want {}\n", id)))
+            .collect::<Vec<_>>()
+            .concat();
+        format!("{}0000{}", wants, pkt_line("done\n"))
+    }
+
+    pub fn parse_upload_response(mut bytes: Bytes) -> R<Bytes> {
+        match parse_pkt_line(&mut bytes)?.as_str() {
+            "NAK" => Ok(bytes),
+            _     => Err(Error::Protocol("Missing 'NAK'.".into()))
+        }
+    }
+
+    pub fn read_pkt_lines<T>(input: &mut T) -> R<Bytes>
+    where T : Read {
+        let mut bytes = Vec::new();
+
+        loop {
+            let mut len = [0; 4];
+            input.read_exact(&mut len)
+                .map_err(|e| Error::Protocol(match e.kind() {
+                    ErrorKind::UnexpectedEof => "Remote end hung up unexpectedly.".to_string(),
+                    _ => format!("Failed to read pkt-len. {}", e)
+                }))?;
+            bytes.extend_from_slice(&len);
+
+            let len = str::from_utf8(&len)
+                .map_err(|e| Error::Protocol(format!("Failed to read pkt-len. {}", e)))?;
+            let len = usize::from_str_radix(len, 16)
+                .map_err(|e| Error::Protocol(format!("Failed to parse pkt-len. {}", e)))?;
+            if len == 0 { break; }
+            if len < 4 {
+                return Err(Error::Protocol(format!("Invalid pkt-len {}.", len)));
+            }
+
+            let mut data = vec![0; len - 4];
+            input.read_exact(&mut data)
+                .map_err(|e| Error::Protocol(format!("Failed to read pkt-line data. {}", e)))?;
+            bytes.extend(data);
+        }
+
+        Ok(Bytes::from(bytes))
+    }
+
+    pub fn pkt_lines(bytes: Bytes) -> impl Iterator<Item = String> {
+        let mut bytes = bytes;
+        iter::from_fn(move || {
+            if bytes.is_empty() { return None; }
+            let pkt_data = parse_pkt_line(&mut bytes)
+                .expect("Failed to parse pkt-line.");
+            Some(pkt_data)
+        })
+    }
+
+    pub fn pkt_line(data: &str) -> String {
+        format!("{:04x}{}", data.len() + 4, data)
+    }
+
+    pub fn parse_pkt_line(bytes: &mut Bytes) -> R<String> {
+        if bytes.len() < 4 {
+            return Err(Error::Protocol("Failed to read pkt-len. Unexpected end of data.".into()));
+        }
+        let len = bytes.split_to(4);
+        let len = str::from_utf8(&len)
+            .map_err(|e| Error::Protocol(format!("Failed to read pkt-len. {}", e)))?;
+        let len = usize::from_str_radix(len, 16)
+            .map_err(|e| Error::Protocol(format!("Failed to parse pkt-len. {}", e)))?;
+        if len != 0 && (len < 4 || len - 4 > bytes.len()) {
+            return Err(Error::Protocol(format!("Invalid pkt-len {}.", len)));
+        }
+
+        let data = match len {
+            0 => String::new(),
+            _ => str::from_utf8(&bytes.split_to(len - 4))
+                    .map_err(|e| Error::Protocol(format!("Failed to parse pkt-line data. {}", e)))?
+                    .trim_end_matches('\n')
+                    .to_string()
+        };
+
+        Ok(data)
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        const ID_A: &str = "1111111111111111111111111111111111111111";
+        const ID_B: &str = "2222222222222222222222222222222222222222";
+
+        fn r(id: &str, name: &str) -> Ref {
+            Ref { id: Sha::from_str(id).unwrap(), name: name.to_string() }
+        }
+
+        #[test]
+        fn pkt_line_prefixes_length_including_header() {
+            assert_eq!(pkt_line("done\n"), "0009done\n");
+            assert_eq!(pkt_line(""), "0004");
+        }
+
+        #[test]
+        fn parse_pkt_line_strips_newline_and_advances() {
+            let mut bytes = Bytes::from("0009done\n00000008NAK\n");
+            assert_eq!(parse_pkt_line(&mut bytes).unwrap(), "done");
+            assert_eq!(parse_pkt_line(&mut bytes).unwrap(), "");
+            assert_eq!(parse_pkt_line(&mut bytes).unwrap(), "NAK");
+            assert!(bytes.is_empty());
+        }
+
+        #[test]
+        fn parse_pkt_line_rejects_bad_lengths() {
+            assert!(parse_pkt_line(&mut Bytes::from("00")).is_err());
+            assert!(parse_pkt_line(&mut Bytes::from("zzzz")).is_err());
+            assert!(parse_pkt_line(&mut Bytes::from("0002")).is_err());
+            assert!(parse_pkt_line(&mut Bytes::from("0010short")).is_err());
+        }
+
+        #[test]
+        fn read_pkt_lines_stops_at_flush() {
+            let mut input = "0009done\n0000rest".as_bytes();
+            assert_eq!(read_pkt_lines(&mut input).unwrap(), Bytes::from("0009done\n0000"));
+            assert_eq!(input, b"rest");
+        }
+
+        #[test]
+        fn read_pkt_lines_reports_hang_up() {
+            let mut input = "0009done\n".as_bytes();
+            let err = read_pkt_lines(&mut input).unwrap_err();
+            assert!(err.to_string().contains("hung up"), "{}", err);
+        }
+
+        #[test]
+        fn read_pkt_lines_rejects_short_lengths() {
+            for len in &["0001", "0002", "0003"] {
+                let input = format!("{}0000", len);
+                match read_pkt_lines(&mut input.as_bytes()) {
+                    Err(Error::Protocol(msg)) => assert!(msg.starts_with("Invalid pkt-len"), "{}", msg),
+                    other => panic!("{:?}", other.map(|b| b.len()))
+                }
+            }
+        }
+
+        #[test]
+        fn parse_advertised_refs_reads_symref() {
+            let adv = [
+                pkt_line(&format!("{} HEAD\0multi_ack symref=HEAD:refs/heads/main agent=git/2\n", ID_A)),
+                pkt_line(&format!("{} refs/heads/main\n", ID_A)),
+                pkt_line(&format!("{} refs/tags/v1\n", ID_B)),
+                "0000".to_string()
+            ].concat();
+            let (refs, head) = parse_advertised_refs(Bytes::from(adv)).unwrap();
+            assert_eq!(refs.iter().map(|r| r.name.as_str()).collect::<Vec<_>>(), ["HEAD", "refs/heads/main", "refs/tags/v1"]);
+            assert_eq!(head.as_deref(), Some("refs/heads/main"));
+        }
+
+        #[test]
+        fn parse_advertised_refs_skips_placeholder_of_empty_repo() {
+            let adv = pkt_line(&format!("{} capabilities^{{}}\0agent=git/2\n", crate::refs::NULL_ID)) + "0000";
+            let (refs, head) = parse_advertised_refs(Bytes::from(adv)).unwrap();
+            assert!(refs.is_empty());
+            assert!(head.is_none());
+        }
+
+        #[test]
+        fn parse_advertised_refs_reports_remote_errors() {
+            let err = parse_advertised_refs(Bytes::from(pkt_line("ERR access denied\n"))).unwrap_err();
+            assert!(err.to_string().contains("access denied"));
+        }
+
+        #[test]
+        fn find_head_prefers_symref_then_master() {
+            let refs = [r(ID_A, "HEAD"), r(ID_A, "refs/heads/dev"), r(ID_A, "refs/heads/master"), r(ID_B, "refs/heads/main")];
+            assert_eq!(find_head(&refs, Some("refs/heads/main")).unwrap().name, "refs/heads/main");
+            assert_eq!(find_head(&refs, None).unwrap().name, "refs/heads/master");
+            assert!(find_head(&refs, Some("refs/heads/gone")).is_err());
+        }
+
+        #[test]
+        fn find_head_keeps_detached_head() {
+            let refs = [r(ID_A, "HEAD"), r(ID_B, "refs/heads/main")];
+            assert_eq!(find_head(&refs, None).unwrap().name, "HEAD");
+        }
+
+        #[test]
+        fn upload_request_wants_all_refs() {
+            let request = upload_request(&[r(ID_A, "refs/heads/main"), r(ID_B, "refs/tags/v1")]);
+            assert_eq!(request, format!("0032want {}\n0032want {}\n00000009done\n", ID_A, ID_B));
+        }
+    }
+}
+
+pub mod http {
+    use std::{env, fs, io::Read, rc::Rc, time::{Duration, Instant}};
+    use bytes::Bytes;
+    use reqwest::{blocking::{Client, RequestBuilder, Response}, header::{self, HeaderMap, HeaderName, HeaderValue}, Certificate, Proxy, StatusCode};
+    use crate::{config::Config, cred::{self, Credential}, error::Error};
+    use super::{dumb, proto::{self, Ref}};
+
+    use crate::error::R;
+
+    const INFO_REFS: &str = "/info/refs?service=git-upload-pack";
+
+    pub struct Session {
+        client: Client,
+        url: String,
+        cred: Credential,
+        authenticate: bool,
+        low_speed: Option<(u64, Duration)>,
+        config: Rc<Config>
+    }
+
+    pub enum Transfer {
+        Pack(Bytes),
+        Dumb
+    }
+
+    impl Session {
+        // Transport settings come from `config`, which is the config of the repository being
+        // fetched into, or only the system and global config when there is none.
+        pub fn new(url: &str, config: Rc<Config>) -> R<Session> {
+            let (cred, url) = Credential::from_url(url)?;
+            let low_speed = low_speed_limit(&config)?;
+            let client = build_client(&config, low_speed.map(|(_, time)| time))?;
+            Ok(Session { client, url, cred, authenticate: false, low_speed, config })
+        }
+
+        // Returns `None` if the resource does not exist.
+        pub fn get(&mut self, resource_path: &str) -> R<Option<Bytes>> {
+            let response = self.send(&self.url.clone(), resource_path, &|http, url| http.get(url))?;
+            match response.status() {
+                StatusCode::NOT_FOUND => Ok(None),
+                _ => {
+                    let response = response
+                        .error_for_status()
+                        .map_err(Error::Http)?;
+                    Ok(Some(self.read(response)?))
+                }
+            }
+        }
+
+        // Aborts the transfer if it stays below `http.lowSpeedLimit` bytes per second for longer
+        // than `http.lowSpeedTime` seconds.
+        pub fn read(&self, mut response: Response) -> R<Bytes> {
+            let (limit, time) = match self.low_speed {
+                Some(low_speed) => low_speed,
+                None            => return Ok(response.bytes()?)
+            };
+
+            let mut body = Vec::new();
+            let mut buf = [0; 16 * 1024];
+            let mut window_start = Instant::now();
+            let mut window_bytes = 0;
+
+            loop {
+                let n = response.read(&mut buf)
+                    .map_err(|e| format!("Failed to receive response from {}: {}", self.url, e))?;
+                if n == 0 { break; }
+                body.extend_from_slice(&buf[..n]);
+                window_bytes += n as u64;
+
+                let elapsed = window_start.elapsed();
+                if elapsed >= time {
+                    if window_bytes < limit * elapsed.as_secs() {
+                        return Err(format!("Transfer from {} was below {} bytes/sec for the last {} seconds.", self.url, limit, time.as_secs()).into());
+                    }
+                    window_start = Instant::now();
+                    window_bytes = 0;
+                }
+            }
+
+            Ok(Bytes::from(body))
+        }
+
+        // Retries with a `.git` suffix if the repository couldn't be found. The suffix is then
+        // used for all subsequent requests.
+        fn query<F>(&mut self, resource_path: &str, request: F) -> R<Response>
+        where F: Fn(&Client, &str) -> RequestBuilder {
+            let response = self.send(&self.url.clone(), resource_path, &request)?;
+
+            let response = match response.status() {
+                StatusCode::NOT_FOUND |
+                StatusCode::FORBIDDEN if !self.url.ends_with(".git") => {
+                    let url = format!("{}.git", self.url);
+                    let response = self.send(&url, resource_path, &request)?;
+                    if response.status().is_success() { self.url = url; }
+                    response
+                },
+                _ => response
+            };
+
+            response
+                .error_for_status()
+                .map_err(Error::Http)
+        }
+
+        // Requests are sent anonymously until the server challenges us. Credentials are then
+        // filled in and used for all subsequent requests.
+        fn send(&mut self, url: &str, resource_path: &str, request: &dyn Fn(&Client, &str) -> RequestBuilder) -> R<Response> {
+            let url = format!("{}{}", url, resource_path);
+            let response = self.authorize(request(&self.client, &url)).send()?;
+
+            if response.status() != StatusCode::UNAUTHORIZED || self.authenticate {
+                return Ok(response);
+            }
+
+            cred::fill(&mut self.cred, &self.config)?;
+            self.authenticate = true;
+
+            let response = self.authorize(request(&self.client, &url)).send()?;
+            if response.status() == StatusCode::UNAUTHORIZED {
+                cred::reject(&self.cred, &self.config)?;
+                Err(format!("Authentication failed for '{}'.", self.url).into())
+            } else {
+                cred::approve(&self.cred, &self.config)?;
+                Ok(response)
+            }
+        }
+
+        fn authorize(&self, request: RequestBuilder) -> RequestBuilder {
+            match &self.cred {
+                _ if !self.authenticate => request,
+                Credential { token: Some(token), .. } => request.bearer_auth(token),
+                Credential { username: Some(u), password, .. } => request.basic_auth(u, password.as_ref()),
+                _ => request
+            }
+        }
+    }
+
+    pub fn get_advertised_refs(http: &mut Session, refs_only: bool) -> R<Vec<Ref>> {
+        let (refs, _, _) = discover_refs(http)?;
+        Ok(if refs_only { proto::without_head(refs) } else { refs })
+    }
+
+    pub fn clone(http: &mut Session) -> R<(Ref, Vec<Ref>, Transfer)> {
+        let (refs, symref, smart) = discover_refs(http)?;
+        let head = proto::find_head(&refs, symref.as_deref())?;
+        let refs = proto::without_head(refs);
+
+        if !smart {
+            return Ok((head, refs, Transfer::Dumb));
+        }
+
+        let response = http.query("/git-upload-pack", |http, url| http
+            .post(url)
+            .header("Content-Type", "application/x-git-upload-pack-request")
+            .body(proto::upload_request(&refs)))?;
+        let bytes = http.read(response)?;
+
+        Ok((head, refs, Transfer::Pack(proto::parse_upload_response(bytes)?)))
+    }
+
+    // Also reports whether the server speaks the smart protocol. If it doesn't, objects have to
+    // be fetched as static files.
+    fn discover_refs(http: &mut Session) -> R<(Vec<Ref>, Option<String>, bool)> {
+        let response = http.query(INFO_REFS, |http, url| http.get(url))?;
+
+        let smart = response.headers().get(header::CONTENT_TYPE)
+            .is_some_and(|t| t == "application/x-git-upload-pack-advertisement");
+        let mut bytes = http.read(response)?;
+
+        if smart {
+            proto::parse_pkt_line(&mut bytes)?; // skip command
+            proto::parse_pkt_line(&mut bytes)?; // skip flush-pkt
+            let (refs, symref) = proto::parse_advertised_refs(bytes)?;
+            Ok((refs, symref, true))
+        } else {
+            let mut refs = dumb::parse_info_refs(&String::from_utf8_lossy(&bytes))?;
+            let (head, symref) = dumb::get_head(http, &refs)?;
+            refs.splice(0..0, head);
+            Ok((refs, symref, false))
+        }
+    }
+
+    // Settings from the environment take precedence over the configuration, except for proxies
+    // which reqwest picks up from the environment unless `http.proxy` is set.
+    fn build_client(config: &Config, low_speed_time: Option<Duration>) -> R<Client> {
+        let mut client = Client::builder();
+
+        let user_agent = match env::var("GIT_HTTP_USER_AGENT") {
+            Ok(user_agent) => user_agent,
+            Err(_)         => config.get("http.userAgent")?
+                .unwrap_or(format!("git/{}", env!("CARGO_PKG_VERSION")))
+        };
+        client = client.user_agent(user_agent);
+
+        match config.get("http.proxy")? {
+            Some(proxy) if proxy.is_empty() => client = client.no_proxy(),
+            Some(proxy) => {
+                let proxy = if proxy.contains("://") { proxy } else { format!("http://{}", proxy) };
+                client = client.proxy(Proxy::all(&proxy)
+                    .map_err(|e| format!("Invalid proxy '{}': {}", proxy, e))?);
+            },
+            None => {}
+        }
+
+        let ca_info = match env::var("GIT_SSL_CAINFO") {
+            Ok(ca_info) => Some(ca_info),
+            Err(_)      => config.get("http.sslCAInfo")?
+        };
+        if let Some(ca_info) = ca_info {
+            for cert in load_certificates(&ca_info)? {
+                client = client.add_root_certificate(cert);
+            }
+        }
+
+        let ssl_verify = match env::var("GIT_SSL_NO_VERIFY") {
+            Ok(_)  => false,
+            Err(_) => config.get_bool("http.sslVerify")?.unwrap_or(true)
+        };
+        client = client.danger_accept_invalid_certs(!ssl_verify);
+
+        client = client.default_headers(extra_headers(config.get_all("http.extraHeader")?)?);
+
+        // A read that blocks for `http.lowSpeedTime` is a stalled transfer, which `Session::read`
+        // would never get to see.
+        if let Some(time) = low_speed_time {
+            client = client.timeout(time).connect_timeout(time);
+        }
+
+        client.build().map_err(|e| format!("Failed to create HTTP client: {}", e).into())
+    }
+
+    fn load_certificates(ca_info: &str) -> R<Vec<Certificate>> {
+        let pem = fs::read_to_string(ca_info)
+            .map_err(|e| format!("Failed to read CA bundle '{}': {}", ca_info, e))?;
+        let end_marker = "-----END CERTIFICATE-----";
+
+        pem.split_inclusive(end_marker)
+            .filter(|block| block.contains(end_marker))
+            .map(|block| Certificate::from_pem(block.trim().as_bytes())
+                .map_err(|e| format!("Invalid certificate in '{}': {}", ca_info, e).into()))
+            .collect()
+    }
+
+    // An empty `http.extraHeader` clears all headers configured before it.
+    fn extra_headers(values: Vec<String>) -> R<HeaderMap> {
+        let mut headers = HeaderMap::new();
+
+        for header in values {
+            if header.is_empty() {
+                headers.clear();
+                continue;
+            }
+
+            let mut parts = header.splitn(2, ':');
+            let name = parts.next().unwrap().trim();
+            let value = parts.next().ok_or(format!("Invalid http.extraHeader '{}'.", header))?.trim();
+            headers.append(
+                HeaderName::from_bytes(name.as_bytes())
+                    .map_err(|e| format!("Invalid http.extraHeader '{}': {}", header, e))?,
+                HeaderValue::from_str(value)
+                    .map_err(|e| format!("Invalid http.extraHeader '{}': {}", header, e))?);
+        }
+
+        Ok(headers)
+    }
+
+    fn low_speed_limit(config: &Config) -> R<Option<(u64, Duration)>> {
+        let setting = |var: &str, key: &str| -> R<u64> {
+            match env::var(var) {
+                Ok(value) => value.parse().map_err(|e| format!("Invalid value '{}' for {}: {}", value, var, e).into()),
+                Err(_)    => Ok(config.get_int(key)?.unwrap_or(0).max(0) as u64)
+            }
+        };
+
+        let limit = setting("GIT_HTTP_LOW_SPEED_LIMIT", "http.lowSpeedLimit")?;
+        let time = setting("GIT_HTTP_LOW_SPEED_TIME", "http.lowSpeedTime")?;
+
+        if limit > 0 && time > 0 {
+            Ok(Some((limit, Duration::from_secs(time))))
+        } else {
+            Ok(None)
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use std::{io::Write, net::TcpListener, thread};
+        use super::*;
+
+        fn values(values: &[&str]) -> Vec<String> {
+            values.iter().map(|v| v.to_string()).collect()
+        }
+
+        #[test]
+        fn extra_headers_are_appended() {
+            let headers = extra_headers(values(&["X-A: 1", "X-A:2", "X-B: a: b"])).unwrap();
+            assert_eq!(headers.get_all("x-a").iter().collect::<Vec<_>>(), ["1", "2"]);
+            assert_eq!(headers["x-b"], "a: b");
+        }
+
+        #[test]
+        fn empty_extra_header_resets() {
+            let headers = extra_headers(values(&["X-A: 1", "", "X-B: 2"])).unwrap();
+            assert!(!headers.contains_key("x-a"));
+            assert_eq!(headers["x-b"], "2");
+        }
+
+        #[test]
+        fn extra_header_without_colon_is_invalid() {
+            assert!(extra_headers(values(&["X-A"])).is_err());
+        }
+
+        #[test]
+        fn stalled_transfer_times_out() {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let addr = listener.local_addr().unwrap();
+            let server = thread::spawn(move || {
+                let (mut conn, _) = listener.accept().unwrap();
+                let mut buf = [0; 1024];
+                let _ = conn.read(&mut buf).unwrap();
+                conn.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 100\r\n\r\nabc").unwrap();
+                thread::sleep(Duration::from_secs(3));
+            });
+
+            let config = Config::default();
+            let low_speed = Some((1, Duration::from_secs(1)));
+            let client = build_client(&config, low_speed.map(|(_, time)| time)).unwrap();
+            let mut session = Session {
+                client, url: format!("http://{}", addr), cred: Credential::default(),
+                authenticate: false, low_speed, config: Rc::new(config)
+            };
+
+            let start = Instant::now();
+            assert!(session.get("/stall").is_err());
+            assert!(start.elapsed() < Duration::from_secs(3));
+            server.join().unwrap();
+        }
+    }
+}
+
+pub mod dumb {
+    use std::{collections::HashSet, fs};
+    use bytes::Bytes;
+    use crate::{error::Error, obj, repo::Repository, sha::Sha, zlib};
+    use super::{http::Session, proto::Ref, store::Index};
+
+    use crate::error::R;
+
+    pub fn parse_info_refs(info_refs: &str) -> R<Vec<Ref>> {
+        info_refs.lines()
+            .filter(|line| !line.is_empty())
+            .map(|line| {
+                let mut parts = line.split('\t');
+                let id = Sha::from_str(parts.next().unwrap())?;
+                let name = parts.next().ok_or(format!("Failed to parse info/refs line '{}'.", line))?.to_string();
+                Ok(Ref { id, name })
+            })
+            .collect()
+    }
+
+    pub fn get_head(http: &mut Session, refs: &[Ref]) -> R<(Option<Ref>, Option<String>)> {
+        let head = match http.get("/HEAD")? {
+            Some(head) => String::from_utf8(head.to_vec())?,
+            None       => return Ok((None, None))
+        };
+        let head = head.trim_end();
+
+        match head.strip_prefix("ref: ") {
+            Some(target) => Ok((
+                refs.iter()
+                    .find(|r| r.name == target)
+                    .map(|r| Ref { id: r.id.clone(), name: "HEAD".to_string() }),
+                Some(target.to_string()))),
+            None => Ok((Some(Ref { id: Sha::from_str(head)?, name: "HEAD".to_string() }), None))
+        }
+    }
+
+    // A pack listed in `objects/info/packs` of the remote. Its index is downloaded when an object
+    // is first looked for in it and kept for the rest of the fetch.
+    struct RemotePack {
+        name: String,
+        idx: Option<(Bytes, Index)>
+    }
+
+    pub fn fetch(http: &mut Session, repo: &Repository, refs: &[Ref]) -> R<usize> {
+        let mut pending = refs.iter().map(|r| r.id.clone()).collect::<Vec<_>>();
+        let mut visited = HashSet::new();
+        let mut remote_packs = None;
+
+        while let Some(id) = pending.pop() {
+            if !visited.insert(id.clone()) { continue; }
+
+            if !obj::exists(repo, &id)? && !fetch_loose(http, repo, &id)? {
+                let packs = match remote_packs {
+                    Some(ref mut packs) => packs,
+                    None => remote_packs.get_or_insert(list_packs(http)?)
+                };
+                fetch_pack(http, repo, packs, &id)?;
+            }
+
+            let (obj_type, content) = obj::read_raw(repo, &id)?;
+            pending.extend(obj::references(obj_type, &content)?);
+        }
+
+        Ok(visited.len())
+    }
+
+    fn fetch_loose(http: &mut Session, repo: &Repository, id: &Sha) -> R<bool> {
+        let (dir, filename) = id.value().split_at(2);
+        let deflated = match http.get(&format!("/objects/{}/{}", dir, filename))? {
+            Some(bytes) => bytes,
+            None        => return Ok(false)
+        };
+
+        let (inflated, _) = zlib::inflate(deflated.as_ref())?;
+        if Sha::generate(&inflated) != *id {
+            return Err(Error::CorruptObject(format!("Object {} fetched from remote is corrupted: checksum mismatch.", id)));
+        }
+        let (obj_type, content) = obj::parse_loose(id, inflated)?;
+        obj::write(repo, obj_type, &content)?;
+
+        Ok(true)
+    }
+
+    fn list_packs(http: &mut Session) -> R<Vec<RemotePack>> {
+        let packs = http.get("/objects/info/packs")?.unwrap_or_default();
+        Ok(String::from_utf8(packs.to_vec())?
+            .lines()
+            .filter_map(|line| line.strip_prefix("P "))
+            .map(|pack| RemotePack { name: pack.trim().to_string(), idx: None })
+            .collect())
+    }
+
+    fn fetch_pack(http: &mut Session, repo: &Repository, packs: &mut Vec<RemotePack>, id: &Sha) -> R<()> {
+        for i in 0..packs.len() {
+            let idx_name = packs[i].name.trim_end_matches(".pack").to_string() + ".idx";
+            if packs[i].idx.is_none() {
+                let idx = http.get(&format!("/objects/pack/{}", idx_name))?
+                    .ok_or(format!("Remote is missing index of pack {}.", packs[i].name))?;
+                let index = Index::parse(&idx)?;
+                packs[i].idx = Some((idx, index));
+            }
+            let (idx, index) = packs[i].idx.as_ref().unwrap();
+            if index.find(id).is_none() { continue; }
+
+            let pack = &packs[i].name;
+            let data = http.get(&format!("/objects/pack/{}", pack))?
+                .ok_or(format!("Remote is missing pack {}.", pack))?;
+            verify_pack(pack, &data, idx)?;
+            let pack_dir = repo.git_dir().join("objects/pack");
+            fs::create_dir_all(&pack_dir)?;
+            fs::write(pack_dir.join(pack), data)?;
+            fs::write(pack_dir.join(idx_name), idx)?;
+            repo.reload_packs();
+
+            packs.remove(i);
+            return Ok(());
+        }
+
+        Err(format!("Object {} not found on remote.", id).into())
+    }
+
+    // A pack ends with the SHA-1 of its content, which its index repeats before its own checksum.
+    fn verify_pack(name: &str, pack: &[u8], idx: &[u8]) -> R<()> {
+        if pack.len() < 32 || idx.len() < 40 {
+            return Err(Error::BadPack(format!("Pack {} fetched from remote is truncated.", name)));
+        }
+        let (content, checksum) = pack.split_at(pack.len() - 20);
+        if Sha::generate_raw(content)[..] != *checksum {
+            return Err(Error::BadPack(format!("Pack {} fetched from remote is corrupted: checksum mismatch.", name)));
+        }
+        if idx[idx.len() - 40..idx.len() - 20] != *checksum {
+            return Err(Error::BadPack(format!("Index of pack {} fetched from remote does not match the pack.", name)));
+        }
+        Ok(())
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        const ID: &str = "1111111111111111111111111111111111111111";
+
+        fn pack_with_idx(content: &[u8]) -> (Vec<u8>, Vec<u8>) {
+            let checksum = Sha::generate_raw(content);
+            let pack = [content, &checksum[..]].concat();
+            let idx = [&b"index data"[..], &checksum[..], &[0; 20][..]].concat();
+            (pack, idx)
+        }
+
+        #[test]
+        fn parse_info_refs_reads_tab_separated_lines() {
+            let refs = parse_info_refs(&format!("{}\trefs/heads/main\n\n{}\trefs/tags/v1\n", ID, ID)).unwrap();
+            assert_eq!(refs.iter().map(|r| r.name.as_str()).collect::<Vec<_>>(), ["refs/heads/main", "refs/tags/v1"]);
+            assert!(parse_info_refs(ID).is_err());
+            assert!(parse_info_refs("nothex\trefs/heads/main").is_err());
+        }
+
+        #[test]
+        fn verify_pack_accepts_intact_pack() {
+            let (pack, idx) = pack_with_idx(b"PACK\0\0\0\x02\0\0\0\0");
+            assert!(verify_pack("p.pack", &pack, &idx).is_ok());
+        }
+
+        #[test]
+        fn verify_pack_rejects_corrupted_pack() {
+            let (mut pack, idx) = pack_with_idx(b"PACK\0\0\0\x02\0\0\0\0");
+            pack[5] ^= 1;
+            assert!(verify_pack("p.pack", &pack, &idx).is_err());
+            assert!(verify_pack("p.pack", &pack[..10], &idx).is_err());
+        }
+
+        #[test]
+        fn verify_pack_rejects_index_of_other_pack() {
+            let (pack, _) = pack_with_idx(b"PACK\0\0\0\x02\0\0\0\0");
+            let (_, other_idx) = pack_with_idx(b"PACK\0\0\0\x02\0\0\0\x01");
+            assert!(verify_pack("p.pack", &pack, &other_idx).is_err());
+        }
+    }
+
+}
+
+pub mod git {
+    use std::{io::{Read, Write}, net::TcpStream};
+    use bytes::Bytes;
+    use super::proto::{self, Ref};
+
+    use crate::error::R;
+
+    const DEFAULT_PORT: u16 = 9418;
+
+    pub fn is_git_url(url: &str) -> bool {
+        url.starts_with("git://")
+    }
+
+    pub fn get_advertised_refs(url: &str, refs_only: bool) -> R<Vec<Ref>> {
+        let mut conn = connect(url)?;
+        let (refs, _) = proto::parse_advertised_refs(proto::read_pkt_lines(&mut conn)?)?;
+        conn.write_all(b"0000")?; // tell daemon we don't want anything
+        Ok(if refs_only { proto::without_head(refs) } else { refs })
+    }
+
+    pub fn clone(url: &str) -> R<(Ref, Vec<Ref>, Bytes)> {
+        let mut conn = connect(url)?;
+        let (refs, symref) = proto::parse_advertised_refs(proto::read_pkt_lines(&mut conn)?)?;
+        let head = proto::find_head(&refs, symref.as_deref())?;
+        let refs = proto::without_head(refs);
+
+        conn.write_all(proto::upload_request(&refs).as_bytes())?;
+        let mut bytes = Vec::new();
+        conn.read_to_end(&mut bytes)
+            .map_err(|e| format!("Failed to receive pack from {}: {}", url, e))?;
+
+        Ok((head, refs, proto::parse_upload_response(Bytes::from(bytes))?))
+    }
+
+    fn connect(url: &str) -> R<TcpStream> {
+        let (host, port, path) = parse_url(url)?;
+
+        let mut conn = TcpStream::connect((host.as_str(), port))
+            .map_err(|e| format!("Failed to connect to {}:{}: {}", host, port, e))?;
+
+        let host = match port {
+            DEFAULT_PORT => host,
+            _            => format!("{}:{}", host, port)
+        };
+        let request = format!("git-upload-pack {}\0host={}\0", path, host);
+        conn.write_all(proto::pkt_line(&request).as_bytes())?;
+
+        Ok(conn)
+    }
+
+    fn parse_url(url: &str) -> R<(String, u16, String)> {
+        let rest = url.strip_prefix("git://")
+            .ok_or(format!("URL '{}' does not use the git:// scheme.", url))?;
+        let path_start = rest.find('/')
+            .ok_or(format!("URL '{}' is missing a repository path.", url))?;
+        let (authority, path) = rest.split_at(path_start);
+
+        // IPv6 addresses are put in brackets, as they contain colons themselves
+        let (host, port) = match authority.strip_prefix('[') {
+            Some(rest) => {
+                let end = rest.find(']').ok_or(format!("Missing ']' after IPv6 address in URL '{}'.", url))?;
+                (&rest[..end], &rest[end+1..])
+            },
+            None => match authority.rfind(':') {
+                Some(i) => (&authority[..i], &authority[i..]),
+                None    => (authority, "")
+            }
+        };
+        let port = match port {
+            "" => DEFAULT_PORT,
+            _  => port.strip_prefix(':')
+                .ok_or(format!("Invalid port in URL '{}'.", url))?
+                .parse::<u16>()
+                .map_err(|e| format!("Invalid port in URL '{}': {}", url, e))?
+        };
+
+        Ok((host.to_string(), port, path.to_string()))
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn parse_url_defaults_port() {
+            assert_eq!(parse_url("git://example.com/repo.git").unwrap(), ("example.com".to_string(), DEFAULT_PORT, "/repo.git".to_string()));
+            assert_eq!(parse_url("git://localhost:1234/a/b").unwrap(), ("localhost".to_string(), 1234, "/a/b".to_string()));
+        }
+
+        #[test]
+        fn parse_url_with_ipv6_host() {
+            assert_eq!(parse_url("git://[::1]:9419/x").unwrap(), ("::1".to_string(), 9419, "/x".to_string()));
+            assert_eq!(parse_url("git://[fe80::1]/x").unwrap(), ("fe80::1".to_string(), DEFAULT_PORT, "/x".to_string()));
+            assert!(parse_url("git://[::1/x").is_err());
+            assert!(parse_url("git://[::1]9419/x").is_err());
+        }
+
+        #[test]
+        fn parse_url_rejects_invalid_urls() {
+            assert!(parse_url("http://example.com/repo").is_err());
+            assert!(parse_url("git://example.com").is_err());
+            assert!(parse_url("git://example.com:port/repo").is_err());
+        }
+    }
+}
+
+pub mod fmt {
+    use std::{convert::{TryInto, TryFrom}, iter};
+    use bytes::{buf::Buf, Bytes};
+    use crate::{error::Error, obj::{self, ObjType}, repo::Repository, sha::Sha, zlib};
+
+    use crate::error::R;
+
+    #[derive(Debug)]
+    pub enum EntryType {
+        ObjCommit   = 1,
+        ObjTree     = 2,
+        ObjBlob     = 3,
+        ObjTag      = 4,
+        ObjOfsDelta = 6,
+        ObjRefDelta = 7
+    }
+
+    impl TryFrom<u8> for EntryType {
+        type Error = crate::error::Error;
+        fn try_from(val: u8) -> Result<Self, Self::Error> {
+            match val {
+                1 => Ok(EntryType::ObjCommit),
+                2 => Ok(EntryType::ObjTree),
+                3 => Ok(EntryType::ObjBlob),
+                4 => Ok(EntryType::ObjTag),
+                6 => Ok(EntryType::ObjOfsDelta),
+                7 => Ok(EntryType::ObjRefDelta),
+                _ => Err(Error::BadPack(format!("Unknown entry type {}.", val)))
+            }
+        }
+    }
+
+    impl TryFrom<EntryType> for ObjType {
+        type Error = crate::error::Error;
+        fn try_from(entry_type: EntryType) -> Result<Self, Self::Error> {
+            match entry_type {
+                EntryType::ObjCommit   => Ok(ObjType::Commit),
+                EntryType::ObjTree     => Ok(ObjType::Tree),
+                EntryType::ObjBlob     => Ok(ObjType::Blob),
+                EntryType::ObjTag      => Ok(ObjType::Tag),
+                EntryType::ObjOfsDelta |
+                EntryType::ObjRefDelta => Err(Error::BadPack(format!("Entry type {:?} is not a proper object type.", entry_type)))
+            }
+        }
+    }
+
+    pub struct RawObj {
+        pub obj_type: ObjType,
+        pub content: Vec<u8>
+    }
+
+    #[derive(Debug)]
+    enum Instr {
+        Copy { start: usize, end: usize },
+        Insert { data: Bytes }
+    }
+
+    pub fn unpack_objects(repo: &Repository, pack: &mut Bytes) -> R<usize> {
+        fn has_no_cont_bit(byte: &u8) -> bool { (byte & 0b10000000) == 0 }
+        let mut objs = std::collections::HashMap::new();
+
+        loop {
+            let first = pack.first();
+            if let None = first { break; }
+
+            let obj_type: EntryType = ((first.unwrap() & 0b01110000) >> 4).try_into()?;
+            let obj_start = pack.iter().position(has_no_cont_bit).ok_or_else(|| Error::BadPack("Never ending variable sized integer.".into()))?;
+            let _obj_props = pack.split_to(obj_start + 1);
+
+            let deflated_len = match obj_type {
+                EntryType::ObjCommit |
+                EntryType::ObjTree |
+                EntryType::ObjBlob |
+                EntryType::ObjTag => {
+                    let (content, deflated_len) = zlib::inflate(pack.as_ref())?;
+                    let obj = RawObj { obj_type: obj_type.try_into()?, content };
+                    let id = obj::write(repo, obj.obj_type, &obj.content)?;
+                    objs.insert(id, obj);
+                    Ok(deflated_len)
+                },
+                EntryType::ObjRefDelta => {
+                    let base_id = Sha::from_bytes(&pack.split_to(20))?;
+                    let (delta, deflated_len) = zlib::inflate(pack.as_ref())?;
+
+                    // thin packs may reference bases that are only present in the repository
+                    let base = match objs.get(&base_id) {
+                        Some(base) => RawObj { obj_type: base.obj_type, content: base.content.clone() },
+                        None if obj::exists(repo, &base_id)? => {
+                            let (obj_type, content) = obj::read_raw(repo, &base_id)?;
+                            RawObj { obj_type, content }
+                        },
+                        None => return Err(Error::BadPack(format!("Found delta referencing unknown base object {}.", base_id)))
+                    };
+
+                    let content = undeltify(delta, &base.content)?;
+                    let obj = RawObj { obj_type: base.obj_type, content };
+                    let id = obj::write(repo, obj.obj_type, &obj.content)?;
+                    objs.insert(id, obj);
+
+                    Ok(deflated_len)
+                },
+                _ => Err(Error::BadPack(format!("Unsupported entry type {:?}", obj_type)))
+            }?;
+
+            pack.advance(deflated_len.try_into()?);
+        }
+
+        Ok(objs.len())
+    }
+
+    // Objects are stored undeltified.
+    pub fn write_pack(repo: &Repository, ids: &[Sha]) -> R<Vec<u8>> {
+        let mut pack = b"PACK".to_vec();
+        pack.extend_from_slice(&2u32.to_be_bytes());
+        pack.extend_from_slice(&(ids.len() as u32).to_be_bytes());
+
+        for id in ids {
+            let (obj_type, content) = obj::read_raw(repo, id)?;
+            let entry_type = match obj_type {
+                ObjType::Commit => EntryType::ObjCommit,
+                ObjType::Tree   => EntryType::ObjTree,
+                ObjType::Blob   => EntryType::ObjBlob,
+                ObjType::Tag    => EntryType::ObjTag
+            };
+
+            let mut size = content.len();
+            let mut byte = ((entry_type as u8) << 4) | (size & 0b1111) as u8;
+            size >>= 4;
+            while size != 0 {
+                pack.push(byte | 0b10000000);
+                byte = (size & 0b01111111) as u8;
+                size >>= 7;
+            }
+            pack.push(byte);
+
+            zlib::deflate(&content, &mut pack)?;
+        }
+
+        let checksum = Sha::generate_raw(&pack);
+        pack.extend_from_slice(&checksum);
+
+        Ok(pack)
+    }
+
+    pub fn undeltify(delta: Vec<u8>, base: &[u8]) -> R<Vec<u8>> {
+        let mut delta = Bytes::from(delta);
+        let source_len = parse_var_int(&mut delta)? as usize;
+        let target_len = parse_var_int(&mut delta)? as usize;
+
+        if source_len != base.len() {
+            return Err(Error::BadPack(format!("Delta source length ({}) did not match length of base data ({}).", source_len, base.len())));
+        }
+
+        let mut content = Vec::new();
+
+        loop {
+            if delta.len() == 0 { break; }
+
+            let instr = parse_instr(&mut delta)?;
+
+            match instr {
+                Instr::Copy { start, end } => content.extend_from_slice(&base[start..end]),
+                Instr::Insert { data }     => content.extend_from_slice(&data)
+            };
+        }
+
+        if target_len != content.len() {
+            return Err(Error::BadPack(format!("Delta target length ({}) did not match length of undeltified data ({}).", source_len, content.len())));
+        }
+
+        Ok(content)
+    }
+
+    fn parse_instr(bytes: &mut Bytes) -> R<Instr> {
+        let instr = bytes.first().unwrap();
+        if *instr == 0 {
+            Err(Error::BadPack("Encountered reserved delta instruction 0x0.".into()))
+        } else if *instr & 0b10000000 != 0 {
+            Ok(parse_copy_instr(bytes))
+        } else {
+            Ok(parse_insert_instr(bytes))
+        }
+    }
+
+    fn parse_copy_instr(bytes: &mut Bytes) -> Instr {
+        let instr = bytes.split_to(1);
+        let instr = instr.first().unwrap();
+
+        let offset = (0..4)
+            .map(|i| *instr & (1 << i) != 0)
+            .map(|read| {
+                if read {
+                    let byte = bytes.split_to(1);
+                    *byte.first().unwrap()
+                } else {
+                    0
+                }
+            })
+            .collect::<Vec<_>>();
+        let offset = u32::from_le_bytes(offset.as_slice().try_into().unwrap()) as usize;
+
+        let len = (4..7)
+            .map(|i| *instr & (1 << i) != 0)
+            .map(|read| {
+                if read {
+                    let byte = bytes.split_to(1);
+                    *byte.first().unwrap()
+                } else {
+                    0
+                }
+            })
+            .chain(iter::once(0))
+            .collect::<Vec<_>>();
+        let len = match u32::from_le_bytes(len.as_slice().try_into().unwrap()) as usize {
+            0   => 0x10000,
+            len => len
+        };
+
+        Instr::Copy { start: offset, end: offset + len }
+    }
+
+    fn parse_insert_instr(bytes: &mut Bytes) -> Instr {
+        let instr = bytes.split_to(1);
+        let instr = instr.first().unwrap();
+        let len = instr & 0b01111111;
+        let data = bytes.split_to(len as usize);
+        Instr::Insert { data }
+    }
+
+    fn parse_var_int(bytes: &mut Bytes) -> R<u64> {
+        fn has_no_cont_bit(byte: &u8) -> bool { (byte & 0b10000000) == 0 }
+
+        let var_int_end = bytes.iter()
+            .position(has_no_cont_bit)
+            .ok_or_else(|| Error::BadPack("Never ending variable sized integer.".into()))?;
+
+        let var_int = bytes.split_to(var_int_end + 1).iter()
+            .map(|b| (b & 0b01111111) as u64)
+            .enumerate()
+            .map(|(i,b)| b << 7*i)
+            .fold(0, |n,b| n | b);
+
+        Ok(var_int)
+    }
+
+    pub fn parse_header(pack: &mut Bytes) -> R<u32> {
+        parse_checksum(pack)?;
+        parse_signature(pack)?;
+        parse_version(pack)?;
+        let n = parse_obj_count(pack)?;
+        Ok(n)
+    }
+
+    fn parse_checksum(pack: &mut Bytes) -> R<()> {
+        let checksum = Sha::from_bytes(&pack.split_off(pack.len() - 20))?;
+        let packsum = Sha::generate(&pack);
+
+        if packsum != checksum {
+            Err(Error::BadPack("Pack file corrupted: checksum mismatch.".into()))
+        } else {
+            Ok(())
+        }
+    }
+
+    fn parse_signature(pack: &mut Bytes) -> R<()> {
+        let sig = pack.split_to(4);
+        match sig.as_ref() {
+            b"PACK" => Ok(()),
+            _       => Err(Error::BadPack("Missing signature 'PACK'.".into()))
+        }
+    }
+
+    fn parse_version(pack: &mut Bytes) -> R<()> {
+        let ver = pack.split_to(4);
+        let ver = u32::from_be_bytes(ver.as_ref().try_into()?);
+        match ver {
+            2 => Ok(()),
+            _ => Err(Error::BadPack(format!("Unsupported pack version {}. Expected version 2.", ver)))
+        }
+    }
+
+    fn parse_obj_count(pack: &mut Bytes) -> R<u32> {
+        let cnt = pack.split_to(4);
+        let cnt = u32::from_be_bytes(cnt.as_ref().try_into()?);
+        Ok(cnt)
+    }
+}
+
+pub mod store {
+    use std::{convert::TryInto, fs::{self, File}, io::{BufReader, Read, Seek, SeekFrom}, path::{Path, PathBuf}};
+    use crate::{error::Error, obj::{self, ObjType}, repo::Repository, sha::Sha, util, zlib};
+    use super::fmt::{self, EntryType};
+
+    use crate::error::R;
+
+    pub struct Index {
+        ids: Vec<[u8; 20]>,
+        offsets: Vec<u64>
+    }
+
+    impl Index {
+        pub fn parse(bytes: &[u8]) -> R<Index> {
+            fn be_u32(bytes: &[u8], at: usize) -> R<u32> {
+                let word = bytes.get(at..at+4).ok_or_else(|| Error::BadPack("Pack index truncated.".into()))?;
+                Ok(u32::from_be_bytes(word.try_into()?))
+            }
+
+            fn be_u64(bytes: &[u8], at: usize) -> R<u64> {
+                let word = bytes.get(at..at+8).ok_or_else(|| Error::BadPack("Pack index truncated.".into()))?;
+                Ok(u64::from_be_bytes(word.try_into()?))
+            }
+
+            fn id(bytes: &[u8], at: usize) -> R<[u8; 20]> {
+                let id = bytes.get(at..at+20).ok_or_else(|| Error::BadPack("Pack index truncated.".into()))?;
+                Ok(id.try_into()?)
+            }
+
+            if bytes.starts_with(b"\xfftOc") {
+                let ver = be_u32(bytes, 4)?;
+                if ver != 2 {
+                    return Err(Error::BadPack(format!("Unsupported pack index version {}. Expected version 2.", ver)));
+                }
+
+                let fanout = 8;
+                let n = be_u32(bytes, fanout + 255*4)? as usize;
+                let ids_start = fanout + 256*4;
+                let offsets_start = ids_start + n*20 + n*4; // skip CRC32 table
+                let large_offsets_start = offsets_start + n*4;
+
+                let ids = (0..n).map(|i| id(bytes, ids_start + i*20)).collect::<R<Vec<_>>>()?;
+                let offsets = (0..n)
+                    .map(|i| {
+                        let offset = be_u32(bytes, offsets_start + i*4)?;
+                        if offset & 0x80000000 == 0 {
+                            Ok(offset as u64)
+                        } else {
+                            be_u64(bytes, large_offsets_start + (offset & 0x7fffffff) as usize * 8)
+                        }
+                    })
+                    .collect::<R<Vec<_>>>()?;
+
+                Ok(Index { ids, offsets })
+            } else {
+                let n = be_u32(bytes, 255*4)? as usize;
+                let entries_start = 256*4;
+
+                let ids = (0..n).map(|i| id(bytes, entries_start + i*24 + 4)).collect::<R<Vec<_>>>()?;
+                let offsets = (0..n)
+                    .map(|i| Ok(be_u32(bytes, entries_start + i*24)? as u64))
+                    .collect::<R<Vec<_>>>()?;
+
+                Ok(Index { ids, offsets })
+            }
+        }
+
+        pub fn find(&self, id: &Sha) -> Option<u64> {
+            self.ids.binary_search(&id.to_bytes()).ok().map(|i| self.offsets[i])
+        }
+
+        // Returns all ids starting with the given lowercase hex prefix. Ids are sorted, so they
+        // form a contiguous run starting at the first id not less than the prefix.
+        pub fn find_prefix(&self, prefix: &str) -> R<Vec<Sha>> {
+            let start = self.ids.partition_point(|id| util::print_hex(id).as_str() < prefix);
+            self.ids[start..].iter()
+                .map(|id| util::print_hex(id))
+                .take_while(|id| id.starts_with(prefix))
+                .map(Sha::from_string)
+                .collect()
+        }
+    }
+
+    // A pack file together with its parsed index.
+    pub struct Pack {
+        pub path: PathBuf,
+        pub index: Index
+    }
+
+    pub fn load(git_dir: &Path) -> R<Vec<Pack>> {
+        list(git_dir)?.into_iter()
+            .map(|idx_path| Ok(Pack { path: idx_path.with_extension("pack"), index: load_index(&idx_path)? }))
+            .collect()
+    }
+
+    pub fn find_prefix(repo: &Repository, prefix: &str) -> R<Vec<Sha>> {
+        let mut ids = Vec::new();
+        for pack in repo.packs()?.iter() {
+            ids.extend(pack.index.find_prefix(prefix)?);
+        }
+        Ok(ids)
+    }
+
+    pub fn contains(repo: &Repository, id: &Sha) -> R<bool> {
+        Ok(repo.packs()?.iter().any(|pack| pack.index.find(id).is_some()))
+    }
+
+    pub fn read(repo: &Repository, id: &Sha) -> R<Option<(ObjType, Vec<u8>)>> {
+        for pack in repo.packs()?.iter() {
+            if let Some(offset) = pack.index.find(id) {
+                let mut file = File::open(&pack.path)
+                    .map_err(|e| format!("Failed to open pack {:?}. {}", pack.path, e))?;
+                return read_entry(repo, &mut file, offset).map(Some);
+            }
+        }
+        Ok(None)
+    }
+
+    pub fn list(git_dir: &Path) -> R<Vec<PathBuf>> {
+        let pack_dir = git_dir.join("objects/pack");
+        if !pack_dir.is_dir() { return Ok(Vec::new()); }
+
+        let mut idx_paths = pack_dir.read_dir()?
+            .map(|e| e.map(|e| e.path()))
+            .collect::<Result<Vec<_>, _>>()?;
+        idx_paths.retain(|p| p.extension().is_some_and(|ext| ext == "idx") && p.with_extension("pack").is_file());
+        idx_paths.sort();
+
+        Ok(idx_paths)
+    }
+
+    pub fn load_index(idx_path: &Path) -> R<Index> {
+        let bytes = fs::read(idx_path)
+            .map_err(|e| format!("Failed to read pack index {:?}. {}", idx_path, e))?;
+        Index::parse(&bytes)
+            .map_err(|e| Error::BadPack(format!("Failed to parse pack index {:?}. {}", idx_path, e)))
+    }
+
+    fn read_entry(repo: &Repository, pack: &mut File, offset: u64) -> R<(ObjType, Vec<u8>)> {
+        fn next_byte<T: Read>(input: &mut T) -> R<u8> {
+            let mut byte = [0];
+            input.read_exact(&mut byte)?;
+            Ok(byte[0])
+        }
+
+        pack.seek(SeekFrom::Start(offset))?;
+        let mut input = BufReader::new(&mut *pack);
+
+        let mut byte = next_byte(&mut input)?;
+        let entry_type: EntryType = ((byte & 0b01110000) >> 4).try_into()?;
+        while byte & 0b10000000 != 0 { byte = next_byte(&mut input)?; } // size is implied by zlib stream
+
+        match entry_type {
+            EntryType::ObjOfsDelta => {
+                let mut byte = next_byte(&mut input)?;
+                let mut base_dist = (byte & 0b01111111) as u64;
+                while byte & 0b10000000 != 0 {
+                    byte = next_byte(&mut input)?;
+                    base_dist = ((base_dist + 1) << 7) | (byte & 0b01111111) as u64;
+                }
+                let (delta, _) = zlib::inflate(&mut input)?;
+                drop(input);
+
+                let base_offset = offset.checked_sub(base_dist)
+                    .ok_or_else(|| Error::BadPack(format!("Delta at offset {} points before start of pack.", offset)))?;
+                let (obj_type, base) = read_entry(repo, pack, base_offset)?;
+                Ok((obj_type, fmt::undeltify(delta, &base)?))
+            },
+            EntryType::ObjRefDelta => {
+                let mut base_id = [0; 20];
+                input.read_exact(&mut base_id)?;
+                let (delta, _) = zlib::inflate(&mut input)?;
+
+                let (obj_type, base) = obj::read_raw(repo, &Sha::from_bytes(&base_id)?)?;
+                Ok((obj_type, fmt::undeltify(delta, &base)?))
+            },
+            _ => {
+                let (content, _) = zlib::inflate(&mut input)?;
+                Ok((entry_type.try_into()?, content))
+            }
+        }
+    }
+
+    #[cfg(test)]
+    pub(crate) mod tests {
+        use std::collections::BTreeMap;
+        use crate::testutil;
+        use super::*;
+
+        // A version 2 index for the given ids and offsets, without a valid checksum.
+        pub(crate) fn index_v2(entries: &BTreeMap<[u8; 20], u64>) -> Vec<u8> {
+            let mut idx = b"\xfftOc\0\0\0\x02".to_vec();
+            for byte in 0..=255u8 {
+                idx.extend(&(entries.keys().filter(|id| id[0] <= byte).count() as u32).to_be_bytes());
+            }
+            for id in entries.keys() { idx.extend(id); }
+            for _ in entries { idx.extend(&[0; 4]); }
+            let mut large = Vec::new();
+            for offset in entries.values() {
+                if *offset < 0x80000000 {
+                    idx.extend(&(*offset as u32).to_be_bytes());
+                } else {
+                    idx.extend(&(0x80000000 | large.len() as u32).to_be_bytes());
+                    large.push(*offset);
+                }
+            }
+            for offset in large { idx.extend(&offset.to_be_bytes()); }
+            idx.extend(&[0; 40]);
+            idx
+        }
+
+        fn id(hex: &str) -> [u8; 20] {
+            Sha::from_str(hex).unwrap().to_bytes()
+        }
+
+        fn entries() -> BTreeMap<[u8; 20], u64> {
+            [("12aa000000000000000000000000000000000000", 12),
+             ("12ab000000000000000000000000000000000000", 0x1_0000_0000),
+             ("ff00000000000000000000000000000000000000", 34)]
+                .iter().map(|(hex, offset)| (id(hex), *offset)).collect()
+        }
+
+        #[test]
+        fn parse_v2_index() {
+            let index = Index::parse(&index_v2(&entries())).unwrap();
+            for (id, offset) in entries() {
+                assert_eq!(index.find(&Sha::from_bytes(&id).unwrap()), Some(offset));
+            }
+            assert_eq!(index.find(&Sha::from_str(&"1".repeat(40)).unwrap()), None);
+        }
+
+        #[test]
+        fn parse_v1_index() {
+            let entries = entries().into_iter().filter(|(_, offset)| *offset < 0x80000000).collect::<BTreeMap<_, _>>();
+            let mut idx = Vec::new();
+            for byte in 0..=255u8 {
+                idx.extend(&(entries.keys().filter(|id| id[0] <= byte).count() as u32).to_be_bytes());
+            }
+            for (id, offset) in &entries {
+                idx.extend(&(*offset as u32).to_be_bytes());
+                idx.extend(id);
+            }
+
+            let index = Index::parse(&idx).unwrap();
+            assert_eq!(index.find(&Sha::from_str("ff00000000000000000000000000000000000000").unwrap()), Some(34));
+        }
+
+        #[test]
+        fn parse_invalid_index() {
+            let mut idx = index_v2(&entries());
+            assert!(Index::parse(&idx[..idx.len() - 60]).is_err());
+            idx[7] = 3;
+            assert!(Index::parse(&idx).is_err());
+        }
+
+        #[test]
+        fn find_prefix_in_index() {
+            let index = Index::parse(&index_v2(&entries())).unwrap();
+            let found = |prefix| index.find_prefix(prefix).unwrap().iter().map(|id| id.to_string()).collect::<Vec<_>>();
+            assert_eq!(found("12a"), ["12aa000000000000000000000000000000000000", "12ab000000000000000000000000000000000000"]);
+            assert_eq!(found("12ab"), ["12ab000000000000000000000000000000000000"]);
+            assert!(found("13").is_empty());
+            assert_eq!(found("").len(), 3);
+        }
+
+        #[test]
+        fn packs_need_their_pack_file() {
+            let (_dir, repo) = testutil::repo();
+            let pack_dir = repo.git_dir().join("objects/pack");
+            fs::write(pack_dir.join("pack-a.idx"), index_v2(&entries())).unwrap();
+            assert!(list(repo.git_dir()).unwrap().is_empty());
+
+            fs::write(pack_dir.join("pack-a.pack"), b"").unwrap();
+            assert_eq!(list(repo.git_dir()).unwrap(), [pack_dir.join("pack-a.idx")]);
+            assert_eq!(find_prefix(&repo, "ff").unwrap().len(), 1);
+            assert!(contains(&repo, &Sha::from_str("12aa000000000000000000000000000000000000").unwrap()).unwrap());
+        }
+    }
+}
@@ -0,0 +1,309 @@
+use crate::{attr::{Attributes, State}, glob, repo::Repository, wtree};
+
+use crate::error::R;
+
+// How a pathspec matched a path. A pathspec naming a dir matches the files below it, which
+// commands that delete files only accept when told to work recursively.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Match {
+    Exact,
+    Dir,
+    Wildcard
+}
+
+#[derive(Debug)]
+struct Item {
+    original: String,
+    pattern: String,
+    nowildcard_len: usize,
+    glob: bool,
+    icase: bool,
+    exclude: bool,
+    attrs: Vec<(String, State)>
+}
+
+impl Item {
+    // Parses the long form `:(magic,...)pattern` and the short form `:<magic chars>pattern`,
+    // where `/` stands for `top` and `!` or `^` for `exclude`.
+    fn parse(arg: &str, prefix: &str) -> R<Item> {
+        let (mut top, mut literal, mut glob, mut icase, mut exclude) = (false, false, false, false, false);
+        let mut attrs = Vec::new();
+        let pattern = if let Some(rest) = arg.strip_prefix(":(") {
+            let (magic, rest) = rest.split_once(')')
+                .ok_or_else(|| format!("Missing ')' at the end of pathspec magic in '{}'.", arg))?;
+            for word in magic.split(',') {
+                match word {
+                    "top"     => top = true,
+                    "literal" => literal = true,
+                    "glob"    => glob = true,
+                    "icase"   => icase = true,
+                    "exclude" => exclude = true,
+                    _ => match word.strip_prefix("attr:") {
+                        Some(list) => attrs = list.split_whitespace().map(State::parse).collect(),
+                        None       => return Err(format!("Invalid pathspec magic '{}' in '{}'.", word, arg).into())
+                    }
+                }
+            }
+            rest
+        } else if let Some(rest) = arg.strip_prefix(':') {
+            let end = rest.find(|c| !matches!(c, '/' | '!' | '^')).unwrap_or(rest.len());
+            top = rest[..end].contains('/');
+            exclude = rest[..end].contains(['!', '^']);
+            rest[end..].strip_prefix(':').unwrap_or(&rest[end..])
+        } else {
+            arg
+        };
+        if literal && glob {
+            return Err(format!("'literal' and 'glob' magic are incompatible in '{}'.", arg).into());
+        }
+
+        let pattern = normalize(if top { "" } else { prefix }, pattern)
+            .ok_or_else(|| format!("'{}' is outside repository.", arg))?;
+        let pattern = if icase { pattern.to_lowercase() } else { pattern };
+        let nowildcard_len = match pattern.find(['*', '?', '[', '\\']) {
+            Some(pos) if !literal => pos,
+            _                     => pattern.len()
+        };
+        Ok(Item { original: arg.to_string(), pattern, nowildcard_len, glob, icase, exclude, attrs })
+    }
+
+    fn matches(&self, path: &str) -> Option<Match> {
+        let lowercase;
+        let path = if self.icase { lowercase = path.to_lowercase(); lowercase.as_str() } else { path };
+        let (pattern, path) = (self.pattern.as_bytes(), path.as_bytes());
+
+        if pattern.is_empty() {
+            return Some(Match::Dir);
+        }
+        if path.starts_with(pattern) {
+            if path.len() == pattern.len() {
+                return Some(Match::Exact);
+            }
+            if pattern.ends_with(b"/") || path[pattern.len()] == b'/' {
+                return Some(Match::Dir);
+            }
+        }
+        let n = self.nowildcard_len;
+        if n < pattern.len() && path.len() >= n && path[..n] == pattern[..n]
+            && glob::wildmatch(&pattern[n..], &path[n..], self.glob) {
+            return Some(Match::Wildcard);
+        }
+        None
+    }
+}
+
+// Joins the pattern to the dir it was given in and resolves `.` and `..`, keeping a trailing slash.
+// `None` if the pattern leaves the work tree.
+fn normalize(prefix: &str, pattern: &str) -> Option<String> {
+    let mut components = Vec::new();
+    for component in prefix.split('/').chain(pattern.split('/')) {
+        match component {
+            "" | "." => {},
+            ".."     => { components.pop()?; },
+            _        => components.push(component)
+        }
+    }
+    let mut normalized = components.join("/");
+    if pattern.ends_with('/') && !normalized.is_empty() {
+        normalized.push('/');
+    }
+    Some(normalized)
+}
+
+// Selects paths of the work tree like git's pathspecs. A path is selected if it matches any
+// pathspec that is not an exclude, or there are none of those, and it matches no exclude. Without
+// any pathspecs all paths are selected.
+pub struct Pathspec {
+    items: Vec<Item>,
+    attributes: Option<Attributes>
+}
+
+impl Pathspec {
+    // Parses the pathspecs as given relative to the current dir.
+    pub fn parse(repo: &Repository, args: &[String]) -> R<Pathspec> {
+        let prefix = match repo.work_tree() {
+            Ok(work_tree) => wtree::relative_path(work_tree, ".").unwrap_or_default(),
+            Err(_)        => String::new()
+        };
+        let items = args.iter().map(|arg| Item::parse(arg, &prefix)).collect::<R<Vec<_>>>()?;
+        let attributes = if items.iter().any(|i| !i.attrs.is_empty()) { Some(Attributes::load(repo)?) } else { None };
+        Ok(Pathspec { items, attributes })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    pub fn matches(&self, path: &str) -> R<bool> {
+        let mut included = self.items.iter().all(|i| i.exclude);
+        for item in self.items.iter().filter(|i| !i.exclude) {
+            if self.matches_item(item, path)?.is_some() {
+                included = true;
+                break;
+            }
+        }
+        if !included {
+            return Ok(false);
+        }
+        for item in self.items.iter().filter(|i| i.exclude) {
+            if self.matches_item(item, path)?.is_some() {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    // Whether any path below the dir may be selected, so that walks can skip the dir otherwise.
+    pub fn may_contain(&self, dir: &str) -> bool {
+        let mut includes = self.items.iter().filter(|i| !i.exclude).peekable();
+        if includes.peek().is_none() {
+            return true;
+        }
+        includes.any(|item| {
+            let dir = if item.icase { dir.to_lowercase() } else { dir.to_string() } + "/";
+            let literal = &item.pattern[..item.nowildcard_len];
+            literal.starts_with(&dir) || dir.starts_with(literal)
+        })
+    }
+
+    // The first pathspec that is not an exclude and selects none of the paths, for commands that
+    // report pathspecs without effect.
+    pub fn unmatched<'a>(&'a self, paths: &[&str]) -> R<Option<&'a str>> {
+        for item in self.items.iter().filter(|i| !i.exclude) {
+            let mut matched = false;
+            for path in paths {
+                if self.matches_item(item, path)?.is_some() {
+                    matched = true;
+                    break;
+                }
+            }
+            if !matched {
+                return Ok(Some(&item.original));
+            }
+        }
+        Ok(None)
+    }
+
+    // The first pathspec that selects some of the paths only because it names a dir they are
+    // in, like `d` for `d/f`.
+    pub fn matched_as_dir<'a>(&'a self, paths: &[&str]) -> R<Option<&'a str>> {
+        for item in self.items.iter().filter(|i| !i.exclude) {
+            let mut matches = Vec::new();
+            for path in paths {
+                matches.extend(self.matches_item(item, path)?);
+            }
+            if matches.contains(&Match::Dir) && !matches.iter().any(|m| *m != Match::Dir) {
+                return Ok(Some(&item.original));
+            }
+        }
+        Ok(None)
+    }
+
+    fn matches_item(&self, item: &Item, path: &str) -> R<Option<Match>> {
+        let matched = match item.matches(path) {
+            Some(matched) => matched,
+            None          => return Ok(None)
+        };
+        if let Some(attributes) = &self.attributes {
+            for (name, state) in &item.attrs {
+                if attributes.get(path, name)? != *state {
+                    return Ok(None);
+                }
+            }
+        }
+        Ok(Some(matched))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use crate::testutil;
+    use super::*;
+
+    fn pathspec(args: &[&str]) -> Pathspec {
+        let items = args.iter().map(|arg| Item::parse(arg, "sub")).collect::<R<Vec<_>>>().unwrap();
+        Pathspec { items, attributes: None }
+    }
+
+    fn selected(pathspec: &Pathspec, paths: &[&'static str]) -> Vec<&'static str> {
+        paths.iter().copied().filter(|path| pathspec.matches(path).unwrap()).collect()
+    }
+
+    const PATHS: &[&str] = &["a.txt", "sub/a.txt", "sub/B.TXT", "sub/dir/c.rs", "sub/dir/d.txt", "sub/file*"];
+
+    #[test]
+    fn parse_magic() {
+        let item = Item::parse(":(top,icase,exclude)Dir/*.c", "sub").unwrap();
+        assert_eq!((item.pattern.as_str(), item.nowildcard_len), ("dir/*.c", 4));
+        assert!(item.icase && item.exclude && !item.glob);
+
+        let item = Item::parse(":(literal)a*", "sub").unwrap();
+        assert_eq!((item.pattern.as_str(), item.nowildcard_len), ("sub/a*", 6));
+
+        let item = Item::parse(":(attr:text -diff eol=lf)x", "").unwrap();
+        assert_eq!(item.attrs, [("text".to_string(), State::Set), ("diff".to_string(), State::Unset), ("eol".to_string(), State::Value("lf".into()))]);
+
+        let item = Item::parse(":/!x", "sub").unwrap();
+        assert_eq!(item.pattern, "x");
+        assert!(item.exclude);
+        let item = Item::parse(":^:x", "sub").unwrap();
+        assert_eq!(item.pattern, "sub/x");
+        assert!(item.exclude);
+        assert_eq!(Item::parse(":/", "sub").unwrap().pattern, "");
+
+        assert!(Item::parse(":(top", "").is_err());
+        assert!(Item::parse(":(bogus)x", "").is_err());
+        assert!(Item::parse(":(literal,glob)x", "").is_err());
+        assert!(Item::parse("../../x", "sub").is_err());
+    }
+
+    #[test]
+    fn normalize_paths() {
+        assert_eq!(normalize("sub", "../a/./b/").as_deref(), Some("a/b/"));
+        assert_eq!(normalize("sub", ".").as_deref(), Some("sub"));
+        assert_eq!(normalize("", "..").as_deref(), None);
+    }
+
+    #[test]
+    fn matching() {
+        assert_eq!(selected(&pathspec(&[]), PATHS), PATHS);
+        assert_eq!(selected(&pathspec(&["a.txt"]), PATHS), ["sub/a.txt"]);
+        assert_eq!(selected(&pathspec(&["dir"]), PATHS), ["sub/dir/c.rs", "sub/dir/d.txt"]);
+        assert_eq!(selected(&pathspec(&["*.txt"]), PATHS), ["sub/a.txt", "sub/dir/d.txt"]);
+        assert_eq!(selected(&pathspec(&[":(glob)*.txt"]), PATHS), ["sub/a.txt"]);
+        assert_eq!(selected(&pathspec(&[":(glob)**/*.txt"]), PATHS), ["sub/a.txt", "sub/dir/d.txt"]);
+        assert_eq!(selected(&pathspec(&[":(icase)*.txt"]), PATHS), ["sub/a.txt", "sub/B.TXT", "sub/dir/d.txt"]);
+        assert_eq!(selected(&pathspec(&[":(literal)file*"]), PATHS), ["sub/file*"]);
+        assert_eq!(selected(&pathspec(&[":/*.txt"]), PATHS), ["a.txt", "sub/a.txt", "sub/dir/d.txt"]);
+        assert_eq!(selected(&pathspec(&[":!*.txt"]), PATHS), ["a.txt", "sub/B.TXT", "sub/dir/c.rs", "sub/file*"]);
+        assert_eq!(selected(&pathspec(&["dir", ":!*.rs"]), PATHS), ["sub/dir/d.txt"]);
+    }
+
+    #[test]
+    fn unmatched_and_dirs() {
+        let spec = pathspec(&["a.txt", "dir", "nothing", ":!x"]);
+        assert_eq!(spec.unmatched(&["sub/a.txt", "sub/dir/c.rs"]).unwrap(), Some("nothing"));
+        assert_eq!(spec.unmatched(&["sub/a.txt"]).unwrap(), Some("dir"));
+        assert_eq!(spec.matched_as_dir(&["sub/a.txt", "sub/dir/c.rs"]).unwrap(), Some("dir"));
+        assert_eq!(pathspec(&["*"]).matched_as_dir(&["sub/dir/c.rs"]).unwrap(), None);
+
+        assert!(spec.may_contain("sub"));
+        assert!(spec.may_contain("sub/dir"));
+        assert!(!spec.may_contain("other"));
+        assert!(pathspec(&[":(icase)DIR"]).may_contain("sub/dir"));
+        assert!(pathspec(&[":!x"]).may_contain("other"));
+    }
+
+    #[test]
+    fn attr_magic() {
+        let (dir, repo) = testutil::repo();
+        fs::write(dir.path().join(".gitattributes"), "*.txt text\n*.bin binary\n").unwrap();
+        let spec = Pathspec::parse(&repo, &[":(attr:text)".to_string()]).unwrap();
+        assert_eq!(selected(&spec, &["a.txt", "b.bin", "c"]), ["a.txt"]);
+        let spec = Pathspec::parse(&repo, &[":(attr:-diff)".to_string()]).unwrap();
+        assert_eq!(selected(&spec, &["a.txt", "b.bin", "c"]), ["b.bin"]);
+        let spec = Pathspec::parse(&repo, &[":(attr:!text)".to_string()]).unwrap();
+        assert_eq!(selected(&spec, &["a.txt", "b.bin", "c"]), ["c"]);
+    }
+}
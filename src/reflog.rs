@@ -0,0 +1,144 @@
+use std::{fs::{self, OpenOptions}, io::Write, path::PathBuf};
+use crate::{ident::{self, Role}, refs::NULL_ID, repo::Repository, sha::Sha};
+
+use crate::error::R;
+
+#[derive(Clone,Debug)]
+pub struct Entry {
+    pub new: Sha,
+    pub message: String
+}
+
+pub fn path(repo: &Repository, name: &str) -> PathBuf {
+    repo.git_dir().join("logs").join(name)
+}
+
+// Appends an entry to the log of the ref. Like git, only HEAD, branches, remote-tracking branches
+// and notes get a log by default, unless one has been created for the ref before or
+// `core.logAllRefUpdates` is `always`.
+pub fn append(repo: &Repository, name: &str, old: Option<&Sha>, new: &Sha, message: &str) -> R<()> {
+    let path = path(repo, name);
+    let config = repo.config()?;
+    let always = config.get("core.logAllRefUpdates")?.is_some_and(|v| v.eq_ignore_ascii_case("always"));
+    let default = name == "HEAD" || ["refs/heads/", "refs/remotes/", "refs/notes/"].iter().any(|p| name.starts_with(p));
+    if !(path.is_file() || always || default && config.get_bool("core.logAllRefUpdates")?.unwrap_or(!repo.is_bare()?)) {
+        return Ok(());
+    }
+
+    let old = old.map(|id| id.to_string()).unwrap_or_else(|| NULL_ID.to_string());
+    // newlines would break the line based format, so like git all runs of whitespace are folded
+    let message = message.split_whitespace().collect::<Vec<_>>().join(" ");
+
+    fs::create_dir_all(path.parent().unwrap())?;
+    let mut log = OpenOptions::new().create(true).append(true).open(&path)
+        .map_err(|e| format!("Unable to append to {:?}: {}", path, e))?;
+    log.write_all(format!("{} {} {}\t{}\n", old, new, ident::get(&config, Role::Committer, false)?, message).as_bytes())?;
+    Ok(())
+}
+
+// Returns the entries of the log with the most recent one first, so that the index of an entry
+// matches the `n` of `<ref>@{n}`.
+pub fn read(repo: &Repository, name: &str) -> R<Vec<Entry>> {
+    let path = path(repo, name);
+    if !path.is_file() { return Ok(Vec::new()); }
+
+    let content = fs::read_to_string(&path)
+        .map_err(|e| format!("Failed to read reflog of {}: {}", name, e))?;
+
+    let mut entries = content.lines()
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            let (header, message) = line.split_once('\t').unwrap_or((line, ""));
+            let invalid = || format!("Invalid reflog entry of {}: '{}'.", name, line);
+            Sha::validate(header.get(..40).ok_or_else(invalid)?)?;
+            Ok(Entry {
+                new: Sha::from_str(header.get(41..81).ok_or_else(invalid)?)?,
+                message: message.to_string()
+            })
+        })
+        .collect::<R<Vec<_>>>()?;
+
+    entries.reverse();
+    Ok(entries)
+}
+
+// Resolves `<ref>@{n}`, i.e. the value the ref had `n` updates ago.
+pub fn nth(repo: &Repository, name: &str, n: usize) -> R<Sha> {
+    let entries = read(repo, name)?;
+    match entries.get(n) {
+        Some(entry)                => Ok(entry.new.clone()),
+        None if entries.is_empty() => Err(format!("Reflog for '{}' is empty.", name).into()),
+        None                       => Err(format!("Reflog for '{}' only has {} entries.", name, entries.len()).into())
+    }
+}
+
+pub fn delete(repo: &Repository, name: &str) -> R<()> {
+    let path = path(repo, name);
+    if path.is_file() {
+        fs::remove_file(&path)
+            .map_err(|e| format!("Failed to delete reflog of {}: {}", name, e))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testutil;
+    use super::*;
+
+    fn id(digit: &str) -> Sha {
+        Sha::from_str(&digit.repeat(40)).unwrap()
+    }
+
+    fn set_log_all(repo: &Repository, value: &str) {
+        repo.set_config("core.logAllRefUpdates", value).unwrap();
+    }
+
+    #[test]
+    fn entries_are_read_most_recent_first() {
+        let (_dir, repo) = testutil::repo();
+        append(&repo, "refs/heads/master", None, &id("1"), "one").unwrap();
+        append(&repo, "refs/heads/master", Some(&id("1")), &id("2"), "two\n\n  lines").unwrap();
+
+        let entries = read(&repo, "refs/heads/master").unwrap();
+        assert_eq!(entries.iter().map(|e| (&e.new, e.message.as_str())).collect::<Vec<_>>(),
+                   [(&id("2"), "two lines"), (&id("1"), "one")]);
+        assert_eq!(nth(&repo, "refs/heads/master", 1).unwrap(), id("1"));
+        assert!(nth(&repo, "refs/heads/master", 2).is_err());
+        assert!(nth(&repo, "refs/heads/other", 0).is_err());
+
+        let log = fs::read_to_string(path(&repo, "refs/heads/master")).unwrap();
+        assert!(log.starts_with(&format!("{} {} ", NULL_ID, id("1"))));
+    }
+
+    #[test]
+    fn only_default_refs_are_logged() {
+        let (_dir, repo) = testutil::repo();
+        append(&repo, "refs/heads/master", None, &id("1"), "").unwrap();
+        append(&repo, "refs/tags/v1", None, &id("1"), "").unwrap();
+        assert_eq!(read(&repo, "refs/heads/master").unwrap().len(), 1);
+        assert!(read(&repo, "refs/tags/v1").unwrap().is_empty());
+    }
+
+    #[test]
+    fn log_all_ref_updates() {
+        let (_dir, repo) = testutil::repo();
+        set_log_all(&repo, "false");
+        append(&repo, "refs/heads/master", None, &id("1"), "").unwrap();
+        assert!(read(&repo, "refs/heads/master").unwrap().is_empty());
+
+        set_log_all(&repo, "always");
+        append(&repo, "refs/heads/master", None, &id("1"), "").unwrap();
+        append(&repo, "refs/tags/v1", None, &id("1"), "").unwrap();
+        assert_eq!(read(&repo, "refs/heads/master").unwrap().len(), 1);
+        assert_eq!(read(&repo, "refs/tags/v1").unwrap().len(), 1);
+
+        // an existing log is always appended to
+        set_log_all(&repo, "false");
+        append(&repo, "refs/tags/v1", Some(&id("1")), &id("2"), "").unwrap();
+        assert_eq!(read(&repo, "refs/tags/v1").unwrap().len(), 2);
+
+        set_log_all(&repo, "sometimes");
+        assert!(append(&repo, "refs/heads/dev", None, &id("1"), "").is_err());
+    }
+}
@@ -0,0 +1,442 @@
+use std::{fs, path::Path};
+use crate::{lock::Lock, obj::{self, ObjType}, reflog, repo::Repository, sha::Sha};
+
+use crate::error::R;
+
+pub const NULL_ID: &str = "0000000000000000000000000000000000000000";
+
+const MAX_SYMREF_DEPTH: usize = 5;
+
+#[derive(Clone,Debug)]
+pub struct Ref {
+    pub id: Sha,
+    pub name: String
+}
+
+#[derive(Clone,Debug)]
+pub enum Value {
+    Direct(Sha),
+    Symbolic(String)
+}
+
+struct PackedRef {
+    name: String,
+    id: Sha,
+    peeled: Option<Sha>
+}
+
+pub fn read(repo: &Repository, name: &str) -> R<Option<Value>> {
+    // the name becomes a path in the git dir, which it must not leave
+    check_name(name)?;
+    let path = repo.git_dir().join(name);
+
+    if path.is_file() {
+        let content = fs::read_to_string(&path)
+            .map_err(|e| format!("Failed to read ref {}: {}", name, e))?;
+        let content = content.trim_end();
+        match content.strip_prefix("ref:") {
+            Some(target) => Ok(Some(Value::Symbolic(target.trim().to_string()))),
+            None         => Ok(Some(Value::Direct(Sha::from_str(content)
+                .map_err(|e| format!("Ref {} is corrupt: {}", name, e))?)))
+        }
+    } else {
+        Ok(read_packed(repo)?.into_iter()
+            .find(|r| r.name == name)
+            .map(|r| Value::Direct(r.id)))
+    }
+}
+
+pub fn resolve(repo: &Repository, name: &str) -> R<Option<Sha>> {
+    Ok(follow(repo, name)?.1)
+}
+
+// Follows symbolic refs and returns the name of the ref at the end of the chain together with
+// its value. The value is `None` if that ref does not exist yet (e.g. an unborn branch).
+pub fn follow(repo: &Repository, name: &str) -> R<(String, Option<Sha>)> {
+    let mut name = name.to_string();
+
+    for _ in 0..MAX_SYMREF_DEPTH {
+        match read(repo, &name)? {
+            Some(Value::Symbolic(target)) => name = target,
+            Some(Value::Direct(id))       => return Ok((name, Some(id))),
+            None                          => return Ok((name, None))
+        }
+    }
+
+    Err(format!("Symbolic ref {} is nested too deeply.", name).into())
+}
+
+// Lists all refs starting with `prefix` sorted by name. Loose refs shadow packed ones and
+// symbolic refs are resolved.
+pub fn list(repo: &Repository, prefix: &str) -> R<Vec<Ref>> {
+    fn list_loose(dir: &Path, name: &str, names: &mut Vec<String>) -> R<()> {
+        for entry in dir.read_dir()? {
+            let entry = entry?;
+            let file_name = entry.file_name().to_string_lossy().into_owned();
+            let name = format!("{}/{}", name, file_name);
+            if entry.file_type()?.is_dir() {
+                list_loose(&entry.path(), &name, names)?;
+            } else if !file_name.ends_with(".lock") {
+                names.push(name);
+            }
+        }
+        Ok(())
+    }
+
+    let mut names = Vec::new();
+    if repo.git_dir().join("refs").is_dir() {
+        list_loose(&repo.git_dir().join("refs"), "refs", &mut names)?;
+    }
+
+    let mut refs = Vec::new();
+    for name in names {
+        if let Some(id) = resolve(repo, &name)? {
+            refs.push(Ref { id, name });
+        }
+    }
+
+    for packed in read_packed(repo)? {
+        if !refs.iter().any(|r| r.name == packed.name) {
+            refs.push(Ref { id: packed.id, name: packed.name });
+        }
+    }
+
+    refs.retain(|r| r.name.starts_with(prefix));
+    refs.sort_by(|a, b| a.name.cmp(&b.name));
+
+    Ok(refs)
+}
+
+// Returns the object an annotated tag ultimately points to, or `None` if the ref doesn't point to
+// a tag. The peeled values recorded in packed-refs are used when available.
+pub fn peel(repo: &Repository, r: &Ref) -> R<Option<Sha>> {
+    let packed = read_packed(repo)?.into_iter()
+        .find(|p| p.name == r.name && p.id == r.id)
+        .and_then(|p| p.peeled);
+    if packed.is_some() {
+        return Ok(packed);
+    }
+
+    let mut id = r.id.clone();
+    loop {
+        let (obj_type, content) = obj::read_raw(repo, &id)?;
+        match obj_type {
+            ObjType::Tag => id = obj::references(obj_type, &content)?.pop()
+                .ok_or(format!("Tag {} does not point to an object.", id))?,
+            _ if id == r.id => return Ok(None),
+            _ => return Ok(Some(id))
+        }
+    }
+}
+
+// Updates the ref to `new` if it still has the value `old`. A `NULL_ID` as `old` value requires
+// the ref to not exist yet, while `None` skips the check. The update is recorded in the reflog of
+// the ref and, if it is the current branch, also in the reflog of HEAD.
+pub fn update(repo: &Repository, name: &str, new: &Sha, old: Option<&Sha>, message: &str) -> R<()> {
+    check_name(name)?;
+    let lock = Lock::acquire(repo.git_dir().join(name))?;
+    verify(repo, name, old)?;
+    let previous = resolve(repo, name)?;
+    lock.commit(format!("{}\n", new))?;
+
+    reflog::append(repo, name, previous.as_ref(), new, message)?;
+    if let Some(Value::Symbolic(target)) = read(repo, "HEAD")? {
+        if target == name {
+            reflog::append(repo, "HEAD", previous.as_ref(), new, message)?;
+        }
+    }
+    Ok(())
+}
+
+pub fn delete(repo: &Repository, name: &str, old: Option<&Sha>) -> R<()> {
+    check_name(name)?;
+    let _lock = Lock::acquire(repo.git_dir().join(name))?;
+    verify(repo, name, old)?;
+
+    let path = repo.git_dir().join(name);
+    if path.is_file() {
+        fs::remove_file(&path)
+            .map_err(|e| format!("Failed to delete ref {}: {}", name, e))?;
+    }
+
+    let packed = read_packed(repo)?;
+    if packed.iter().any(|r| r.name == name) {
+        let lock = Lock::acquire(repo.git_dir().join("packed-refs"))?;
+        let packed = packed.into_iter().filter(|r| r.name != name).collect::<Vec<_>>();
+        lock.commit(print_packed(&packed))?;
+    }
+
+    reflog::delete(repo, name)
+}
+
+// Points the symbolic ref `name` to `target`. Unless `message` is empty, the commit it resolves to
+// afterwards is recorded in the reflog of `name`.
+pub fn write_symbolic(repo: &Repository, name: &str, target: &str, message: &str) -> R<()> {
+    check_name(name)?;
+    check_name(target)?;
+    if !target.starts_with("refs/") {
+        return Err(format!("Refusing to point {} outside of refs/: {}", name, target).into());
+    }
+
+    let lock = Lock::acquire(repo.git_dir().join(name))?;
+    let previous = resolve(repo, name)?;
+    lock.commit(format!("ref: {}\n", target))?;
+
+    match resolve(repo, target)? {
+        Some(new) if !message.is_empty() => reflog::append(repo, name, previous.as_ref(), &new, message),
+        _                                => Ok(())
+    }
+}
+
+pub fn delete_symbolic(repo: &Repository, name: &str) -> R<()> {
+    match read(repo, name)? {
+        Some(Value::Symbolic(_)) => {
+            let _lock = Lock::acquire(repo.git_dir().join(name))?;
+            fs::remove_file(repo.git_dir().join(name))
+                .map_err(|e| format!("Failed to delete ref {}: {}", name, e).into())
+        },
+        _ => Err(format!("Ref {} is not a symbolic ref.", name).into())
+    }
+}
+
+// Finds the full name of an abbreviated ref like `main` or `origin/main` using the same order of
+// precedence as git.
+pub fn expand(repo: &Repository, name: &str) -> R<Option<String>> {
+    let candidates = [
+        name.to_string(),
+        format!("refs/{}", name),
+        format!("refs/tags/{}", name),
+        format!("refs/heads/{}", name),
+        format!("refs/remotes/{}", name),
+        format!("refs/remotes/{}/HEAD", name)
+    ];
+
+    for candidate in candidates.iter() {
+        if check_name(candidate).is_ok()
+            && (candidate.starts_with("refs/") || !candidate.contains('/'))
+            && read(repo, candidate)?.is_some() {
+            return Ok(Some(candidate.clone()));
+        }
+    }
+    Ok(None)
+}
+
+pub fn shorten(name: &str) -> &str {
+    ["refs/heads/", "refs/tags/", "refs/remotes/", "refs/"].iter()
+        .find_map(|prefix| name.strip_prefix(prefix))
+        .unwrap_or(name)
+}
+
+// Implements the rules of git-check-ref-format. One-level names are only allowed for special
+// refs like HEAD or ORIG_HEAD.
+pub fn check_name(name: &str) -> R<()> {
+    let invalid = |reason: &str| Err(format!("'{}' is not a valid ref name: {}.", name, reason).into());
+
+    if name.is_empty() || name == "@" {
+        return invalid("name is empty");
+    }
+    if !name.contains('/') && !name.chars().all(|c| c.is_ascii_uppercase() || c == '_') {
+        return invalid("only special refs like HEAD may have a single component");
+    }
+    if name.chars().any(|c| c.is_ascii_control() || " ~^:?*[\\".contains(c)) {
+        return invalid("contains forbidden characters");
+    }
+    if name.contains("..") || name.contains("@{") || name.contains("//") {
+        return invalid("contains forbidden sequences");
+    }
+    if name.starts_with('/') || name.ends_with('/') || name.ends_with('.') {
+        return invalid("must not start with '/' or end with '/' or '.'");
+    }
+    if name.split('/').any(|part| part.starts_with('.') || part.ends_with(".lock")) {
+        return invalid("components must not start with '.' or end with '.lock'");
+    }
+
+    Ok(())
+}
+
+fn verify(repo: &Repository, name: &str, old: Option<&Sha>) -> R<()> {
+    let expected = match old {
+        Some(expected) => expected,
+        None           => return Ok(())
+    };
+
+    let current = match read(repo, name)? {
+        Some(Value::Direct(id))  => Some(id),
+        Some(Value::Symbolic(_)) => return Err(format!("Ref {} is a symbolic ref.", name).into()),
+        None                     => None
+    };
+
+    match current {
+        None if expected.value() == NULL_ID => Ok(()),
+        None                                => Err(format!("Cannot lock ref {}: ref does not exist, but expected {}.", name, expected).into()),
+        Some(id) if id == *expected         => Ok(()),
+        Some(id)                            => Err(format!("Cannot lock ref {}: is at {} but expected {}.", name, id, expected).into())
+    }
+}
+
+fn read_packed(repo: &Repository) -> R<Vec<PackedRef>> {
+    let path = repo.git_dir().join("packed-refs");
+    if !path.is_file() { return Ok(Vec::new()); }
+
+    let content = fs::read_to_string(&path)
+        .map_err(|e| format!("Failed to read packed-refs: {}", e))?;
+    parse_packed(&content)
+}
+
+fn parse_packed(content: &str) -> R<Vec<PackedRef>> {
+    let mut packed: Vec<PackedRef> = Vec::new();
+
+    for line in content.lines() {
+        if line.starts_with('#') || line.is_empty() { continue; }
+
+        if let Some(peeled) = line.strip_prefix('^') {
+            let last = packed.last_mut().ok_or("Peeled line in packed-refs without preceding ref.")?;
+            last.peeled = Some(Sha::from_str(peeled)?);
+            continue;
+        }
+
+        let (id, name) = line.split_once(' ')
+            .ok_or(format!("Invalid line in packed-refs: '{}'.", line))?;
+        packed.push(PackedRef { name: name.to_string(), id: Sha::from_str(id)?, peeled: None });
+    }
+
+    Ok(packed)
+}
+
+fn print_packed(packed: &[PackedRef]) -> String {
+    let mut content = "# pack-refs with: peeled fully-peeled sorted \n".to_string();
+    for r in packed {
+        content.push_str(&format!("{} {}\n", r.id, r.name));
+        if let Some(peeled) = &r.peeled {
+            content.push_str(&format!("^{}\n", peeled));
+        }
+    }
+    content
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testutil;
+    use super::*;
+
+    fn id(digit: &str) -> Sha {
+        Sha::from_str(&digit.repeat(40)).unwrap()
+    }
+
+    #[test]
+    fn valid_names() {
+        for name in ["HEAD", "ORIG_HEAD", "refs/heads/master", "refs/heads/feature/x-1", "refs/tags/v1.0", "refs/heads/a.b"].iter() {
+            assert!(check_name(name).is_ok(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn invalid_names() {
+        for name in ["", "@", "master", "refs/heads/a..b", "refs/heads/a b", "refs/heads/a~1", "refs/heads/a^",
+                     "refs/heads/a:b", "refs/heads/a?", "refs/heads/a*", "refs/heads/a[", "refs/heads/a\\b",
+                     "refs/heads/a\tb", "refs/heads/a@{1}", "refs//heads", "refs/heads/", "refs/heads/a.",
+                     "refs/heads/.a", "refs/heads/a.lock", "/etc/passwd"].iter() {
+            assert!(check_name(name).is_err(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn parse_packed_refs() {
+        let content = format!("# pack-refs with: peeled fully-peeled sorted \n{} refs/heads/master\n{} refs/tags/v1\n^{}\n", id("1"), id("2"), id("3"));
+        let packed = parse_packed(&content).unwrap();
+        assert_eq!(packed.len(), 2);
+        assert_eq!((packed[0].name.as_str(), &packed[0].id, &packed[0].peeled), ("refs/heads/master", &id("1"), &None));
+        assert_eq!((packed[1].name.as_str(), &packed[1].id, &packed[1].peeled), ("refs/tags/v1", &id("2"), &Some(id("3"))));
+        assert_eq!(print_packed(&packed), content);
+    }
+
+    #[test]
+    fn parse_invalid_packed_refs() {
+        assert!(parse_packed(&format!("^{}\n", id("1"))).is_err());
+        assert!(parse_packed(&format!("{}\n", id("1"))).is_err());
+        assert!(parse_packed("1234 refs/heads/master\n").is_err());
+    }
+
+    #[test]
+    fn update_checks_old_value() {
+        let (_dir, repo) = testutil::repo();
+        let null = Sha::from_str(NULL_ID).unwrap();
+
+        update(&repo, "refs/heads/master", &id("1"), Some(&null), "create").unwrap();
+        assert!(update(&repo, "refs/heads/master", &id("2"), Some(&null), "create").is_err());
+        assert!(update(&repo, "refs/heads/master", &id("2"), Some(&id("3")), "update").is_err());
+        update(&repo, "refs/heads/master", &id("2"), Some(&id("1")), "update").unwrap();
+        update(&repo, "refs/heads/master", &id("3"), None, "update").unwrap();
+
+        assert_eq!(resolve(&repo, "refs/heads/master").unwrap(), Some(id("3")));
+        assert_eq!(resolve(&repo, "HEAD").unwrap(), Some(id("3")));
+        assert!(!repo.git_dir().join("refs/heads/master.lock").exists());
+    }
+
+    #[test]
+    fn loose_refs_shadow_packed_refs() {
+        let (_dir, repo) = testutil::repo();
+        fs::write(repo.git_dir().join("packed-refs"), format!("{} refs/heads/a\n{} refs/heads/b\n", id("1"), id("2"))).unwrap();
+        update(&repo, "refs/heads/b", &id("3"), None, "update").unwrap();
+
+        let refs = list(&repo, "refs/heads/").unwrap();
+        assert_eq!(refs.iter().map(|r| (r.name.as_str(), &r.id)).collect::<Vec<_>>(),
+                   [("refs/heads/a", &id("1")), ("refs/heads/b", &id("3"))]);
+
+        delete(&repo, "refs/heads/b", Some(&id("3"))).unwrap();
+        assert!(read(&repo, "refs/heads/b").unwrap().is_none());
+        assert!(!fs::read_to_string(repo.git_dir().join("packed-refs")).unwrap().contains("refs/heads/b"));
+        delete(&repo, "refs/heads/a", None).unwrap();
+        assert!(list(&repo, "refs/").unwrap().is_empty());
+    }
+
+    #[test]
+    fn symbolic_refs() {
+        let (_dir, repo) = testutil::repo();
+        assert_eq!(follow(&repo, "HEAD").unwrap(), ("refs/heads/master".to_string(), None));
+
+        update(&repo, "refs/heads/dev", &id("1"), None, "create").unwrap();
+        write_symbolic(&repo, "HEAD", "refs/heads/dev", "checkout").unwrap();
+        assert_eq!(follow(&repo, "HEAD").unwrap(), ("refs/heads/dev".to_string(), Some(id("1"))));
+        assert!(write_symbolic(&repo, "HEAD", "HEAD", "").is_err());
+
+        assert!(delete_symbolic(&repo, "refs/heads/dev").is_err());
+        delete_symbolic(&repo, "HEAD").unwrap();
+        assert!(read(&repo, "HEAD").unwrap().is_none());
+    }
+
+    #[test]
+    fn read_stays_in_the_git_dir() {
+        let (dir, repo) = testutil::repo();
+        fs::write(dir.path().join("secret"), "not a ref\n").unwrap();
+        for name in ["../secret", "refs/../../secret", &dir.path().join("secret").to_string_lossy()].iter() {
+            let e = read(&repo, name).unwrap_err().to_string();
+            assert!(e.contains("is not a valid ref name") && !e.contains("not a ref\n"), "{}", e);
+            assert!(follow(&repo, name).is_err());
+        }
+
+        // nor do symbolic refs pointing outside of it
+        fs::write(repo.git_dir().join("HEAD"), "ref: ../secret\n").unwrap();
+        assert!(resolve(&repo, "HEAD").is_err());
+    }
+
+    #[test]
+    fn expand_in_git_order() {
+        let (_dir, repo) = testutil::repo();
+        update(&repo, "refs/heads/x", &id("1"), None, "").unwrap();
+        update(&repo, "refs/remotes/origin/y", &id("1"), None, "").unwrap();
+        assert_eq!(expand(&repo, "x").unwrap().as_deref(), Some("refs/heads/x"));
+        update(&repo, "refs/tags/x", &id("2"), None, "").unwrap();
+        assert_eq!(expand(&repo, "x").unwrap().as_deref(), Some("refs/tags/x"));
+        assert_eq!(expand(&repo, "origin/y").unwrap().as_deref(), Some("refs/remotes/origin/y"));
+        assert_eq!(expand(&repo, "z").unwrap(), None);
+    }
+
+    #[test]
+    fn shorten_names() {
+        assert_eq!(shorten("refs/heads/a/b"), "a/b");
+        assert_eq!(shorten("refs/remotes/origin/a"), "origin/a");
+        assert_eq!(shorten("refs/notes/x"), "notes/x");
+        assert_eq!(shorten("HEAD"), "HEAD");
+    }
+}
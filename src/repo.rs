@@ -1,23 +1,388 @@
-use std::{fs, path::{Path, PathBuf}};
-
-type R<T> = std::result::Result<T, Box<dyn std::error::Error>>;
-
-pub fn git_dir() -> R<PathBuf> {
-    let wd = Path::new(".");
-    let git_dir = wd.join(".git");
-    if git_dir.as_path().exists() {
-        Ok(git_dir)
-    } else {
-        Err(format!("No '.git' directory found in {:?}.", fs::canonicalize(wd).unwrap()).into())
-    }
-}
-
-pub fn init(path: &Path) -> R<PathBuf> {
-    let git_dir = path.join(".git");
-    fs::create_dir_all(git_dir.join("objects/info"))?;
-    fs::create_dir_all(git_dir.join("objects/pack"))?;
-    fs::create_dir_all(git_dir.join("refs/heads"))?;
-    fs::create_dir_all(git_dir.join("refs/tags"))?;
-    fs::write(git_dir.join("HEAD"), "ref: refs/heads/master\n")?;
-    Ok(git_dir)
-}
+use std::{cell::RefCell, env, fs, path::{Path, PathBuf}, rc::Rc};
+use crate::{config::{self, Config, Scope}, error::Error, pack::{self, store::Pack}};
+
+use crate::error::R;
+
+// A handle to a repository, which caches what is expensive to load, like the parsed config and the
+// pack indexes, for as long as it lives. Long running processes should open a fresh handle for
+// each operation so that changes made by others are seen.
+pub struct Repository {
+    git_dir: PathBuf,
+    work_tree: Option<PathBuf>,
+    config: RefCell<Option<Rc<Config>>>,
+    packs: RefCell<Option<Rc<Vec<Pack>>>>
+}
+
+impl Repository {
+    pub fn open(git_dir: PathBuf, work_tree: Option<PathBuf>) -> Repository {
+        Repository { git_dir, work_tree, config: RefCell::new(None), packs: RefCell::new(None) }
+    }
+
+    // Opens the repository containing the current dir.
+    pub fn find() -> R<Repository> {
+        Repository::discover(&env::current_dir()?)
+    }
+
+    pub fn discover(dir: &Path) -> R<Repository> {
+        let (git_dir, work_tree) = discover(dir)?;
+        Ok(Repository::open(git_dir, work_tree))
+    }
+
+    pub fn init(path: &Path, bare: bool) -> R<Repository> {
+        let git_dir = init(path, bare)?;
+        let work_tree = if bare { None } else { Some(absolute(path)?) };
+        Ok(Repository::open(git_dir, work_tree))
+    }
+
+    pub fn reopen(&self) -> Repository {
+        Repository::open(self.git_dir.clone(), self.work_tree.clone())
+    }
+
+    pub fn git_dir(&self) -> &Path {
+        &self.git_dir
+    }
+
+    pub fn work_tree(&self) -> R<&Path> {
+        self.work_tree.as_deref()
+            .ok_or_else(|| "This operation must be run in a work tree.".into())
+    }
+
+    pub fn is_bare(&self) -> R<bool> {
+        is_bare(&self.git_dir)
+    }
+
+    pub fn config(&self) -> R<Rc<Config>> {
+        if let Some(config) = self.config.borrow().as_ref() {
+            return Ok(config.clone());
+        }
+        let config = Rc::new(Config::load(Some(&self.git_dir))?);
+        *self.config.borrow_mut() = Some(config.clone());
+        Ok(config)
+    }
+
+    // Writes to the local config and drops the cached one.
+    pub fn set_config(&self, key: &str, value: &str) -> R<()> {
+        config::set(&self.git_dir.join("config"), key, value, false)?;
+        self.config.borrow_mut().take();
+        Ok(())
+    }
+
+    pub fn packs(&self) -> R<Rc<Vec<Pack>>> {
+        if let Some(packs) = self.packs.borrow().as_ref() {
+            return Ok(packs.clone());
+        }
+        let packs = Rc::new(pack::store::load(&self.git_dir)?);
+        *self.packs.borrow_mut() = Some(packs.clone());
+        Ok(packs)
+    }
+
+    // Must be called after adding a pack, so that its objects are found.
+    pub fn reload_packs(&self) {
+        self.packs.borrow_mut().take();
+    }
+
+    // Bare repositories only get the URL, as their branches are fetched into refs/heads directly.
+    pub fn add_remote(&self, remote: &str, url: &str) -> R<()> {
+        self.set_config(&format!("remote.{}.url", remote), url)?;
+        if !self.is_bare()? {
+            self.set_config(&format!("remote.{}.fetch", remote), &format!("+refs/heads/*:refs/remotes/{}/*", remote))?;
+        }
+        Ok(())
+    }
+
+    pub fn set_upstream(&self, branch: &str, remote: &str, remote_branch: &str) -> R<()> {
+        self.set_config(&format!("branch.{}.remote", branch), remote)?;
+        self.set_config(&format!("branch.{}.merge", branch), remote_branch)
+    }
+
+    pub fn rename_branch_config(&self, branch: &str, new: &str) -> R<()> {
+        config::rename_section(&self.git_dir.join("config"), &format!("branch.{}", branch), &format!("branch.{}", new))?;
+        self.config.borrow_mut().take();
+        Ok(())
+    }
+
+    pub fn remove_branch_config(&self, branch: &str) -> R<()> {
+        config::remove_section(&self.git_dir.join("config"), &format!("branch.{}", branch))?;
+        self.config.borrow_mut().take();
+        Ok(())
+    }
+
+    // Removes the upstream of the branch from the local config. False if it had none.
+    pub fn unset_upstream(&self, branch: &str) -> R<bool> {
+        let mut found = false;
+        for name in ["remote", "merge"].iter() {
+            let key = format!("branch.{}.{}", branch, name);
+            if self.config()?.get(&key)?.is_some() {
+                config::unset(&self.git_dir.join("config"), &key, true)?;
+                self.config.borrow_mut().take();
+                found = true;
+            }
+        }
+        Ok(found)
+    }
+}
+
+// Finds the repository containing `dir` and returns its git dir and work tree, if it has one.
+// GIT_DIR and GIT_WORK_TREE take precedence. Otherwise `dir` and its parents are searched for a
+// `.git` directory or file, or a bare repository, stopping below any GIT_CEILING_DIRECTORIES.
+fn discover(dir: &Path) -> R<(PathBuf, Option<PathBuf>)> {
+    let dir = absolute(dir)?;
+
+    if let Some(git_dir) = env::var_os("GIT_DIR") {
+        let git_dir = resolve_git_file(&dir.join(git_dir))?;
+        if !is_git_dir(&git_dir) {
+            return Err(format!("GIT_DIR {:?} is not a git repository.", git_dir).into());
+        }
+        let work_tree = work_tree_of(&git_dir, Some(dir))?;
+        return Ok((git_dir, work_tree));
+    }
+
+    let ceiling = env::var_os("GIT_CEILING_DIRECTORIES")
+        .and_then(|dirs| env::split_paths(&dirs)
+            .filter(|c| c.is_absolute())
+            .map(|c| fs::canonicalize(&c).unwrap_or(c))
+            .filter(|c| dir.starts_with(c) && dir != *c)
+            .map(|c| c.components().count())
+            .max())
+        .unwrap_or(0);
+
+    let mut candidate = Some(dir.as_path());
+    while let Some(current) = candidate.filter(|c| c.components().count() > ceiling || c == &dir) {
+        let dot_git = current.join(".git");
+        if dot_git.exists() {
+            let git_dir = resolve_git_file(&dot_git)?;
+            if is_git_dir(&git_dir) {
+                let work_tree = work_tree_of(&git_dir, Some(current.to_path_buf()))?;
+                return Ok((git_dir, work_tree));
+            }
+        }
+        if is_git_dir(current) {
+            let work_tree = work_tree_of(current, None)?;
+            return Ok((current.to_path_buf(), work_tree));
+        }
+        candidate = current.parent();
+    }
+
+    Err(Error::NotARepository(dir))
+}
+
+fn is_bare(git_dir: &Path) -> R<bool> {
+    match local_setting(git_dir, "core.bare")? {
+        Some(bare) => Ok(config::parse_bool(&bare).unwrap_or(false)),
+        None       => Ok(git_dir.file_name().is_none_or(|name| name != ".git"))
+    }
+}
+
+fn init(path: &Path, bare: bool) -> R<PathBuf> {
+    let git_dir = if bare { path.to_path_buf() } else { path.join(".git") };
+    fs::create_dir_all(git_dir.join("objects/info"))?;
+    fs::create_dir_all(git_dir.join("objects/pack"))?;
+    fs::create_dir_all(git_dir.join("refs/heads"))?;
+    fs::create_dir_all(git_dir.join("refs/tags"))?;
+    // re-running init must not move HEAD away from the branch checked out
+    if !git_dir.join("HEAD").is_file() {
+        // like git, only the system and global config count, as there is no repository yet
+        let branch = Config::load(None)?.get("init.defaultBranch")?.unwrap_or_else(|| "master".to_string());
+        fs::write(git_dir.join("HEAD"), format!("ref: refs/heads/{}\n", branch))?;
+    }
+    if !git_dir.join("config").is_file() {
+        fs::write(git_dir.join("config"), match bare {
+            true  => "[core]\n\trepositoryformatversion = 0\n\tfilemode = true\n\tbare = true\n",
+            false => "[core]\n\trepositoryformatversion = 0\n\tfilemode = true\n\tbare = false\n\tlogallrefupdates = true\n"
+        })?;
+    }
+    absolute(&git_dir)
+}
+
+fn is_git_dir(dir: &Path) -> bool {
+    dir.join("HEAD").is_file() && dir.join("objects").is_dir() && dir.join("refs").is_dir()
+}
+
+// Follows a `.git` file containing `gitdir: <path>`, as used by worktrees and submodules.
+fn resolve_git_file(path: &Path) -> R<PathBuf> {
+    if !path.is_file() {
+        return absolute(path);
+    }
+    let content = fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read {:?}: {}", path, e))?;
+    let target = content.trim_end().strip_prefix("gitdir: ")
+        .ok_or_else(|| format!("Invalid gitfile format: {:?}.", path))?;
+    absolute(&path.parent().unwrap_or(Path::new("/")).join(target))
+}
+
+// The work tree is GIT_WORK_TREE or core.worktree if set. Otherwise bare repositories have none
+// and the default is used, which is the dir containing `.git` or the current dir with GIT_DIR.
+fn work_tree_of(git_dir: &Path, default: Option<PathBuf>) -> R<Option<PathBuf>> {
+    if let Some(work_tree) = env::var_os("GIT_WORK_TREE") {
+        return absolute(Path::new(&work_tree)).map(Some);
+    }
+    if let Some(work_tree) = local_setting(git_dir, "core.worktree")? {
+        return absolute(&git_dir.join(work_tree)).map(Some);
+    }
+    match local_setting(git_dir, "core.bare")?.and_then(|b| config::parse_bool(&b)) {
+        Some(true) => Ok(None),
+        _          => Ok(default)
+    }
+}
+
+fn local_setting(git_dir: &Path, key: &str) -> R<Option<String>> {
+    Ok(config::list_file(&git_dir.join("config"), Scope::Local)?.into_iter()
+        .rfind(|e| e.key == key)
+        .map(|e| e.value.unwrap_or_default()))
+}
+
+// Makes the path absolute and removes `.` and `..` components without resolving symlinks, so
+// that paths printed to the user stay recognizable.
+pub fn absolute(path: &Path) -> R<PathBuf> {
+    let path = if path.is_absolute() { path.to_path_buf() } else { env::current_dir()?.join(path) };
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component.as_os_str().to_str() {
+            Some(".")  => {},
+            Some("..") => { normalized.pop(); },
+            _          => normalized.push(component)
+        }
+    }
+    Ok(normalized)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testutil::{self, TempDir};
+    use super::*;
+
+    #[test]
+    fn discover_walks_up_to_the_work_tree() {
+        let (dir, repo) = testutil::repo();
+        fs::create_dir_all(dir.path().join("a/b")).unwrap();
+
+        let found = Repository::discover(&dir.path().join("a/b")).unwrap();
+        assert_eq!(found.git_dir(), repo.git_dir());
+        assert_eq!(found.work_tree().unwrap(), dir.path());
+        assert!(!found.is_bare().unwrap());
+    }
+
+    #[test]
+    fn discover_bare_repositories() {
+        testutil::isolate();
+        let dir = TempDir::new();
+        let repo = Repository::init(&dir.path().join("bare.git"), true).unwrap();
+        fs::create_dir_all(repo.git_dir().join("refs/heads/x")).unwrap();
+
+        let found = Repository::discover(&repo.git_dir().join("refs/heads/x")).unwrap();
+        assert_eq!(found.git_dir(), dir.path().join("bare.git"));
+        assert!(found.work_tree().is_err());
+        assert!(found.is_bare().unwrap());
+    }
+
+    #[test]
+    fn discover_follows_git_files() {
+        let (dir, repo) = testutil::repo();
+        let work_tree = dir.path().join("linked");
+        fs::create_dir_all(&work_tree).unwrap();
+        fs::write(work_tree.join(".git"), "gitdir: ../.git\n").unwrap();
+
+        let found = Repository::discover(&work_tree).unwrap();
+        assert_eq!(found.git_dir(), repo.git_dir());
+        assert_eq!(found.work_tree().unwrap(), work_tree);
+
+        fs::write(work_tree.join(".git"), "nonsense\n").unwrap();
+        assert!(Repository::discover(&work_tree).is_err());
+    }
+
+    #[test]
+    fn core_worktree_is_relative_to_git_dir() {
+        let (dir, repo) = testutil::repo();
+        repo.set_config("core.worktree", "../elsewhere").unwrap();
+        let found = Repository::discover(dir.path()).unwrap();
+        assert_eq!(found.work_tree().unwrap(), dir.path().join("elsewhere"));
+    }
+
+    #[test]
+    fn outside_of_repositories() {
+        testutil::isolate();
+        let dir = TempDir::new();
+        match Repository::discover(dir.path()) {
+            Err(Error::NotARepository(path)) => assert_eq!(path, dir.path()),
+            Err(e)                           => panic!("{}", e),
+            Ok(repo)                         => panic!("found {:?}", repo.git_dir())
+        }
+    }
+
+    #[test]
+    fn init_again_keeps_head_and_config() {
+        let (dir, repo) = testutil::repo();
+        assert_eq!(fs::read_to_string(repo.git_dir().join("HEAD")).unwrap(), "ref: refs/heads/master\n");
+        fs::write(repo.git_dir().join("HEAD"), "ref: refs/heads/dev\n").unwrap();
+        repo.set_config("user.name", "x").unwrap();
+
+        Repository::init(dir.path(), false).unwrap();
+        assert_eq!(fs::read_to_string(repo.git_dir().join("HEAD")).unwrap(), "ref: refs/heads/dev\n");
+        assert_eq!(repo.reopen().config().unwrap().get("user.name").unwrap().as_deref(), Some("x"));
+    }
+
+    #[test]
+    fn config_is_cached_until_written() {
+        let (_dir, repo) = testutil::repo();
+        assert_eq!(repo.config().unwrap().get("user.name").unwrap(), None);
+
+        config::set(&repo.git_dir().join("config"), "user.name", "x", false).unwrap();
+        assert_eq!(repo.config().unwrap().get("user.name").unwrap(), None);
+        assert_eq!(repo.reopen().config().unwrap().get("user.name").unwrap().as_deref(), Some("x"));
+
+        repo.set_config("user.email", "x@example.com").unwrap();
+        assert_eq!(repo.config().unwrap().get("user.name").unwrap().as_deref(), Some("x"));
+    }
+
+    #[test]
+    fn packs_are_cached_until_reloaded() {
+        let (_dir, repo) = testutil::repo();
+        assert!(repo.packs().unwrap().is_empty());
+
+        let pack_dir = repo.git_dir().join("objects/pack");
+        fs::write(pack_dir.join("pack-a.idx"), pack::store::tests::index_v2(&Default::default())).unwrap();
+        fs::write(pack_dir.join("pack-a.pack"), b"").unwrap();
+        assert!(repo.packs().unwrap().is_empty());
+
+        repo.reload_packs();
+        assert_eq!(repo.packs().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn remotes_and_upstreams() {
+        let (_dir, repo) = testutil::repo();
+        repo.add_remote("origin", "https://example.com/x.git").unwrap();
+        repo.set_upstream("main", "origin", "refs/heads/main").unwrap();
+        let config = repo.config().unwrap();
+        assert_eq!(config.get("remote.origin.fetch").unwrap().as_deref(), Some("+refs/heads/*:refs/remotes/origin/*"));
+        assert_eq!(config.get("branch.main.merge").unwrap().as_deref(), Some("refs/heads/main"));
+
+        repo.rename_branch_config("main", "dev").unwrap();
+        assert_eq!(repo.config().unwrap().get("branch.main.remote").unwrap(), None);
+        assert_eq!(repo.config().unwrap().get("branch.dev.remote").unwrap().as_deref(), Some("origin"));
+
+        assert!(repo.unset_upstream("dev").unwrap());
+        assert!(!repo.unset_upstream("dev").unwrap());
+        assert_eq!(repo.config().unwrap().get("branch.dev.merge").unwrap(), None);
+
+        repo.set_upstream("dev", "origin", "refs/heads/dev").unwrap();
+        repo.remove_branch_config("dev").unwrap();
+        assert_eq!(repo.config().unwrap().get("branch.dev.remote").unwrap(), None);
+    }
+
+    #[test]
+    fn bare_remotes_have_no_fetch_refspec() {
+        testutil::isolate();
+        let dir = TempDir::new();
+        let repo = Repository::init(dir.path(), true).unwrap();
+        repo.add_remote("origin", "https://example.com/x.git").unwrap();
+        assert_eq!(repo.config().unwrap().get("remote.origin.url").unwrap().as_deref(), Some("https://example.com/x.git"));
+        assert_eq!(repo.config().unwrap().get("remote.origin.fetch").unwrap(), None);
+    }
+
+    #[test]
+    fn absolute_paths_are_normalized() {
+        assert_eq!(absolute(Path::new("/a/./b/../c")).unwrap(), Path::new("/a/c"));
+        assert_eq!(absolute(Path::new("x")).unwrap(), env::current_dir().unwrap().join("x"));
+    }
+}
@@ -0,0 +1,359 @@
+use std::{collections::HashSet, convert::TryFrom};
+use crate::{obj::{self, Obj, ObjType}, reflog, refs::{self, Value}, repo::Repository, sha::Sha};
+
+use crate::error::R;
+
+const MIN_ABBREV: usize = 4;
+
+// Resolves a revision as described in gitrevisions(7) to an object id. Supported are ref names,
+// full and abbreviated ids, `@{n}` and `@{upstream}` suffixes, the `^`, `^N`, `~N` and `^{type}`
+// operators, `<rev>:<path>` and `:/<message>`.
+pub fn parse(repo: &Repository, spec: &str) -> R<Sha> {
+    if let Some(pattern) = spec.strip_prefix(":/") {
+        return find_by_message(repo, pattern)
+            .and_then(|id| id.ok_or_else(|| format!("No commit message matches '{}'.", pattern).into()));
+    }
+
+    if let Some(path) = spec.strip_prefix(':') {
+        return Err(format!("Cannot resolve '{}': looking up paths in the index is not supported.", path).into());
+    }
+
+    match spec.split_once(':') {
+        Some((rev, path)) => {
+            let tree = peel(repo, &parse_rev(repo, rev)?, ObjType::Tree)
+                .map_err(|e| format!("Invalid revision '{}': {}", spec, e))?;
+            find_path(repo, &tree, path)?
+                .ok_or_else(|| format!("Path '{}' does not exist in '{}'.", path, rev).into())
+        },
+        None => parse_rev(repo, spec)
+    }
+}
+
+// Like `parse`, but makes sure the revision resolves to an object of the given type by peeling
+// tags and commits.
+pub fn parse_as(repo: &Repository, spec: &str, obj_type: ObjType) -> R<Sha> {
+    peel(repo, &parse(repo, spec)?, obj_type)
+        .map_err(|e| format!("Invalid revision '{}': {}", spec, e).into())
+}
+
+// Follows tags and the tree of commits until an object of type `target` is reached.
+pub fn peel(repo: &Repository, id: &Sha, target: ObjType) -> R<Sha> {
+    let mut id = id.clone();
+    loop {
+        let (obj_type, content) = obj::read_raw(repo, &id)?;
+        match (obj_type, target) {
+            (ObjType::Commit, ObjType::Commit) |
+            (ObjType::Tree, ObjType::Tree)     |
+            (ObjType::Blob, ObjType::Blob)     |
+            (ObjType::Tag, ObjType::Tag)       => return Ok(id),
+            (ObjType::Tag, _)                  |
+            (ObjType::Commit, ObjType::Tree)   => id = obj::references(obj_type, &content)?.remove(0),
+            _ => return Err(format!("Object {} is a {}, not a {}.", id, obj_type.as_str(), target.as_str()).into())
+        }
+    }
+}
+
+// Parses a revision without a `:<path>` part. The base name ends at the first `^` or `~`, which
+// start the chain of navigation operators.
+fn parse_rev(repo: &Repository, spec: &str) -> R<Sha> {
+    let end = spec.find(['^', '~']).unwrap_or(spec.len());
+    let (base, mut ops) = spec.split_at(end);
+    let mut id = parse_base(repo, base)?;
+
+    while !ops.is_empty() {
+        let op = ops.chars().next().unwrap();
+        ops = &ops[1..];
+
+        if op == '^' && ops.starts_with('{') {
+            let close = ops.find('}').ok_or_else(|| format!("Missing '}}' in revision '{}'.", spec))?;
+            id = match &ops[1..close] {
+                ""       => peel_tags(repo, &id)?,
+                "object" => id,
+                obj_type => peel(repo, &id, ObjType::try_from(obj_type)?)?
+            };
+            ops = &ops[close+1..];
+            continue;
+        }
+
+        let digits = ops.find(|c: char| !c.is_ascii_digit()).unwrap_or(ops.len());
+        let n = match &ops[..digits] {
+            ""     => 1,
+            digits => digits.parse::<usize>()?
+        };
+        ops = &ops[digits..];
+
+        id = match op {
+            '^' if n == 0 => peel(repo, &id, ObjType::Commit)?,
+            '^'           => nth_parent(repo, &id, n)
+                .map_err(|e| format!("Invalid revision '{}': {}", spec, e))?,
+            _             => (0..n).try_fold(id, |id, _| nth_parent(repo, &id, 1))
+                .map_err(|e| format!("Invalid revision '{}': {}", spec, e))?
+        };
+    }
+
+    Ok(id)
+}
+
+fn parse_base(repo: &Repository, base: &str) -> R<Sha> {
+    if let Some((name, suffix)) = base.strip_suffix('}').and_then(|b| b.split_once("@{")) {
+        let name = match name {
+            "" => current_branch(repo)?.unwrap_or_else(|| "HEAD".to_string()),
+            _  => refs::expand(repo, name)?.ok_or_else(|| format!("Unknown ref '{}'.", name))?
+        };
+
+        return match suffix {
+            "upstream" | "u" => {
+                let upstream = upstream(repo, &name)?;
+                refs::resolve(repo, &upstream)?
+                    .ok_or_else(|| format!("Upstream branch {} of {} does not exist.", upstream, name).into())
+            },
+            n => reflog::nth(repo, &name, n.parse()
+                .map_err(|_| format!("Unsupported reflog selector '@{{{}}}'.", n))?)
+        };
+    }
+
+    let base = if base == "@" { "HEAD" } else { base };
+    if let Some(name) = refs::expand(repo, base)? {
+        return refs::resolve(repo, &name)?
+            .ok_or_else(|| format!("Ref '{}' does not point to a commit yet.", name).into());
+    }
+
+    if base.len() >= MIN_ABBREV && base.len() <= 40 && base.chars().all(|c| c.is_ascii_hexdigit()) {
+        let prefix = base.to_lowercase();
+        if prefix.len() == 40 {
+            return Sha::from_string(prefix);
+        }
+        let mut candidates = obj::find_prefix(repo, &prefix)?;
+        return match candidates.len() {
+            0 => Err(format!("Unknown revision '{}'.", base).into()),
+            1 => Ok(candidates.remove(0)),
+            _ => Err(ambiguous(repo, base, &candidates)?.into())
+        };
+    }
+
+    Err(format!("Unknown revision '{}'.", base).into())
+}
+
+fn ambiguous(repo: &Repository, prefix: &str, candidates: &[Sha]) -> R<String> {
+    let mut msg = format!("Short object id {} is ambiguous. The candidates are:", prefix);
+    for id in candidates {
+        let (obj_type, _) = obj::read_raw(repo, id)?;
+        msg.push_str(&format!("\n  {} {}", obj::abbreviate(repo, id, obj::DEFAULT_ABBREV)?, obj_type.as_str()));
+    }
+    Ok(msg)
+}
+
+fn nth_parent(repo: &Repository, id: &Sha, n: usize) -> R<Sha> {
+    match obj::read(repo, &peel(repo, id, ObjType::Commit)?)? {
+        Obj::Commit { mut parents, .. } if n <= parents.len() => Ok(parents.remove(n - 1)),
+        _ => Err(format!("Commit {} has no parent {}.", id, n).into())
+    }
+}
+
+fn peel_tags(repo: &Repository, id: &Sha) -> R<Sha> {
+    let mut id = id.clone();
+    loop {
+        match obj::read_raw(repo, &id)? {
+            (ObjType::Tag, content) => id = obj::references(ObjType::Tag, &content)?.remove(0),
+            _                       => return Ok(id)
+        }
+    }
+}
+
+fn find_path(repo: &Repository, tree: &Sha, path: &str) -> R<Option<Sha>> {
+    let mut id = tree.clone();
+    for name in path.split('/').filter(|n| !n.is_empty()) {
+        let entries = match obj::read(repo, &id)? {
+            Obj::Tree { entries } => entries,
+            _                     => return Ok(None)
+        };
+        match entries.into_iter().find(|e| e.name == name) {
+            Some(entry) => id = entry.id,
+            None        => return Ok(None)
+        }
+    }
+    Ok(Some(id))
+}
+
+fn current_branch(repo: &Repository) -> R<Option<String>> {
+    match refs::read(repo, "HEAD")? {
+        Some(Value::Symbolic(target)) => Ok(Some(target)),
+        _                             => Ok(None)
+    }
+}
+
+// Maps a branch to the remote-tracking ref of its upstream as configured by `branch.<name>.remote`
+// and `branch.<name>.merge`.
+pub fn upstream(repo: &Repository, name: &str) -> R<String> {
+    let branch = name.strip_prefix("refs/heads/")
+        .ok_or_else(|| format!("{} is not a branch.", name))?;
+    let config = repo.config()?;
+    let remote = config.get(&format!("branch.{}.remote", branch))?;
+    let merge = config.get(&format!("branch.{}.merge", branch))?;
+
+    match (remote, merge) {
+        (Some(remote), Some(merge)) if remote == "." => Ok(merge),
+        (Some(remote), Some(merge)) => {
+            let merge = merge.strip_prefix("refs/heads/").unwrap_or(&merge);
+            let tracking = format!("refs/remotes/{}/{}", remote, merge);
+            if refs::read(repo, &tracking)?.is_none() {
+                return Err(format!("Upstream branch {} of {} is not fetched.", tracking, branch).into());
+            }
+            Ok(tracking)
+        },
+        _ => Err(format!("No upstream configured for branch '{}'.", branch).into())
+    }
+}
+
+// The commit and all commits reachable from it through their parents.
+pub fn ancestors(repo: &Repository, commit: &Sha) -> R<HashSet<Sha>> {
+    let mut queue = vec![commit.clone()];
+    let mut seen = HashSet::new();
+
+    while let Some(id) = queue.pop() {
+        if !seen.insert(id.clone()) { continue; }
+        if let Obj::Commit { parents, .. } = obj::read(repo, &id)? {
+            queue.extend(parents);
+        }
+    }
+
+    Ok(seen)
+}
+
+// Finds the youngest commit reachable from any ref whose message contains `pattern`.
+fn find_by_message(repo: &Repository, pattern: &str) -> R<Option<Sha>> {
+    let mut queue = refs::list(repo, "refs/")?.into_iter().map(|r| r.id).collect::<Vec<_>>();
+    queue.extend(refs::resolve(repo, "HEAD")?);
+    let mut seen = HashSet::new();
+    let mut best: Option<(u64, Sha)> = None;
+
+    while let Some(id) = queue.pop() {
+        if !seen.insert(id.clone()) { continue; }
+        let id = match peel(repo, &id, ObjType::Commit) {
+            Ok(id) => id,
+            Err(_) => continue // refs may point to trees or blobs
+        };
+
+        if let Obj::Commit { parents, committer, message, .. } = obj::read(repo, &id)? {
+            let time = committer.rsplit(' ').nth(1).and_then(|t| t.parse().ok()).unwrap_or(0);
+            if message.contains(pattern) && best.as_ref().is_none_or(|(t, _)| time > *t) {
+                best = Some((time, id));
+            }
+            queue.extend(parents);
+        }
+    }
+
+    Ok(best.map(|(_, id)| id))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{obj::TreeEntry, testutil};
+    use super::*;
+
+    // A merge of `second` and `side`, which both descend from `first`, checked out on master.
+    struct History { first: Sha, second: Sha, side: Sha, merge: Sha }
+
+    fn history(repo: &Repository) -> History {
+        let first = testutil::commit(repo, &[], "first");
+        let second = testutil::commit(repo, &[&first], "second");
+        let side = testutil::commit(repo, &[&first], "side");
+        let merge = testutil::commit(repo, &[&second, &side], "merge");
+        refs::update(repo, "refs/heads/master", &second, None, "commit").unwrap();
+        refs::update(repo, "refs/heads/master", &merge, None, "merge").unwrap();
+        History { first, second, side, merge }
+    }
+
+    fn parse_ok(repo: &Repository, spec: &str) -> Sha {
+        parse(repo, spec).unwrap_or_else(|e| panic!("{}: {}", spec, e))
+    }
+
+    #[test]
+    fn refs_and_ids() {
+        let (_dir, repo) = testutil::repo();
+        let h = history(&repo);
+        for spec in ["HEAD", "@", "master", "heads/master", "refs/heads/master"].iter() {
+            assert_eq!(parse_ok(&repo, spec), h.merge, "{}", spec);
+        }
+        assert_eq!(parse_ok(&repo, h.side.value()), h.side);
+        assert_eq!(parse_ok(&repo, &h.side.value()[..7].to_uppercase()), h.side);
+        assert!(parse(&repo, "nope").is_err());
+        assert!(parse(&repo, "abc").is_err());
+    }
+
+    #[test]
+    fn navigation_operators() {
+        let (_dir, repo) = testutil::repo();
+        let h = history(&repo);
+        let cases = [
+            ("master^", &h.second), ("master^1", &h.second), ("master^2", &h.side), ("master^0", &h.merge),
+            ("master~", &h.second), ("master~2", &h.first), ("master^^", &h.first), ("master^2~1", &h.first),
+            ("master^{commit}", &h.merge), ("master^{}", &h.merge), ("master^{object}", &h.merge)
+        ];
+        for (spec, expected) in cases.iter() {
+            assert_eq!(&parse_ok(&repo, spec), *expected, "{}", spec);
+        }
+        assert!(parse(&repo, "master^3").is_err());
+        assert!(parse(&repo, "master~3").is_err());
+        assert!(parse(&repo, "master^{blob}").is_err());
+        assert!(parse(&repo, "master^{commit").is_err());
+    }
+
+    #[test]
+    fn annotated_tags_are_peeled() {
+        let (_dir, repo) = testutil::repo();
+        let h = history(&repo);
+        let tag = obj::write(&repo, ObjType::Tag, format!("object {}\ntype commit\ntag v1\n\
+            tagger C O Mitter <committer@example.com> 1700000000 +0000\n\nv1\n", h.merge).as_bytes()).unwrap();
+        refs::update(&repo, "refs/tags/v1", &tag, None, "").unwrap();
+
+        assert_eq!(parse_ok(&repo, "v1"), tag);
+        assert_eq!(parse_ok(&repo, "v1^{tag}"), tag);
+        assert_eq!(parse_ok(&repo, "v1^{}"), h.merge);
+        assert_eq!(parse_ok(&repo, "v1~1"), h.second);
+        assert_eq!(parse_as(&repo, "v1", ObjType::Commit).unwrap(), h.merge);
+    }
+
+    #[test]
+    fn reflog_and_upstream() {
+        let (_dir, repo) = testutil::repo();
+        let h = history(&repo);
+        assert_eq!(parse_ok(&repo, "master@{0}"), h.merge);
+        assert_eq!(parse_ok(&repo, "master@{1}"), h.second);
+        assert_eq!(parse_ok(&repo, "@{1}"), h.second);
+        assert_eq!(parse_ok(&repo, "HEAD@{1}"), h.second);
+        assert!(parse(&repo, "master@{2}").is_err());
+
+        assert!(parse(&repo, "@{u}").is_err());
+        repo.set_config("branch.master.remote", "origin").unwrap();
+        repo.set_config("branch.master.merge", "refs/heads/master").unwrap();
+        assert!(parse(&repo, "@{u}").is_err());
+        refs::update(&repo, "refs/remotes/origin/master", &h.first, None, "").unwrap();
+        assert_eq!(parse_ok(&repo, "@{u}"), h.first);
+        assert_eq!(parse_ok(&repo, "master@{upstream}~0"), h.first);
+
+        repo.set_config("branch.master.remote", ".").unwrap();
+        assert_eq!(parse_ok(&repo, "master@{u}"), h.merge);
+    }
+
+    #[test]
+    fn paths_and_messages() {
+        let (_dir, repo) = testutil::repo();
+        let h = history(&repo);
+        let blob = obj::write(&repo, ObjType::Blob, b"content\n").unwrap();
+        let dir = obj::write(&repo, ObjType::Tree, &obj::encode_tree(&[TreeEntry { mode: 100644, name: "file".to_string(), id: blob.clone() }])).unwrap();
+        let root = obj::write(&repo, ObjType::Tree, &obj::encode_tree(&[TreeEntry { mode: 40000, name: "dir".to_string(), id: dir.clone() }])).unwrap();
+
+        // the same tree as git writes
+        assert_eq!(root.to_string(), "6bc44aa6666b98cdc0eaed3cd47d1c2cea787aec");
+        assert_eq!(parse_ok(&repo, &format!("{}:dir", root)), dir);
+        assert_eq!(parse_ok(&repo, &format!("{}:dir/file", root)), blob);
+        assert_eq!(parse_ok(&repo, &format!("{}:", root)), root);
+        assert!(parse(&repo, &format!("{}:nope", root)).is_err());
+        assert!(parse(&repo, ":file").is_err());
+
+        assert_eq!(parse_ok(&repo, ":/side"), h.side);
+        assert_eq!(parse_ok(&repo, ":/merge"), h.merge);
+        assert!(parse(&repo, ":/nothing").is_err());
+    }
+}
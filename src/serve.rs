@@ -0,0 +1,374 @@
+use std::{collections::HashSet, io::{BufRead, BufReader, Read, Write}, net::{TcpListener, TcpStream}, str, time::Duration};
+use bytes::Bytes;
+use flate2::read::GzDecoder;
+use crate::{obj, pack::{fmt, proto}, refs::{self, Ref, Value, NULL_ID}, repo::Repository, sha::Sha};
+
+use crate::error::R;
+
+// Limits for what a client may send, so that a single request can't exhaust memory or tie up
+// the server, which handles one connection at a time.
+const MAX_LINE_LEN: u64 = 8 * 1024;
+const MAX_BODY_LEN: usize = 1024 * 1024 * 1024;
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+struct Request {
+    method: String,
+    path: String,
+    query: String,
+    body: Vec<u8>
+}
+
+struct Response {
+    status: &'static str,
+    content_type: String,
+    body: Vec<u8>
+}
+
+impl Response {
+    fn ok(content_type: &str, body: Vec<u8>) -> Response {
+        Response { status: "200 OK", content_type: content_type.to_string(), body }
+    }
+
+    fn error(status: &'static str, msg: &str) -> Response {
+        Response { status, content_type: "text/plain".to_string(), body: format!("{}\n", msg).into_bytes() }
+    }
+}
+
+// Serves the repository over the smart HTTP protocol. The path prefix of requests is ignored,
+// so the repository can be reached through any URL on the given address.
+pub fn run(repo: &Repository, addr: &str) -> R<()> {
+    let listener = TcpListener::bind(addr)
+        .map_err(|e| format!("Failed to listen on {}: {}", addr, e))?;
+    println!("Serving {:?} on http://{}/", repo.git_dir(), listener.local_addr()?);
+
+    for conn in listener.incoming() {
+        let mut conn = conn?;
+        conn.set_read_timeout(Some(TIMEOUT))?;
+        conn.set_write_timeout(Some(TIMEOUT))?;
+        let response = match read_request(&mut conn) {
+            Ok(request) => {
+                // every request gets a fresh handle, as pushes change refs and objects
+                let response = handle(&repo.reopen(), &request).unwrap_or_else(|e| Response::error("500 Internal Server Error", &e.to_string()));
+                println!("{} {} {}", request.method, request.path, response.status);
+                response
+            },
+            Err(e) => Response::error("400 Bad Request", &e.to_string())
+        };
+
+        if let Err(e) = write_response(&mut conn, response) {
+            println!("Failed to send response: {}", e);
+        }
+    }
+
+    Ok(())
+}
+
+fn handle(repo: &Repository, request: &Request) -> R<Response> {
+    let service = request.query.split('&')
+        .find_map(|param| param.strip_prefix("service="))
+        .unwrap_or_default();
+
+    match request.method.as_str() {
+        "GET" if request.path.ends_with("/info/refs") => match service {
+            "git-upload-pack" |
+            "git-receive-pack" => Ok(Response::ok(
+                &format!("application/x-{}-advertisement", service),
+                advertise_refs(repo, service)?)),
+            _ => Ok(Response::error("403 Forbidden", "Only the smart HTTP protocol is supported."))
+        },
+        "POST" if request.path.ends_with("/git-upload-pack") => Ok(Response::ok(
+            "application/x-git-upload-pack-result",
+            upload_pack(repo, Bytes::from(request.body.clone()))?)),
+        "POST" if request.path.ends_with("/git-receive-pack") => Ok(Response::ok(
+            "application/x-git-receive-pack-result",
+            receive_pack(repo, Bytes::from(request.body.clone()))?)),
+        _ => Ok(Response::error("404 Not Found", "Not found."))
+    }
+}
+
+fn advertise_refs(repo: &Repository, service: &str) -> R<Vec<u8>> {
+    let mut refs = refs::list(repo, "refs/")?;
+    let mut caps = vec![format!("agent=git/{}", env!("CARGO_PKG_VERSION"))];
+
+    if service == "git-upload-pack" {
+        if let Some(Value::Symbolic(target)) = refs::read(repo, "HEAD")? {
+            if let Some(head) = refs.iter().find(|r| r.name == target).cloned() {
+                caps.push(format!("symref=HEAD:{}", target));
+                refs.insert(0, Ref { id: head.id, name: "HEAD".to_string() });
+            }
+        }
+    } else {
+        caps.push("report-status".to_string());
+        caps.push("delete-refs".to_string());
+    }
+
+    let mut adv = proto::pkt_line(&format!("# service={}\n", service));
+    adv.push_str("0000");
+
+    if refs.is_empty() {
+        adv.push_str(&proto::pkt_line(&format!("{} capabilities^{{}}\0{}\n", NULL_ID, caps.join(" "))));
+    }
+    for (i, Ref { id, name }) in refs.iter().enumerate() {
+        let line = match i {
+            0 => format!("{} {}\0{}\n", id, name, caps.join(" ")),
+            _ => format!("{} {}\n", id, name)
+        };
+        adv.push_str(&proto::pkt_line(&line));
+    }
+    adv.push_str("0000");
+
+    Ok(adv.into_bytes())
+}
+
+// Negotiation is stateless: each request repeats all wants and the haves sent so far. The pack
+// is only sent once the client is `done`.
+fn upload_pack(repo: &Repository, mut body: Bytes) -> R<Vec<u8>> {
+    let mut wants = Vec::new();
+    let mut haves = Vec::new();
+    let mut done = false;
+
+    while !body.is_empty() {
+        let line = proto::parse_pkt_line(&mut body)?;
+        if let Some(want) = line.strip_prefix("want ") {
+            wants.push(Sha::from_str(want.get(..40).ok_or("Invalid want line.")?)?);
+        } else if let Some(have) = line.strip_prefix("have ") {
+            haves.push(Sha::from_str(have.get(..40).ok_or("Invalid have line.")?)?);
+        } else if line == "done" {
+            done = true;
+        }
+    }
+
+    let mut common = Vec::new();
+    for have in haves {
+        if obj::exists(repo, &have)? { common.push(have); }
+    }
+
+    let mut response = match common.first() {
+        Some(id) => proto::pkt_line(&format!("ACK {}\n", id)),
+        None     => proto::pkt_line("NAK\n")
+    }.into_bytes();
+
+    if done {
+        let known = obj::reachable(repo, &common, &HashSet::new())?.into_iter().collect();
+        let objs = obj::reachable(repo, &wants, &known)?;
+        response.extend(fmt::write_pack(repo, &objs)?);
+    }
+
+    Ok(response)
+}
+
+fn receive_pack(repo: &Repository, mut body: Bytes) -> R<Vec<u8>> {
+    let mut commands = Vec::new();
+    let mut report_status = false;
+
+    loop {
+        let line = proto::parse_pkt_line(&mut body)?;
+        if line.is_empty() { break; }
+
+        let mut line = line.splitn(2, '\0');
+        let mut cmd = line.next().unwrap().split(' ');
+        if let Some(caps) = line.next() {
+            report_status = caps.split(' ').any(|c| c == "report-status");
+        }
+
+        let old = cmd.next().ok_or("Invalid ref update command.")?.to_string();
+        let new = cmd.next().ok_or("Invalid ref update command.")?.to_string();
+        let name = cmd.next().ok_or("Invalid ref update command.")?.to_string();
+        commands.push((old, new, name));
+    }
+
+    let unpack_result = if body.is_empty() {
+        Ok(())
+    } else {
+        unpack(repo, body)
+    };
+
+    let mut report = match &unpack_result {
+        Ok(_)  => proto::pkt_line("unpack ok\n"),
+        Err(e) => proto::pkt_line(&format!("unpack {}\n", e))
+    };
+
+    for (old, new, name) in commands {
+        let result = match unpack_result {
+            Ok(_)  => update_ref(repo, &old, &new, &name),
+            Err(_) => Err("unpacker error".into())
+        };
+        report.push_str(&match result {
+            Ok(_)  => proto::pkt_line(&format!("ok {}\n", name)),
+            Err(e) => proto::pkt_line(&format!("ng {} {}\n", name, e))
+        });
+    }
+    report.push_str("0000");
+
+    Ok(if report_status { report.into_bytes() } else { Vec::new() })
+}
+
+fn unpack(repo: &Repository, mut pack: Bytes) -> R<()> {
+    let expected_objs = fmt::parse_header(&mut pack)? as usize;
+    let objs = fmt::unpack_objects(repo, &mut pack)?;
+    if objs != expected_objs {
+        return Err(format!("expected {} objects but found {}", expected_objs, objs).into());
+    }
+    Ok(())
+}
+
+fn update_ref(repo: &Repository, old: &str, new: &str, name: &str) -> R<()> {
+    // only refs may be pushed to, not HEAD or other files of the git dir
+    if !name.starts_with("refs/") || refs::check_name(name).is_err() {
+        return Err("funny refname".into());
+    }
+    let old = Sha::from_str(old)?;
+    let new = Sha::from_str(new)?;
+
+    let checked_out = match refs::read(repo, "HEAD")? {
+        Some(Value::Symbolic(target)) => target == name,
+        _                             => false
+    };
+    if checked_out && !repo.is_bare()? {
+        return Err("branch is currently checked out".into());
+    }
+
+    if new.value() == NULL_ID {
+        refs::delete(repo, name, Some(&old))
+    } else if !obj::exists(repo, &new)? {
+        Err("missing necessary objects".into())
+    } else {
+        refs::update(repo, name, &new, Some(&old), "push")
+    }
+}
+
+fn read_request<T: Read>(conn: T) -> R<Request> {
+    let mut input = BufReader::new(conn);
+
+    let request_line = read_line(&mut input)?;
+    let mut request_line = request_line.split_whitespace();
+    let method = request_line.next().ok_or("Missing request method.")?.to_string();
+    let target = request_line.next().ok_or("Missing request target.")?;
+    let mut target = target.splitn(2, '?');
+    let path = target.next().unwrap().to_string();
+    let query = target.next().unwrap_or_default().to_string();
+
+    let mut content_len = 0;
+    let mut chunked = false;
+    let mut gzipped = false;
+    loop {
+        let header = read_line(&mut input)?;
+        if header.is_empty() { break; }
+
+        let mut header = header.splitn(2, ':');
+        let name = header.next().unwrap().trim().to_lowercase();
+        let value = header.next().unwrap_or_default().trim().to_lowercase();
+        match name.as_str() {
+            "content-length"    => content_len = value.parse()?,
+            "transfer-encoding" => chunked = value == "chunked",
+            "content-encoding"  => gzipped = value == "gzip",
+            _                   => {}
+        }
+    }
+
+    let mut body = Vec::new();
+    if chunked {
+        loop {
+            let chunk_len = read_line(&mut input)?;
+            let chunk_len = chunk_len.split(';').next().unwrap();
+            let chunk_len = usize::from_str_radix(chunk_len, 16)
+                .map_err(|e| format!("Invalid chunk length '{}': {}", chunk_len, e))?;
+
+            read_body(&mut input, chunk_len, &mut body)?;
+            let mut crlf = [0; 2]; // chunk is terminated by CRLF
+            input.read_exact(&mut crlf)?;
+            if chunk_len == 0 { break; }
+        }
+    } else {
+        read_body(&mut input, content_len, &mut body)?;
+    }
+
+    if gzipped {
+        let mut inflated = Vec::new();
+        GzDecoder::new(body.as_slice()).take(MAX_BODY_LEN as u64 + 1).read_to_end(&mut inflated)
+            .map_err(|e| format!("Failed to decompress request body: {}", e))?;
+        if inflated.len() > MAX_BODY_LEN {
+            return Err("Request body is too large.".into());
+        }
+        body = inflated;
+    }
+
+    Ok(Request { method, path, query, body })
+}
+
+// A line without its line ending.
+fn read_line<T: BufRead>(input: &mut T) -> R<String> {
+    let mut line = String::new();
+    input.take(MAX_LINE_LEN).read_line(&mut line)?;
+    if !line.ends_with('\n') {
+        return Err("Request line is too long or incomplete.".into());
+    }
+    Ok(line.trim_end().to_string())
+}
+
+// Appends `len` bytes to `body`, which grows as data arrives rather than trusting the length
+// the client announced.
+fn read_body<T: Read>(input: &mut T, len: usize, body: &mut Vec<u8>) -> R<()> {
+    let total = body.len().checked_add(len)
+        .filter(|total| *total <= MAX_BODY_LEN)
+        .ok_or("Request body is too large.")?;
+    input.take(len as u64).read_to_end(body)?;
+    if body.len() != total {
+        return Err("Request body is incomplete.".into());
+    }
+    Ok(())
+}
+
+fn write_response(conn: &mut TcpStream, response: Response) -> R<()> {
+    write!(conn,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n",
+        response.status, response.content_type, response.body.len())?;
+    conn.write_all(&response.body)?;
+    conn.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use flate2::{write::GzEncoder, Compression};
+    use super::*;
+
+    #[test]
+    fn read_request_with_content_length() {
+        let request = read_request(&b"POST /repo/git-upload-pack?x=1 HTTP/1.1\r\nContent-Length: 4\r\n\r\nbody"[..]).unwrap();
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.path, "/repo/git-upload-pack");
+        assert_eq!(request.query, "x=1");
+        assert_eq!(request.body, b"body");
+    }
+
+    #[test]
+    fn read_request_chunked() {
+        let request = read_request(&b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n3;ext\r\nabc\r\n2\r\nde\r\n0\r\n\r\n"[..]).unwrap();
+        assert_eq!(request.body, b"abcde");
+    }
+
+    #[test]
+    fn read_request_gzipped() {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(b"0009done\n").unwrap();
+        let body = encoder.finish().unwrap();
+
+        let mut input = format!("POST / HTTP/1.1\r\nContent-Encoding: gzip\r\nContent-Length: {}\r\n\r\n", body.len()).into_bytes();
+        input.extend(body);
+        assert_eq!(read_request(input.as_slice()).unwrap().body, b"0009done\n");
+    }
+
+    #[test]
+    fn read_request_rejects_huge_lengths() {
+        assert!(read_request(&b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\nffffffffffffffff\r\n"[..]).is_err());
+        assert!(read_request(&b"POST / HTTP/1.1\r\nContent-Length: 18446744073709551615\r\n\r\n"[..]).is_err());
+    }
+
+    #[test]
+    fn read_request_rejects_incomplete_input() {
+        assert!(read_request(&b"POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\nabc"[..]).is_err());
+        assert!(read_request(&b"GET / HTTP/1.1\r\nHost: exam"[..]).is_err());
+        assert!(read_request(vec![b'a'; 2 * MAX_LINE_LEN as usize].as_slice()).is_err());
+    }
+}
@@ -2,7 +2,7 @@ use std::{convert::{Into, TryInto, TryFrom}, fmt::{Display, Error, Formatter}, r
 use sha1::{Sha1, Digest};
 use crate::util;
 
-type R<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+use crate::error::R;
 
 #[derive(PartialEq,Eq,Hash,Debug)]
 pub struct Sha(String);
@@ -29,13 +29,15 @@ impl Sha {
             .expect("Failed to generate raw SHA.")
     }
 
+    // Ids are kept in lower case, so that they compare equal however they were written.
     pub fn from_str(s: &str) -> R<Sha> {
         Sha::validate(s)?;
-        Ok(Sha(s.to_string()))
+        Ok(Sha(s.to_ascii_lowercase()))
     }
 
-    pub fn from_string(s: String) -> R<Sha> {
+    pub fn from_string(mut s: String) -> R<Sha> {
         Sha::validate(&s)?;
+        s.make_ascii_lowercase();
         Ok(Sha(s))
     }
 
@@ -46,7 +48,8 @@ impl Sha {
 
     pub fn validate(s: &str) -> R<()> {
         match s.len() {
-            40 => Ok(()),
+            40 if s.chars().all(|c| c.is_ascii_hexdigit()) => Ok(()),
+            40 => Err(format!("String '{}' is not a valid SHA. Expected hexadecimal digits.", s).into()),
             _  => Err(format!("String '{}' is not a valid SHA. Expected 40 characters.", s).into())
         }
     }
@@ -60,7 +63,7 @@ impl Sha {
 }
 
 impl TryFrom<String> for Sha {
-    type Error = std::boxed::Box<dyn std::error::Error>;
+    type Error = crate::error::Error;
     fn try_from(s: String) -> Result<Self, <Self as TryFrom<String>>::Error> {
         Sha::from_string(s)
     }
@@ -85,3 +88,34 @@ impl Display for Sha {
         write!(f, "{}", self.value())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ID: &str = "da39a3ee5e6b4b0d3255bfef95601890afd80709";
+
+    #[test]
+    fn validate_ids() {
+        assert!(Sha::validate(ID).is_ok());
+        assert!(Sha::validate(&ID[1..]).is_err());
+        assert!(Sha::validate(&format!("{}0", ID)).is_err());
+        assert!(Sha::validate(&"g".repeat(40)).is_err());
+        assert!(Sha::validate(&"\u{e9}".repeat(20)).is_err());
+    }
+
+    #[test]
+    fn ids_are_lower_case() {
+        assert_eq!(Sha::from_str(&ID.to_uppercase()).unwrap().value(), ID);
+        assert_eq!(Sha::from_string(ID.to_uppercase()).unwrap(), Sha::from_str(ID).unwrap());
+    }
+
+    #[test]
+    fn bytes_round_trip() {
+        let id = Sha::generate(b"");
+        assert_eq!(id.value(), ID);
+        assert_eq!(Sha::from_bytes(&id.to_bytes()).unwrap(), id);
+        assert_eq!(Sha::generate_raw(b""), id.to_bytes());
+        assert!(Sha::from_bytes(&[0; 19]).is_err());
+    }
+}
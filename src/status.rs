@@ -0,0 +1,442 @@
+use std::{collections::{BTreeSet, HashSet}, fs, path::Path};
+use crate::{ignore::Ignore, index::{self, Entry, Index}, obj::{self, ObjType}, pathspec::Pathspec, refs::{self, Value}, repo::Repository, rev, sha::Sha, wtree};
+
+use crate::error::R;
+
+const MODE_TYPE: u32 = 0o170000;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Change {
+    Added,
+    Modified,
+    Deleted,
+    TypeChanged
+}
+
+impl Change {
+    pub fn code(&self) -> char {
+        match self {
+            Change::Added       => 'A',
+            Change::Modified    => 'M',
+            Change::Deleted     => 'D',
+            Change::TypeChanged => 'T'
+        }
+    }
+
+    fn label(&self) -> &str {
+        match self {
+            Change::Added       => "new file:",
+            Change::Modified    => "modified:",
+            Change::Deleted     => "deleted:",
+            Change::TypeChanged => "typechange:"
+        }
+    }
+}
+
+// A file as recorded in HEAD or in one stage of the index.
+#[derive(Clone, Debug)]
+pub struct Version {
+    pub mode: u32,
+    pub id: Sha
+}
+
+// A path that is changed between HEAD and the index, or between the index and the work tree.
+#[derive(Debug)]
+pub struct FileStatus {
+    pub path: String,
+    pub head: Option<Version>,
+    pub index: Option<Version>,
+    pub work_tree_mode: Option<u32>,
+    pub staged: Option<Change>,
+    pub unstaged: Option<Change>
+}
+
+// A path with unmerged stages: the common ancestor, ours and theirs.
+#[derive(Debug)]
+pub struct Conflict {
+    pub path: String,
+    pub stages: [Option<Version>; 3],
+    pub work_tree_mode: Option<u32>
+}
+
+#[derive(Debug)]
+pub struct Status {
+    pub branch: Option<String>,
+    pub commit: Option<Sha>,
+    pub changes: Vec<FileStatus>,
+    pub conflicts: Vec<Conflict>,
+    pub untracked: Vec<String>
+}
+
+impl Status {
+    fn has_staged(&self) -> bool {
+        self.changes.iter().any(|c| c.staged.is_some())
+    }
+
+    fn has_unstaged(&self) -> bool {
+        self.changes.iter().any(|c| c.unstaged.is_some())
+    }
+}
+
+// Compares the tree of HEAD with the index and the index with the work tree for the paths
+// selected by the pathspec. Files whose stat data matches their index entry are taken as unchanged
+// without hashing them.
+pub fn get(repo: &Repository, pathspec: &Pathspec) -> R<Status> {
+    let work_tree = repo.work_tree()?;
+    let (branch, commit) = match refs::read(repo, "HEAD")? {
+        Some(Value::Symbolic(target)) => (Some(target), refs::resolve(repo, "HEAD")?),
+        Some(Value::Direct(id))       => (None, Some(id)),
+        None                          => return Err("HEAD is missing.".into())
+    };
+    let head = match &commit {
+        Some(commit) => Index::from_tree(repo, &rev::peel(repo, commit, ObjType::Tree)?)?,
+        None         => Index::default()
+    };
+    let index = index::read(repo)?;
+
+    let paths = head.entries.iter().chain(&index.entries)
+        .map(|e| e.path.as_str())
+        .collect::<BTreeSet<_>>();
+    let mut changes = Vec::new();
+    let mut conflicts = Vec::new();
+    for path in paths {
+        if !pathspec.matches(path)? {
+            continue;
+        }
+        let stages = index.entries.iter().filter(|e| e.path == path && e.stage > 0).collect::<Vec<_>>();
+        if !stages.is_empty() {
+            let mut versions = [None, None, None];
+            for e in stages {
+                versions[e.stage as usize - 1] = Some(version(e));
+            }
+            let work_tree_mode = work_tree_mode(work_tree, path);
+            conflicts.push(Conflict { path: path.to_string(), stages: versions, work_tree_mode });
+            continue;
+        }
+
+        let in_head = head.find(path).map(version);
+        let entry = index.find(path);
+        // intent-to-add entries only reserve the path, their content is not staged yet
+        let in_index = entry.filter(|e| !e.intent_to_add).map(version);
+        let staged = compare(in_head.as_ref(), in_index.as_ref());
+        let unstaged = match entry {
+            Some(e) => compare_work_tree(repo, &index, e)?,
+            None    => None
+        };
+        if staged.is_some() || unstaged.is_some() {
+            // the work tree is only looked at for files in the index
+            let work_tree_mode = entry.and_then(|_| work_tree_mode(work_tree, path));
+            changes.push(FileStatus { path: path.to_string(), head: in_head, index: in_index, work_tree_mode, staged, unstaged });
+        }
+    }
+
+    let files = wtree::walk(work_tree, pathspec, Some(&Ignore::load(repo)?))?;
+    let untracked = untracked(&index, files, |path| work_tree.join(path).is_dir());
+    Ok(Status { branch, commit, changes, conflicts, untracked })
+}
+
+fn work_tree_mode(work_tree: &Path, path: &str) -> Option<u32> {
+    fs::symlink_metadata(work_tree.join(path)).ok().map(|meta| index::mode_of(&meta))
+}
+
+fn version(entry: &Entry) -> Version {
+    Version { mode: entry.mode, id: entry.id.clone() }
+}
+
+fn compare(old: Option<&Version>, new: Option<&Version>) -> Option<Change> {
+    match (old, new) {
+        (None, None)       => None,
+        (None, Some(_))    => Some(Change::Added),
+        (Some(_), None)    => Some(Change::Deleted),
+        (Some(old), Some(new)) if old.mode & MODE_TYPE != new.mode & MODE_TYPE => Some(Change::TypeChanged),
+        (Some(old), Some(new)) if old.mode != new.mode || old.id != new.id     => Some(Change::Modified),
+        _                  => None
+    }
+}
+
+fn compare_work_tree(repo: &Repository, index: &Index, entry: &Entry) -> R<Option<Change>> {
+    let work_tree = repo.work_tree()?;
+    let file = work_tree.join(&entry.path);
+    let meta = match fs::symlink_metadata(&file) {
+        Ok(meta) => meta,
+        Err(_)   => return Ok(Some(Change::Deleted))
+    };
+    let mode = index::mode_of(&meta);
+    if entry.intent_to_add {
+        Ok(Some(Change::Added))
+    } else if meta.is_dir() && entry.mode != index::MODE_GITLINK && !file.join(".git").exists() {
+        Ok(Some(Change::Deleted)) // the file was replaced by a dir, which is untracked
+    } else if mode & MODE_TYPE != entry.mode & MODE_TYPE {
+        Ok(Some(Change::TypeChanged))
+    } else if wtree::is_modified(repo, work_tree, index, entry)? {
+        Ok(Some(Change::Modified))
+    } else {
+        Ok(None)
+    }
+}
+
+// The files in the work tree that are not in the index. Dirs without any tracked files in them are
+// listed once with a trailing slash instead of listing all their files.
+fn untracked(index: &Index, files: Vec<String>, is_dir: impl Fn(&str) -> bool) -> Vec<String> {
+    let tracked = index.entries.iter().map(|e| e.path.as_str()).collect::<HashSet<_>>();
+    let tracked_dirs = index.entries.iter()
+        .flat_map(|e| e.path.match_indices('/').map(move |(i, _)| &e.path[..i]))
+        .collect::<HashSet<_>>();
+
+    let mut untracked = Vec::<String>::new();
+    for file in files.iter().filter(|f| !tracked.contains(f.as_str())) {
+        let path = file.match_indices('/')
+            .map(|(i, _)| &file[..i])
+            .find(|dir| !tracked_dirs.contains(dir))
+            .map(|dir| format!("{}/", dir))
+            .unwrap_or_else(|| if is_dir(file) { format!("{}/", file) } else { file.clone() });
+        if untracked.last() != Some(&path) {
+            untracked.push(path);
+        }
+    }
+    untracked
+}
+
+// The output of `git status` without the hints. Paths are shown relative to `prefix`, the current
+// dir within the work tree.
+pub fn print_long(status: &Status, prefix: &str) -> String {
+    let mut output = String::new();
+    match (&status.branch, &status.commit) {
+        (Some(branch), _) => output.push_str(&format!("On branch {}\n", refs::shorten(branch))),
+        (None, Some(id))  => output.push_str(&format!("HEAD detached at {}\n", &id.value()[..obj::DEFAULT_ABBREV])),
+        (None, None)      => {}
+    }
+    if status.commit.is_none() {
+        output.push_str("\nNo commits yet\n\n");
+    }
+
+    let mut section = |title: &str, lines: Vec<String>| {
+        if !lines.is_empty() {
+            output.push_str(&format!("{}:\n", title));
+            for line in lines {
+                output.push_str(&format!("\t{}\n", line));
+            }
+            output.push('\n');
+        }
+    };
+    section("Changes to be committed", status.changes.iter()
+        .filter_map(|c| c.staged.map(|change| format!("{:<12}{}", change.label(), display_path(prefix, &c.path))))
+        .collect());
+    section("Unmerged paths", status.conflicts.iter()
+        .map(|c| format!("{:<17}{}", format!("{}:", conflict_label(c)), display_path(prefix, &c.path)))
+        .collect());
+    section("Changes not staged for commit", status.changes.iter()
+        .filter_map(|c| c.unstaged.map(|change| format!("{:<12}{}", change.label(), display_path(prefix, &c.path))))
+        .collect());
+    section("Untracked files", status.untracked.iter().map(|path| display_path(prefix, path)).collect());
+
+    if !status.has_staged() {
+        output.push_str(if status.has_unstaged() || !status.conflicts.is_empty() {
+            "no changes added to commit (use \"git add\" and/or \"git commit -a\")\n"
+        } else if !status.untracked.is_empty() {
+            "nothing added to commit but untracked files present (use \"git add\" to track)\n"
+        } else if status.commit.is_none() {
+            "nothing to commit (create/copy files and use \"git add\" to track)\n"
+        } else {
+            "nothing to commit, working tree clean\n"
+        });
+    }
+    output
+}
+
+// `XY path` lines as printed by `--short`, or by `--porcelain=v1` with an empty prefix.
+pub fn print_short(status: &Status, prefix: &str) -> String {
+    let mut lines = status.changes.iter()
+        .map(|c| (c.path.as_str(), format!("{}{}", code(c.staged, ' '), code(c.unstaged, ' '))))
+        .chain(status.conflicts.iter().map(|c| (c.path.as_str(), conflict_code(c).to_string())))
+        .collect::<Vec<_>>();
+    lines.sort();
+
+    lines.into_iter()
+        .map(|(path, xy)| format!("{} {}\n", xy, display_path(prefix, path)))
+        .chain(status.untracked.iter().map(|path| format!("?? {}\n", display_path(prefix, path))))
+        .collect()
+}
+
+// The lines of `--porcelain=v2`, which carry the modes and ids of HEAD and the index as well.
+// Unlike v1, paths are relative to the current dir.
+pub fn print_porcelain_v2(status: &Status, prefix: &str) -> String {
+    fn mode(version: Option<&Version>) -> String {
+        format!("{:06o}", version.map_or(0, |v| v.mode))
+    }
+    fn id(version: Option<&Version>) -> String {
+        version.map_or_else(|| "0".repeat(40), |v| v.id.to_string())
+    }
+    fn submodule(mode: u32, unstaged: Option<Change>) -> &'static str {
+        match unstaged {
+            _ if mode != index::MODE_GITLINK => "N...",
+            Some(Change::Modified)           => "SC..",
+            _                                => "S..."
+        }
+    }
+
+    let mut lines = Vec::new();
+    for c in &status.changes {
+        let mode_of_index = c.index.as_ref().or(c.head.as_ref()).map_or(0, |v| v.mode);
+        lines.push((c.path.clone(), format!("1 {}{} {} {} {} {:06o} {} {} {}\n",
+            code(c.staged, '.'), code(c.unstaged, '.'), submodule(mode_of_index, c.unstaged),
+            mode(c.head.as_ref()), mode(c.index.as_ref()), c.work_tree_mode.unwrap_or(0),
+            id(c.head.as_ref()), id(c.index.as_ref()), display_path(prefix, &c.path))));
+    }
+    for c in &status.conflicts {
+        let [base, ours, theirs] = &c.stages;
+        let mode_of_index = ours.as_ref().or(theirs.as_ref()).or(base.as_ref()).map_or(0, |v| v.mode);
+        lines.push((c.path.clone(), format!("u {} {} {} {} {} {:06o} {} {} {} {}\n",
+            conflict_code(c), submodule(mode_of_index, None),
+            mode(base.as_ref()), mode(ours.as_ref()), mode(theirs.as_ref()), c.work_tree_mode.unwrap_or(0),
+            id(base.as_ref()), id(ours.as_ref()), id(theirs.as_ref()), display_path(prefix, &c.path))));
+    }
+    lines.sort();
+
+    lines.into_iter()
+        .map(|(_, line)| line)
+        .chain(status.untracked.iter().map(|path| format!("? {}\n", display_path(prefix, path))))
+        .collect()
+}
+
+fn code(change: Option<Change>, unchanged: char) -> char {
+    change.map_or(unchanged, |c| c.code())
+}
+
+// Which sides added, deleted or modified the path, as told by the stages present.
+fn conflict_code(conflict: &Conflict) -> &'static str {
+    match conflict.stages.each_ref().map(Option::is_some) {
+        [true, true, true]   => "UU",
+        [false, true, true]  => "AA",
+        [true, false, false] => "DD",
+        [false, true, false] => "AU",
+        [true, true, false]  => "UD",
+        [true, false, true]  => "DU",
+        _                    => "UA"
+    }
+}
+
+fn conflict_label(conflict: &Conflict) -> &'static str {
+    match conflict_code(conflict) {
+        "UU" => "both modified",
+        "AA" => "both added",
+        "DD" => "both deleted",
+        "AU" => "added by us",
+        "UD" => "deleted by them",
+        "DU" => "deleted by us",
+        _    => "added by them"
+    }
+}
+
+// The path relative to the dir `prefix`, both given relative to the work tree.
+pub fn display_path(prefix: &str, path: &str) -> String {
+    let dir = path.ends_with('/');
+    let prefix = prefix.split('/').filter(|c| !c.is_empty()).collect::<Vec<_>>();
+    let components = path.split('/').filter(|c| !c.is_empty()).collect::<Vec<_>>();
+    let common = prefix.iter().zip(&components).take_while(|(a, b)| a == b).count();
+
+    let mut relative = vec![".."; prefix.len() - common];
+    relative.extend(&components[common..]);
+    let mut relative = relative.join("/");
+    if relative.is_empty() {
+        relative.push('.');
+    }
+    if dir {
+        relative.push('/');
+    }
+    relative
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::symlink;
+    use crate::testutil;
+    use super::*;
+
+    fn status(repo: &Repository) -> Status {
+        get(repo, &Pathspec::parse(repo, &[]).unwrap()).unwrap()
+    }
+
+    fn repo() -> (testutil::TempDir, Repository) {
+        let (dir, repo) = testutil::repo();
+        repo.set_config("core.excludesFile", "/dev/null").unwrap();
+        (dir, repo)
+    }
+
+    #[test]
+    fn empty_repository() {
+        let (_dir, repo) = repo();
+        assert_eq!(print_long(&status(&repo), ""), "On branch master\n\nNo commits yet\n\n\
+            nothing to commit (create/copy files and use \"git add\" to track)\n");
+    }
+
+    #[test]
+    fn staged_unstaged_and_untracked() {
+        let (dir, repo) = repo();
+        let work_tree = dir.path();
+        testutil::commit_files(&repo, &[("a.txt", "a\n"), ("b.txt", "b\n"), ("dir/c.txt", "c\n"), ("link", "x")], "first");
+        assert_eq!(print_long(&status(&repo), ""), "On branch master\nnothing to commit, working tree clean\n");
+
+        let mut index = index::read(&repo).unwrap();
+        fs::write(work_tree.join("a.txt"), "staged\n").unwrap();
+        fs::write(work_tree.join("n.txt"), "new\n").unwrap();
+        wtree::stage(&repo, work_tree, &mut index, "a.txt").unwrap();
+        wtree::stage(&repo, work_tree, &mut index, "n.txt").unwrap();
+        index.write(&repo).unwrap();
+        fs::write(work_tree.join("a.txt"), "unstaged\n").unwrap();
+        fs::write(work_tree.join("b.txt"), "changed\n").unwrap();
+        fs::remove_file(work_tree.join("dir/c.txt")).unwrap();
+        fs::remove_file(work_tree.join("link")).unwrap();
+        symlink("a.txt", work_tree.join("link")).unwrap();
+        fs::create_dir_all(work_tree.join("new")).unwrap();
+        fs::write(work_tree.join("new/x.txt"), "").unwrap();
+        fs::write(work_tree.join("u.txt"), "").unwrap();
+
+        let status = status(&repo);
+        assert_eq!(print_short(&status, ""), "MM a.txt\n M b.txt\n D dir/c.txt\n T link\nA  n.txt\n?? new/\n?? u.txt\n");
+        assert_eq!(print_short(&status, "dir"), "MM ../a.txt\n M ../b.txt\n D c.txt\n T ../link\nA  ../n.txt\n?? ../new/\n?? ../u.txt\n");
+        assert_eq!(print_long(&status, ""), "On branch master\n\
+            Changes to be committed:\n\tmodified:   a.txt\n\tnew file:   n.txt\n\n\
+            Changes not staged for commit:\n\tmodified:   a.txt\n\tmodified:   b.txt\n\tdeleted:    dir/c.txt\n\ttypechange: link\n\n\
+            Untracked files:\n\tnew/\n\tu.txt\n\n");
+
+        let a = status.changes.iter().find(|c| c.path == "a.txt").unwrap();
+        assert_eq!(print_porcelain_v2(&status, "").lines().next().unwrap(), format!("1 MM N... 100644 100644 100644 {} {} a.txt",
+            a.head.as_ref().unwrap().id, obj::hash(ObjType::Blob, b"staged\n")));
+    }
+
+    #[test]
+    fn conflicts() {
+        let (_dir, repo) = repo();
+        let id = obj::hash(ObjType::Blob, b"");
+        let mut index = Index::default();
+        for (path, stages) in [("both.txt", &[1, 2, 3][..]), ("ours.txt", &[2][..]), ("theirs.txt", &[1, 3][..])].iter() {
+            for stage in *stages {
+                index.entries.push(Entry { stage: *stage, ..Entry::new(path, id.clone(), index::MODE_FILE) });
+            }
+        }
+        index.write(&repo).unwrap();
+
+        let status = status(&repo);
+        assert_eq!(print_short(&status, ""), "UU both.txt\nAU ours.txt\nDU theirs.txt\n");
+        assert!(print_long(&status, "").contains("Unmerged paths:\n\tboth modified:   both.txt\n\tadded by us:     ours.txt\n\tdeleted by us:   theirs.txt\n\n"));
+        assert!(print_porcelain_v2(&status, "").starts_with(&format!("u UU N... 100644 100644 100644 000000 {0} {0} {0} both.txt\n", id)));
+    }
+
+    #[test]
+    fn untracked_dirs_are_listed_once() {
+        let mut index = Index::default();
+        index.add(Entry::new("a/tracked.txt", obj::hash(ObjType::Blob, b""), index::MODE_FILE));
+        let files = ["a/b/c.txt", "a/b/d.txt", "a/new.txt", "a/tracked.txt", "nested", "x/y.txt"].iter().map(|f| f.to_string()).collect();
+        assert_eq!(untracked(&index, files, |path| path == "nested"), ["a/b/", "a/new.txt", "nested/", "x/"]);
+    }
+
+    #[test]
+    fn display_paths() {
+        assert_eq!(display_path("", "a/b.txt"), "a/b.txt");
+        assert_eq!(display_path("a", "a/b.txt"), "b.txt");
+        assert_eq!(display_path("a/c/", "a/b.txt"), "../b.txt");
+        assert_eq!(display_path("a", "a/"), "./");
+        assert_eq!(display_path("b", "a/"), "../a/");
+    }
+}
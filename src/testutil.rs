@@ -0,0 +1,79 @@
+use std::{env, fs, path::{Path, PathBuf}, process, sync::{atomic::{AtomicUsize, Ordering}, Once}};
+use crate::{index, obj::{self, ObjType}, refs, repo::Repository, sha::Sha, wtree};
+
+// A directory for a single test, removed again when the test is done with it.
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new() -> TempDir {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let dir = env::temp_dir().join(format!("git-starter-rust-test-{}-{}", process::id(), COUNT.fetch_add(1, Ordering::SeqCst)));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        TempDir(dir)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+// Keeps the system and global config of the machine out of the tests. All tests set the same
+// values, so it doesn't matter that they run in parallel.
+pub fn isolate() {
+    static ISOLATE: Once = Once::new();
+    ISOLATE.call_once(|| {
+        env::set_var("GIT_CONFIG_NOSYSTEM", "1");
+        env::set_var("GIT_CONFIG_GLOBAL", "/dev/null");
+    });
+}
+
+// A fresh repository with a work tree. The dir must outlive the repository.
+pub fn repo() -> (TempDir, Repository) {
+    isolate();
+    let dir = TempDir::new();
+    let repo = Repository::init(dir.path(), false).unwrap();
+    (dir, repo)
+}
+
+// Writes a commit of an empty tree with a fixed identity and date, so that its id is stable.
+pub fn commit(repo: &Repository, parents: &[&Sha], message: &str) -> Sha {
+    let tree = obj::write(repo, ObjType::Tree, &[]).unwrap();
+    commit_tree(repo, &tree, parents, message)
+}
+
+// Writes the files to the work tree, stages them and commits the index on top of the current
+// branch, which is advanced to the new commit.
+pub fn commit_files(repo: &Repository, files: &[(&str, &str)], message: &str) -> Sha {
+    let work_tree = repo.work_tree().unwrap();
+    let mut index = index::read(repo).unwrap();
+    for (path, content) in files {
+        let file = work_tree.join(path);
+        fs::create_dir_all(file.parent().unwrap()).unwrap();
+        fs::write(&file, content).unwrap();
+        wtree::stage(repo, work_tree, &mut index, path).unwrap();
+    }
+    let tree = index.write_tree(repo).unwrap();
+    index.write(repo).unwrap();
+
+    let (branch, head) = refs::follow(repo, "HEAD").unwrap();
+    let commit = commit_tree(repo, &tree, &head.iter().collect::<Vec<_>>(), message);
+    refs::update(repo, &branch, &commit, None, "commit").unwrap();
+    commit
+}
+
+fn commit_tree(repo: &Repository, tree: &Sha, parents: &[&Sha], message: &str) -> Sha {
+    let mut content = format!("tree {}\n", tree);
+    for parent in parents {
+        content.push_str(&format!("parent {}\n", parent));
+    }
+    content.push_str(&format!("author A U Thor <author@example.com> 1700000000 +0000\n\
+        committer C O Mitter <committer@example.com> 1700000000 +0000\n\n{}\n", message));
+    obj::write(repo, ObjType::Commit, content.as_bytes()).unwrap()
+}
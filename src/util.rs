@@ -2,7 +2,7 @@
 
 use std::{fs::DirEntry, time::{SystemTime, UNIX_EPOCH}};
 
-type R<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+use crate::error::R;
 
 pub fn print_hex(bytes: &[u8]) -> String {
     bytes.iter()
@@ -33,3 +33,20 @@ pub fn name(entry: &DirEntry) -> String {
 pub fn timestamp() -> R<u64> {
     Ok(SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs())
 }
+
+pub fn percent_decode(s: &str) -> String {
+    let mut bytes = Vec::new();
+    let mut rest = s.as_bytes();
+
+    while let Some((&b, tail)) = rest.split_first() {
+        let escaped = tail.get(..2)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(decoded) if b == b'%' => { bytes.push(decoded); rest = &tail[2..]; },
+            _                          => { bytes.push(b); rest = tail; }
+        }
+    }
+
+    String::from_utf8_lossy(&bytes).into_owned()
+}
@@ -1,101 +1,150 @@
-use std::{fs::{self, DirEntry, File}, io::Read, path::Path};
-use crate::{obj::{self, Obj, ObjType}, util, sha::Sha};
+use std::{collections::BTreeSet, env, fs::{self, File, Metadata}, io::{ErrorKind, Read}, os::unix::fs::{symlink, PermissionsExt}, path::Path, str};
+use crate::{ignore::Ignore, index::{self, Entry, Index}, obj::{self, Obj, ObjType}, pathspec::Pathspec, repo::{self, Repository}, rev, util, sha::Sha};
 
-type R<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+use crate::error::R;
 
-pub fn clear(git_dir: & Path) -> R<()> {
-    let dir_entries = git_dir
-        .parent()
-        .ok_or(format!("Reached file system root while trying to enumerate parent of {:?}.", git_dir))?
-        .read_dir()?
-        .collect::<Result<Vec<_>, _>>()?;
-
-    dir_entries.iter()
-        .filter(|e| !git_dir.ends_with(e.path()))
-        .map(|e| if e.file_type()?.is_file() { fs::remove_file(e.path()) } else { fs::remove_dir_all(e.path()) })
-        .collect::<Result<Vec<_>, _>>()?;
+// Moves the work tree and the index from the tree of commit `from` (none for an empty work tree)
+// to the tree of commit `to`, touching only the paths that differ between the two. Local changes
+// to other paths are kept. Unless `force` is set, nothing is changed if that would lose staged or
+// unstaged changes or overwrite untracked files; with it, all local changes are thrown away.
+pub fn checkout(repo: &Repository, work_tree: &Path, from: Option<&Sha>, to: &Sha, force: bool) -> R<()> {
+    let old = match from {
+        Some(commit) => Index::from_tree(repo, &rev::peel(repo, commit, ObjType::Tree)?)?,
+        None         => Index::default()
+    };
+    let new = Index::from_tree(repo, &rev::peel(repo, to, ObjType::Tree)?)?;
+    let mut index = index::read(repo)?;
+    if !force && index.entries.iter().any(|e| e.stage > 0) {
+        return Err("You need to resolve your current index first.".into());
+    }
 
-    Ok(())
-}
+    let paths = old.entries.iter().chain(&new.entries).chain(if force { &index.entries[..] } else { &[] })
+        .map(|e| e.path.clone())
+        .collect::<BTreeSet<_>>();
+    let ignore = Ignore::load(repo)?;
+    let mut changes = Vec::new();
+    let mut modified = Vec::new();
+    let mut untracked = Vec::new();
+    let mut untracked_dirs = Vec::new();
+    for path in paths {
+        let (before, after) = (old.find(&path), new.find(&path));
+        let current = index.find(&path).filter(|e| !e.intent_to_add);
+        if force {
+            let conflicted = index.entries.iter().any(|e| e.path == path && e.stage > 0);
+            let clean = !conflicted && same(current, after) && match current {
+                Some(e) => !is_modified(repo, work_tree, &index, e)?,
+                None    => true
+            };
+            if !clean {
+                changes.push((path, after.cloned()));
+            }
+            continue;
+        }
+        if same(before, after) || same(current, after) {
+            continue; // local changes, if any, are carried over
+        }
 
-pub fn checkout(git_dir: &Path, commit: &Sha) -> R<()> {
-    fn checkout_tree(git_dir: &Path, parent: &Path, id: &Sha) -> R<()> {
-        fs::create_dir_all(parent)?;
-
-        match obj::read(git_dir, id)? {
-            Obj::Tree { entries } => {
-                entries.iter()
-                    .map(|e| match e.mode {
-                        040000 => checkout_tree(git_dir, &parent.join(&e.name), &e.id),
-                        100644 |
-                        100755 => checkout_blob(git_dir, &parent.join(&e.name), &e.id),
-                        _      => Err(format!("Tree entry with unsupported file mode: {:#?}", e).into())
-                    })
-                    .collect::<R<Vec<_>>>()?;
-                    Ok(())
+        match current {
+            Some(e) if !same(current, before) || is_modified(repo, work_tree, &index, e)? => modified.push(path.clone()),
+            Some(_) => {},
+            None if before.is_some() => modified.push(path.clone()), // deleted in the index
+            // the file is not tracked, as neither HEAD nor the index has it
+            None if after.is_some() && in_the_way(work_tree, &index, &ignore, &path)? => match work_tree.join(&path).is_dir() {
+                true  => untracked_dirs.push(path.clone()),
+                false => untracked.push(path.clone())
             },
-            _ => Err(format!("Object {} is not a tree.", id.value()).into())
+            None => {}
         }
+        changes.push((path, after.cloned()));
     }
 
-    fn checkout_blob(git_dir: &Path, filename: &Path, id: &Sha) -> R<()> {
-        match obj::read(git_dir, id)? {
-            Obj::Blob { content } => Ok(fs::write(filename, content)?),
-            _ => Err(format!("Object {} is not a blob.", id.value()).into())
+    if !modified.is_empty() {
+        return Err(format!("Your local changes to the following files would be overwritten by checkout:\n\t{}\n\
+            Please commit your changes or stash them before you switch branches.\nAborting", modified.join("\n\t")).into());
+    }
+    if !untracked.is_empty() {
+        return Err(format!("The following untracked working tree files would be overwritten by checkout:\n\t{}\n\
+            Please move or remove them before you switch branches.\nAborting", untracked.join("\n\t")).into());
+    }
+    if !untracked_dirs.is_empty() {
+        return Err(format!("Updating the following directories would lose untracked files in them:\n\t{}\n\nAborting",
+            untracked_dirs.join("\n\t")).into());
+    }
+
+    // files go first so that dirs can take their place and the other way round
+    for (path, _) in changes.iter().filter(|(_, after)| after.is_none()).rev() {
+        index.remove(path);
+        remove_file(work_tree, path)?;
+    }
+    for (_, after) in changes.iter() {
+        if let Some(entry) = after {
+            index.add(write_entry(repo, work_tree, entry)?);
         }
     }
+    if index.entries.len() == new.entries.len() && index.entries.iter().zip(&new.entries).all(|(a, b)| same(Some(a), Some(b))) {
+        index.tree = new.tree;
+    }
+    index.write(repo)
+}
+
+// Whether the index entries agree on content, including the absence of the file.
+fn same(a: Option<&Entry>, b: Option<&Entry>) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => a.id == b.id && a.mode == b.mode,
+        (None, None)       => true,
+        _                  => false
+    }
+}
 
-    let obj = obj::read(git_dir, &commit)?;
-    let target_dir = git_dir.parent()
-        .ok_or(format!("Reached file system root while trying to get parent of {:?}.", git_dir))?;
+// Whether writing a new file to the path would overwrite untracked work: a file at the path or
+// at one of its parent dirs, or a dir at the path containing untracked files. Ignored files are
+// expendable.
+fn in_the_way(work_tree: &Path, index: &Index, ignore: &Ignore, path: &str) -> R<bool> {
+    fn has_untracked(work_tree: &Path, index: &Index, ignore: &Ignore, dir: &str) -> R<bool> {
+        for e in work_tree.join(dir).read_dir()? {
+            let e = e?;
+            let path = format!("{}/{}", dir, util::name(&e));
+            let is_dir = e.file_type()?.is_dir();
+            if index.find(&path).is_some() || ignore.is_ignored(&path, is_dir)? {
+                continue;
+            }
+            if !is_dir || has_untracked(work_tree, index, ignore, &path)? {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
 
-    match obj {
-        Obj::Commit { tree: t, .. } => {
-            clear(git_dir)?;
-            checkout_tree(git_dir, target_dir, &t)
-        },
-        _ => Err(format!("Object {} is not a commit.", commit.value()).into())
+    for (pos, _) in path.match_indices('/') {
+        let dir = &path[..pos];
+        match metadata(&work_tree.join(dir))? {
+            Some(meta) if !meta.is_dir() => return Ok(index.find(dir).is_none() && !ignore.is_ignored(dir, false)?),
+            Some(_)                      => {},
+            None                         => return Ok(false)
+        }
+    }
+    match metadata(&work_tree.join(path))? {
+        Some(meta) if meta.is_dir() => has_untracked(work_tree, index, ignore, path),
+        Some(_)                     => Ok(!ignore.is_ignored(path, false)?),
+        None                        => Ok(false)
     }
 }
 
-pub fn write_tree(git_dir: &Path, path: &Path) -> R<Sha> {
-    fn render_entry(git_dir: &Path, entry: &DirEntry) -> R<Vec<u8>> {
-        fn render_file(entry: &DirEntry) -> R<Vec<u8>> {
-            let mode_and_name = format!("100644 {}\x00", util::name(entry));
-            let content = read_file(&entry.path())?;
-            let id = Sha::generate_raw(&content);
-            let mut row = mode_and_name.into_bytes();
-            row.extend_from_slice(&id);
-            Ok(row)
-        }
-
-        fn render_dir(git_dir: &Path, entry: &DirEntry) -> R<Vec<u8>> {
-            let mode_and_name = format!("040000 {}\x00", util::name(entry));
-            let id = write_tree(git_dir, &entry.path())?;
-            let mut row = mode_and_name.into_bytes();
-            row.extend_from_slice(&id.to_bytes());
-            Ok(row)
-        }
-
-        let _type = entry.file_type()?;
-        if _type.is_file() {
-            render_file(entry)
-        } else if _type.is_dir() {
-            render_dir(git_dir, entry)
-        } else {
-            Err(format!("Symbolic links ('{}') are not supported.", util::name(entry)).into())
-        }
-    }
-
-    let mut dir_entries = path.read_dir()?.collect::<Result<Vec<_>, _>>()?;
-    dir_entries.sort_by_key(util::name);
-    let content = dir_entries.iter()
-        .filter(|e| util::name(e) != ".git")
-        .map(|e| render_entry(git_dir, e))
-        .collect::<R<Vec<_>>>()?
-        .concat();
-    let sha = obj::write(git_dir, ObjType::Tree, &content)?;
-    Ok(sha)
+// Deletes the file of the path from the work tree, along with the parent dirs this leaves empty.
+// Submodule dirs are only deleted if they are empty.
+pub fn remove_file(work_tree: &Path, path: &str) -> R<()> {
+    let file = work_tree.join(path);
+    match metadata(&file)? {
+        Some(meta) if meta.is_dir() => { fs::remove_dir(&file).ok(); },
+        Some(_)                     => fs::remove_file(&file)?,
+        None                        => return Ok(())
+    }
+    for dir in file.ancestors().skip(1).take_while(|d| *d != work_tree) {
+        if fs::remove_dir(dir).is_err() {
+            break;
+        }
+    }
+    Ok(())
 }
 
 pub fn read_file(path: &Path) -> R<Vec<u8>> {
@@ -108,3 +157,338 @@ pub fn read_file(path: &Path) -> R<Vec<u8>> {
 
     Ok(bytes)
 }
+
+// Writes the content of the entry to the work tree, replacing what is there, even a dir with all
+// files in it, and returns the entry with the stat data of the new file.
+pub fn write_entry(repo: &Repository, work_tree: &Path, entry: &Entry) -> R<Entry> {
+    let file = work_tree.join(&entry.path);
+    match metadata(&file)? {
+        Some(meta) if meta.is_dir() && !(entry.mode == index::MODE_GITLINK && file.join(".git").exists()) => fs::remove_dir_all(&file)?,
+        Some(meta) if !meta.is_dir() => fs::remove_file(&file)?,
+        _ => {}
+    }
+    if let Some(dir) = file.parent() {
+        fs::create_dir_all(dir)?;
+    }
+
+    if entry.mode == index::MODE_GITLINK {
+        // submodules are not cloned, so they are left as empty dirs like git does
+        fs::create_dir_all(&file)?;
+        return Ok(Entry::new(&entry.path, entry.id.clone(), entry.mode));
+    }
+
+    let content = match obj::read(repo, &entry.id)? {
+        Obj::Blob { content } => content,
+        _ => return Err(format!("Object {} is not a blob.", entry.id.value()).into())
+    };
+    match entry.mode {
+        index::MODE_SYMLINK    => symlink(str::from_utf8(&content)?, &file)?,
+        index::MODE_EXECUTABLE => {
+            fs::write(&file, content)?;
+            fs::set_permissions(&file, fs::Permissions::from_mode(0o755))?;
+        },
+        _ => fs::write(&file, content)?
+    }
+    Ok(Entry::from_metadata(&entry.path, entry.id.clone(), entry.mode, &fs::symlink_metadata(&file)?))
+}
+
+// Turns a path given relative to the current dir into a path relative to the work tree, as used
+// in the index. The work tree itself becomes the empty path.
+pub fn relative_path(work_tree: &Path, arg: &str) -> R<String> {
+    let path = repo::absolute(&env::current_dir()?.join(arg))?;
+    let relative = path.strip_prefix(repo::absolute(work_tree)?)
+        .map_err(|_| format!("'{}' is outside repository.", arg))?;
+    Ok(relative.to_string_lossy().into_owned())
+}
+
+// Lists the files of the work tree selected by the pathspec in the order of the index. Nested
+// repositories are listed as a single path like git does for submodules. Ignored files are left
+// out and ignored dirs are not looked into.
+pub fn walk(work_tree: &Path, pathspec: &Pathspec, ignore: Option<&Ignore>) -> R<Vec<String>> {
+    fn visit(work_tree: &Path, path: &str, pathspec: &Pathspec, ignore: Option<&Ignore>, files: &mut Vec<String>) -> R<()> {
+        let file = work_tree.join(path);
+        let meta = match metadata(&file)? {
+            Some(meta) => meta,
+            None       => return Ok(())
+        };
+        if let Some(ignore) = ignore.filter(|_| !path.is_empty()) {
+            if ignore.find(path, meta.is_dir())?.is_some_and(|p| !p.negated) {
+                return Ok(());
+            }
+        }
+        if !meta.is_dir() || (!path.is_empty() && file.join(".git").exists()) {
+            if pathspec.matches(path)? {
+                files.push(path.to_string());
+            }
+            return Ok(());
+        }
+        if !path.is_empty() && !pathspec.may_contain(path) {
+            return Ok(());
+        }
+
+        for e in file.read_dir()? {
+            let name = util::name(&e?);
+            if name != ".git" {
+                let child = if path.is_empty() { name } else { format!("{}/{}", path, name) };
+                visit(work_tree, &child, pathspec, ignore, files)?;
+            }
+        }
+        Ok(())
+    }
+
+    let mut files = Vec::new();
+    visit(work_tree, "", pathspec, ignore, &mut files)?;
+    files.sort();
+    Ok(files)
+}
+
+// Updates the index entry of the path from the work tree, writing the blob, or removes the entry
+// if the file is gone. Files whose stat data still matches the entry are not hashed again.
+pub fn stage(repo: &Repository, work_tree: &Path, index: &mut Index, path: &str) -> R<()> {
+    let file = work_tree.join(path);
+    let meta = match metadata(&file)? {
+        Some(meta) if !meta.is_dir() || file.join(".git").exists() => meta,
+        // submodules that were never cloned are left as empty dirs, which does not delete them
+        Some(_) if index.find(path).is_some_and(|e| e.mode == index::MODE_GITLINK) => return Ok(()),
+        _ => {
+            index.remove(path);
+            return Ok(());
+        }
+    };
+
+    if let Some(entry) = index.find(path) {
+        if !entry.intent_to_add && entry.matches(&meta) && !index.is_racy(entry) {
+            return Ok(());
+        }
+    }
+
+    let mode = index::mode_of(&meta);
+    let id = file_id(repo, &file, mode, true)?;
+    index.add(Entry::from_metadata(path, id, mode, &meta));
+    Ok(())
+}
+
+// Whether the file in the work tree differs from the index entry. A missing file counts as
+// modified.
+pub fn is_modified(repo: &Repository, work_tree: &Path, index: &Index, entry: &Entry) -> R<bool> {
+    let file = work_tree.join(&entry.path);
+    let meta = match metadata(&file)? {
+        Some(meta) => meta,
+        None       => return Ok(true)
+    };
+    let mode = index::mode_of(&meta);
+    if entry.mode == index::MODE_GITLINK && mode == index::MODE_GITLINK && !file.join(".git").exists() {
+        return Ok(false);
+    }
+    if mode != entry.mode || entry.intent_to_add {
+        return Ok(true);
+    }
+    if entry.matches(&meta) && !index.is_racy(entry) {
+        return Ok(false);
+    }
+    Ok(file_id(repo, &file, mode, false)? != entry.id)
+}
+
+// The id of the file with the given index mode, which is the checked out commit for nested
+// repositories. Blobs are only written to the database if `write` is set.
+fn file_id(repo: &Repository, file: &Path, mode: u32, write: bool) -> R<Sha> {
+    let content = match mode {
+        index::MODE_GITLINK => {
+            let nested = Repository::discover(file)?;
+            return rev::parse(&nested, "HEAD")
+                .map_err(|_| format!("'{}' does not have a commit checked out.", file.to_string_lossy()).into());
+        },
+        index::MODE_SYMLINK => fs::read_link(file)?.to_string_lossy().into_owned().into_bytes(),
+        _                   => read_file(file)?
+    };
+    if write {
+        obj::write(repo, ObjType::Blob, &content)
+    } else {
+        Ok(obj::hash(ObjType::Blob, &content))
+    }
+}
+
+// The metadata of the file without following symlinks, or nothing if it does not exist.
+fn metadata(file: &Path) -> R<Option<Metadata>> {
+    match fs::symlink_metadata(file) {
+        Ok(meta) => Ok(Some(meta)),
+        Err(e) if e.kind() == ErrorKind::NotFound || e.kind() == ErrorKind::NotADirectory => Ok(None),
+        Err(e)   => Err(e.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testutil;
+    use super::*;
+
+    fn pathspec(repo: &Repository, args: &[&str]) -> Pathspec {
+        Pathspec::parse(repo, &args.iter().map(|a| a.to_string()).collect::<Vec<_>>()).unwrap()
+    }
+
+    #[test]
+    fn stage_writes_blobs_and_removes_missing_files() {
+        let (dir, repo) = testutil::repo();
+        let work_tree = dir.path();
+        fs::write(work_tree.join("a.txt"), "a\n").unwrap();
+        fs::write(work_tree.join("run.sh"), "#!/bin/sh\n").unwrap();
+        fs::set_permissions(work_tree.join("run.sh"), fs::Permissions::from_mode(0o755)).unwrap();
+        symlink("a.txt", work_tree.join("link")).unwrap();
+
+        let mut index = Index::default();
+        for path in &["a.txt", "run.sh", "link"] {
+            stage(&repo, work_tree, &mut index, path).unwrap();
+        }
+        let entry = |index: &Index, path| index.find(path).map(|e| (e.id.to_string(), e.mode));
+        assert_eq!(entry(&index, "a.txt"), Some((obj::hash(ObjType::Blob, b"a\n").to_string(), index::MODE_FILE)));
+        assert_eq!(entry(&index, "run.sh").unwrap().1, index::MODE_EXECUTABLE);
+        assert_eq!(entry(&index, "link"), Some((obj::hash(ObjType::Blob, b"a.txt").to_string(), index::MODE_SYMLINK)));
+        assert!(obj::exists(&repo, &index.find("a.txt").unwrap().id).unwrap());
+
+        fs::remove_file(work_tree.join("a.txt")).unwrap();
+        stage(&repo, work_tree, &mut index, "a.txt").unwrap();
+        assert!(index.find("a.txt").is_none());
+    }
+
+    #[test]
+    fn modified_files() {
+        let (dir, repo) = testutil::repo();
+        let work_tree = dir.path();
+        fs::write(work_tree.join("a.txt"), "a\n").unwrap();
+        let mut index = Index::default();
+        stage(&repo, work_tree, &mut index, "a.txt").unwrap();
+        let entry = index.find("a.txt").unwrap().clone();
+        assert!(!is_modified(&repo, work_tree, &index, &entry).unwrap());
+
+        fs::write(work_tree.join("a.txt"), "b\n").unwrap();
+        assert!(is_modified(&repo, work_tree, &index, &entry).unwrap());
+        fs::write(work_tree.join("a.txt"), "a\n").unwrap();
+        fs::set_permissions(work_tree.join("a.txt"), fs::Permissions::from_mode(0o755)).unwrap();
+        assert!(is_modified(&repo, work_tree, &index, &entry).unwrap());
+        fs::remove_file(work_tree.join("a.txt")).unwrap();
+        assert!(is_modified(&repo, work_tree, &index, &entry).unwrap());
+    }
+
+    #[test]
+    fn remove_file_removes_empty_parent_dirs() {
+        let (dir, _repo) = testutil::repo();
+        let work_tree = dir.path();
+        fs::create_dir_all(work_tree.join("a/b/c")).unwrap();
+        fs::write(work_tree.join("a/keep.txt"), "").unwrap();
+        fs::write(work_tree.join("a/b/c/d.txt"), "").unwrap();
+
+        remove_file(work_tree, "a/b/c/d.txt").unwrap();
+        assert!(!work_tree.join("a/b").exists());
+        assert!(work_tree.join("a/keep.txt").exists());
+        remove_file(work_tree, "a/missing.txt").unwrap();
+    }
+
+    #[test]
+    fn walk_lists_selected_files() {
+        let (dir, repo) = testutil::repo();
+        let work_tree = dir.path();
+        fs::create_dir_all(work_tree.join("src/nested/.git")).unwrap();
+        fs::create_dir_all(work_tree.join("docs")).unwrap();
+        for path in &["b.txt", "a.rs", "src/main.rs", "docs/x.md"] {
+            fs::write(work_tree.join(path), "").unwrap();
+        }
+
+        assert_eq!(walk(work_tree, &pathspec(&repo, &[]), None).unwrap(), ["a.rs", "b.txt", "docs/x.md", "src/main.rs", "src/nested"]);
+        assert_eq!(walk(work_tree, &pathspec(&repo, &["src"]), None).unwrap(), ["src/main.rs", "src/nested"]);
+        assert_eq!(walk(work_tree, &pathspec(&repo, &["*.rs"]), None).unwrap(), ["a.rs", "src/main.rs"]);
+    }
+
+    // Two commits: the first with a.txt, b.txt, dir/c.txt and x, the second changing a.txt,
+    // adding new.txt and deleting dir/c.txt and x. The work tree is left at the second.
+    fn two_commits() -> (testutil::TempDir, Repository, Sha, Sha) {
+        let (dir, repo) = testutil::repo();
+        repo.set_config("core.excludesFile", "/dev/null").unwrap();
+        let first = testutil::commit_files(&repo, &[("a.txt", "1\n"), ("b.txt", "b\n"), ("dir/c.txt", "c\n"), ("x", "x\n")], "first");
+        let mut index = index::read(&repo).unwrap();
+        for path in &["dir/c.txt", "x"] {
+            index.remove(path);
+            remove_file(dir.path(), path).unwrap();
+        }
+        index.write(&repo).unwrap();
+        let second = testutil::commit_files(&repo, &[("a.txt", "2\n"), ("new.txt", "n\n")], "second");
+        (dir, repo, first, second)
+    }
+
+    fn read(dir: &testutil::TempDir, path: &str) -> Option<String> {
+        fs::read_to_string(dir.path().join(path)).ok()
+    }
+
+    #[test]
+    fn checkout_updates_changed_paths_and_keeps_local_changes() {
+        let (dir, repo, first, second) = two_commits();
+        fs::write(dir.path().join("b.txt"), "local\n").unwrap();
+        fs::write(dir.path().join("untracked.txt"), "u\n").unwrap();
+
+        checkout(&repo, dir.path(), Some(&second), &first, false).unwrap();
+        assert_eq!(read(&dir, "a.txt").as_deref(), Some("1\n"));
+        assert_eq!(read(&dir, "dir/c.txt").as_deref(), Some("c\n"));
+        assert_eq!(read(&dir, "new.txt"), None);
+        assert_eq!(read(&dir, "b.txt").as_deref(), Some("local\n"));
+        assert_eq!(read(&dir, "untracked.txt").as_deref(), Some("u\n"));
+
+        let mut index = index::read(&repo).unwrap();
+        assert_eq!(index.entries.iter().map(|e| e.path.as_str()).collect::<Vec<_>>(), ["a.txt", "b.txt", "dir/c.txt", "x"]);
+        assert!(!is_modified(&repo, dir.path(), &index, index.find("a.txt").unwrap()).unwrap());
+        assert!(is_modified(&repo, dir.path(), &index, index.find("b.txt").unwrap()).unwrap());
+        let tree = rev::peel(&repo, &first, ObjType::Tree).unwrap();
+        assert_eq!(index.write_tree(&repo).unwrap(), tree);
+    }
+
+    #[test]
+    fn checkout_refuses_to_lose_changes() {
+        let (dir, repo, first, second) = two_commits();
+        fs::write(dir.path().join("a.txt"), "local\n").unwrap();
+        let e = checkout(&repo, dir.path(), Some(&second), &first, false).unwrap_err().to_string();
+        assert!(e.starts_with("Your local changes to the following files would be overwritten by checkout:\n\ta.txt\n"), "{}", e);
+        assert_eq!(read(&dir, "new.txt").as_deref(), Some("n\n"));
+
+        // staged changes count as well
+        let mut index = index::read(&repo).unwrap();
+        stage(&repo, dir.path(), &mut index, "a.txt").unwrap();
+        index.write(&repo).unwrap();
+        assert!(checkout(&repo, dir.path(), Some(&second), &first, false).is_err());
+
+        checkout(&repo, dir.path(), Some(&second), &first, true).unwrap();
+        assert_eq!(read(&dir, "a.txt").as_deref(), Some("1\n"));
+        let index = index::read(&repo).unwrap();
+        assert_eq!(index.find("a.txt").unwrap().id, obj::hash(ObjType::Blob, b"1\n"));
+    }
+
+    #[test]
+    fn checkout_refuses_to_overwrite_untracked_files() {
+        let (dir, repo, first, second) = two_commits();
+        fs::create_dir_all(dir.path().join("dir")).unwrap();
+        fs::write(dir.path().join("dir/c.txt"), "untracked\n").unwrap();
+        let e = checkout(&repo, dir.path(), Some(&second), &first, false).unwrap_err().to_string();
+        assert!(e.starts_with("The following untracked working tree files would be overwritten by checkout:\n\tdir/c.txt\n"), "{}", e);
+        fs::remove_file(dir.path().join("dir/c.txt")).unwrap();
+
+        fs::create_dir_all(dir.path().join("x")).unwrap();
+        fs::write(dir.path().join("x/u.txt"), "untracked\n").unwrap();
+        let e = checkout(&repo, dir.path(), Some(&second), &first, false).unwrap_err().to_string();
+        assert!(e.starts_with("Updating the following directories would lose untracked files in them:\n\tx\n"), "{}", e);
+        assert_eq!(read(&dir, "a.txt").as_deref(), Some("2\n"));
+
+        // ignored files are expendable
+        fs::write(dir.path().join(".gitignore"), "x/\n").unwrap();
+        checkout(&repo, dir.path(), Some(&second), &first, false).unwrap();
+        assert_eq!(read(&dir, "x").as_deref(), Some("x\n"));
+    }
+
+    #[test]
+    fn checkout_into_an_empty_work_tree() {
+        let (dir, repo, first, _) = two_commits();
+        fs::remove_file(repo.git_dir().join("index")).unwrap();
+        for path in &["a.txt", "b.txt", "new.txt"] {
+            fs::remove_file(dir.path().join(path)).unwrap();
+        }
+        checkout(&repo, dir.path(), None, &first, false).unwrap();
+        assert_eq!(read(&dir, "x").as_deref(), Some("x\n"));
+        assert_eq!(index::read(&repo).unwrap().entries.len(), 4);
+    }
+}
@@ -0,0 +1,73 @@
+// Staging and unstaging files with `add` and `rm`.
+
+mod common;
+
+use std::fs;
+use common::{commit, fail, git, init};
+
+fn staged(dir: &std::path::Path) -> String {
+    git(dir, &["status", "--porcelain"])
+}
+
+#[test]
+fn add_files_and_updates() {
+    let tmp = init("add");
+    let dir = tmp.path();
+    fs::create_dir_all(dir.join("sub")).unwrap();
+    fs::write(dir.join("a.txt"), "a\n").unwrap();
+    fs::write(dir.join("sub/b.txt"), "b\n").unwrap();
+    fs::write(dir.join("c.txt"), "c\n").unwrap();
+
+    git(dir, &["add", "a.txt", "sub"]);
+    assert_eq!(staged(dir), "A  a.txt\nA  sub/b.txt\n?? c.txt");
+    assert_eq!(fail(dir, &["add", "missing.txt"]), "pathspec 'missing.txt' did not match any files");
+    assert_eq!(fail(dir, &["add"]), "Nothing specified, nothing added.");
+
+    git(dir, &["commit", "-m", "first"]);
+    fs::write(dir.join("a.txt"), "changed\n").unwrap();
+    fs::remove_file(dir.join("sub/b.txt")).unwrap();
+    git(dir, &["add", "-u"]);
+    assert_eq!(staged(dir), "M  a.txt\nD  sub/b.txt\n?? c.txt");
+
+    git(dir, &["add", "-A"]);
+    assert_eq!(staged(dir), "M  a.txt\nA  c.txt\nD  sub/b.txt");
+}
+
+#[test]
+fn add_ignored_and_intent_to_add() {
+    let tmp = init("add-ignored");
+    let dir = tmp.path();
+    fs::write(dir.join(".gitignore"), "*.log\n").unwrap();
+    fs::write(dir.join("x.log"), "").unwrap();
+    fs::write(dir.join("new.txt"), "new\n").unwrap();
+
+    assert!(fail(dir, &["add", "x.log"]).starts_with("The following paths are ignored by one of your .gitignore files:\nx.log\n"));
+    git(dir, &["add", "-f", "x.log"]);
+    git(dir, &["add", "-N", "new.txt"]);
+    assert_eq!(staged(dir), " A new.txt\nA  x.log\n?? .gitignore");
+    git(dir, &["add", "new.txt"]);
+    assert_eq!(staged(dir), "A  new.txt\nA  x.log\n?? .gitignore");
+}
+
+#[test]
+fn rm_keeps_changes_unless_forced() {
+    let tmp = init("rm");
+    let dir = tmp.path();
+    fs::create_dir_all(dir.join("sub")).unwrap();
+    fs::write(dir.join("sub/b.txt"), "b\n").unwrap();
+    commit(dir, "a.txt", "first");
+
+    fs::write(dir.join("a.txt"), "changed\n").unwrap();
+    assert_eq!(fail(dir, &["rm", "a.txt"]), "'a.txt' has local modifications (use --cached to keep the file, or -f to force removal).");
+    git(dir, &["add", "a.txt"]);
+    assert_eq!(fail(dir, &["rm", "a.txt"]), "'a.txt' has changes staged in the index (use --cached to keep the file, or -f to force removal).");
+    git(dir, &["rm", "--cached", "a.txt"]);
+    assert_eq!(staged(dir), "D  a.txt\n?? a.txt\n?? sub/");
+    assert!(dir.join("a.txt").exists());
+
+    git(dir, &["add", "sub"]);
+    git(dir, &["commit", "-m", "sub"]);
+    assert_eq!(fail(dir, &["rm", "sub"]), "not removing 'sub' recursively without -r");
+    assert_eq!(git(dir, &["rm", "-r", "sub"]), "rm 'sub/b.txt'");
+    assert!(!dir.join("sub").exists());
+}
@@ -0,0 +1,61 @@
+// Creating, listing, renaming and deleting branches and tags, with options in any order.
+
+mod common;
+
+use common::{commit, fail, git, TempDir};
+
+#[test]
+fn branches() {
+    let tmp = TempDir::new("branch");
+    let dir = tmp.path();
+    git(dir, &["init", "."]);
+    let first = commit(dir, "a.txt", "first");
+    let short = &first[..7];
+    git(dir, &["update-ref", "refs/remotes/origin/master", &first]);
+
+    git(dir, &["branch", "topic"]);
+    assert_eq!(fail(dir, &["branch", "topic"]), "A branch named 'topic' already exists.");
+    assert_eq!(git(dir, &["branch"]), "* master\n  topic");
+    let all = format!("* master                {0} first\n  topic                 {0} first\n  remotes/origin/master {0} first", short);
+    assert_eq!(git(dir, &["branch", "-v", "-a"]), all);
+    assert_eq!(git(dir, &["branch", "-a", "-v"]), all);
+    assert_eq!(fail(dir, &["branch", "-x"]), "Unknown option '-x'.");
+
+    git(dir, &["branch", "topic", "-u", "origin/master"]);
+    assert_eq!(git(dir, &["config", "get", "branch.topic.merge"]), "refs/heads/master");
+    assert!(git(dir, &["branch", "-vv"]).contains(&format!("topic  {} [origin/master] first", short)));
+    git(dir, &["branch", "--unset-upstream", "topic"]);
+    assert_eq!(fail(dir, &["branch", "topic", "--unset-upstream"]), "Branch 'topic' has no upstream information.");
+
+    git(dir, &["branch", "topic", "renamed", "-m"]);
+    assert_eq!(git(dir, &["branch"]), "* master\n  renamed");
+    assert_eq!(git(dir, &["branch", "renamed", "-d"]), format!("Deleted branch renamed (was {}).", short));
+}
+
+#[test]
+fn tags() {
+    let tmp = TempDir::new("tag");
+    let dir = tmp.path();
+    git(dir, &["init", "."]);
+    let first = commit(dir, "a.txt", "first");
+    let second = commit(dir, "a.txt", "second");
+
+    git(dir, &["tag", "-a", "v1", "-m", "Release", "-m", "Notes"]);
+    let tag = git(dir, &["rev-parse", "v1"]);
+    let content = git(dir, &["cat-file", "-p", &tag]);
+    assert!(content.starts_with(&format!("object {}\ntype commit\ntag v1\ntagger C O Mitter <committer@example.com> ", second)));
+    assert!(content.ends_with("\n\nRelease\n\nNotes"));
+
+    git(dir, &["tag", "v2", &first]);
+    assert_eq!(git(dir, &["rev-parse", "v2"]), first);
+    assert_eq!(fail(dir, &["tag", "v2"]), "Tag 'v2' already exists.");
+    assert_eq!(git(dir, &["tag", "v2", "-f"]), format!("Updated tag 'v2' (was {})", &first[..7]));
+    git(dir, &["tag", "other"]);
+
+    assert_eq!(git(dir, &["tag"]), "other\nv1\nv2");
+    assert_eq!(git(dir, &["tag", "-l", "v*"]), "v1\nv2");
+    assert_eq!(git(dir, &["tag", "v*", "--list"]), "v1\nv2");
+    assert_eq!(fail(dir, &["tag", "-m"]), "Not enough arguments provided: missing value for '-m'.");
+    assert_eq!(git(dir, &["tag", "-d", "v2", "other"]), format!("Deleted tag 'v2' (was {0})\nDeleted tag 'other' (was {0})", &second[..7]));
+    assert_eq!(git(dir, &["tag"]), "v1");
+}
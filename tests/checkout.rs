@@ -0,0 +1,99 @@
+// Switching branches, detaching HEAD and restoring paths with `checkout`.
+
+mod common;
+
+use std::fs;
+use common::{commit, fail, git, init};
+
+#[test]
+fn switch_create_and_detach() {
+    let tmp = init("checkout-switch");
+    let dir = tmp.path();
+    let first = commit(dir, "a.txt", "first");
+
+    assert_eq!(git(dir, &["checkout", "-b", "topic"]), "Switched to a new branch 'topic'");
+    let second = commit(dir, "a.txt", "second");
+    assert_eq!(git(dir, &["checkout", "master"]), "Switched to branch 'master'");
+    assert_eq!(fs::read_to_string(dir.join("a.txt")).unwrap(), "first\n");
+    assert_eq!(git(dir, &["checkout", "master"]), "Already on 'master'");
+    assert_eq!(fail(dir, &["checkout", "-b", "topic"]), "A branch named 'topic' already exists.");
+
+    assert_eq!(git(dir, &["checkout", "topic~1"]), format!("HEAD is now at {} first", &first[..7]));
+    assert_eq!(fs::read_to_string(dir.join(".git/HEAD")).unwrap(), format!("{}\n", first));
+    git(dir, &["checkout", "--detach", "topic"]);
+    assert_eq!(git(dir, &["rev-parse", "HEAD"]), second);
+    assert_eq!(fs::read_to_string(dir.join("a.txt")).unwrap(), "second\n");
+
+    fs::write(dir.join("a.txt"), "local\n").unwrap();
+    assert!(fail(dir, &["checkout", "master"]).starts_with("Your local changes to the following files would be overwritten by checkout:"));
+    git(dir, &["checkout", "-f", "master"]);
+    assert_eq!(fs::read_to_string(dir.join("a.txt")).unwrap(), "first\n");
+    assert_eq!(git(dir, &["reflog"]).lines().next().unwrap().split_once(' ').unwrap().1, format!("HEAD@{{0}}: checkout: moving from {} to master", second));
+}
+
+#[test]
+fn options_in_any_order() {
+    let tmp = init("checkout-options");
+    let dir = tmp.path();
+    let first = commit(dir, "a.txt", "first");
+    let second = commit(dir, "a.txt", "second");
+
+    fs::write(dir.join("a.txt"), "local\n").unwrap();
+    assert_eq!(git(dir, &["checkout", "-b", "topic", "-f", "HEAD~1"]), "Switched to a new branch 'topic'");
+    assert_eq!(fs::read_to_string(dir.join("a.txt")).unwrap(), "first\n");
+    fs::write(dir.join("a.txt"), "local\n").unwrap();
+    git(dir, &["checkout", "--detach", "-f", "master"]);
+    assert_eq!(fs::read_to_string(dir.join(".git/HEAD")).unwrap(), format!("{}\n", second));
+    assert_eq!(fs::read_to_string(dir.join("a.txt")).unwrap(), "second\n");
+    git(dir, &["checkout", "-b", "other", "--force", "topic"]);
+    assert_eq!(git(dir, &["rev-parse", "HEAD"]), first);
+
+    // without a commit, --detach detaches at HEAD
+    git(dir, &["checkout", "--detach"]);
+    assert_eq!(fs::read_to_string(dir.join(".git/HEAD")).unwrap(), format!("{}\n", first));
+    assert_eq!(fail(dir, &["checkout", "-b"]), "Not enough arguments provided: missing value for '-b'.");
+    assert_eq!(fail(dir, &["checkout", "--bogus", "master"]), "Unknown option '--bogus'.");
+}
+
+#[test]
+fn restore_paths() {
+    let tmp = init("checkout-paths");
+    let dir = tmp.path();
+    fs::write(dir.join("b.txt"), "b\n").unwrap();
+    git(dir, &["add", "b.txt"]);
+    commit(dir, "a.txt", "first");
+    commit(dir, "a.txt", "second");
+
+    fs::write(dir.join("a.txt"), "local\n").unwrap();
+    fs::write(dir.join("b.txt"), "local\n").unwrap();
+    git(dir, &["checkout", "--", "a.txt"]);
+    assert_eq!(fs::read_to_string(dir.join("a.txt")).unwrap(), "second\n");
+    assert_eq!(fs::read_to_string(dir.join("b.txt")).unwrap(), "local\n");
+
+    git(dir, &["checkout", "HEAD~1", "a.txt"]);
+    assert_eq!(fs::read_to_string(dir.join("a.txt")).unwrap(), "first\n");
+    assert_eq!(git(dir, &["status", "--porcelain"]), "M  a.txt\n M b.txt");
+    assert_eq!(fail(dir, &["checkout", "--", "missing.txt"]), "pathspec 'missing.txt' did not match any file(s) known to git");
+}
+
+#[test]
+fn restore_paths_keeps_untracked_dirs() {
+    let tmp = init("checkout-dir");
+    let dir = tmp.path();
+    commit(dir, "a.txt", "first");
+    fs::remove_file(dir.join("a.txt")).unwrap();
+    fs::create_dir_all(dir.join("a.txt")).unwrap();
+
+    // an empty dir is replaced
+    git(dir, &["checkout", "--", "a.txt"]);
+    assert_eq!(fs::read_to_string(dir.join("a.txt")).unwrap(), "first\n");
+
+    fs::remove_file(dir.join("a.txt")).unwrap();
+    fs::create_dir_all(dir.join("a.txt")).unwrap();
+    fs::write(dir.join("a.txt/work.txt"), "precious\n").unwrap();
+    assert_eq!(fail(dir, &["checkout", "--", "a.txt"]), "Cannot check out 'a.txt' in place of a directory that is not empty. Use -f to replace it.");
+    assert_eq!(fs::read_to_string(dir.join("a.txt/work.txt")).unwrap(), "precious\n");
+
+    git(dir, &["checkout", "-f", "--", "a.txt"]);
+    assert_eq!(fs::read_to_string(dir.join("a.txt")).unwrap(), "first\n");
+}
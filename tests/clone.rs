@@ -0,0 +1,58 @@
+// The refs and config a clone sets up for the remote's branches and tags.
+
+mod common;
+
+use std::path::Path;
+use common::{command, commit, git, serve, TempDir};
+
+fn origin(tmp: &Path) -> (String, String) {
+    let origin = tmp.join("origin");
+    git(tmp, &["init", "origin"]);
+    let first = commit(&origin, "a.txt", "first");
+    git(&origin, &["tag", "v1"]);
+    git(&origin, &["branch", "dev"]);
+    let second = commit(&origin, "a.txt", "second");
+    (first, second)
+}
+
+#[test]
+fn clone_tracks_remote_branches() {
+    let tmp = TempDir::new("clone");
+    let tmp = tmp.path();
+    let (first, second) = origin(tmp);
+    let (_server, url) = serve(&tmp.join("origin"));
+
+    git(tmp, &["clone", &url, "clone"]);
+    let clone = tmp.join("clone");
+    assert_eq!(git(&clone, &["symbolic-ref", "HEAD"]), "refs/heads/master");
+    assert_eq!(git(&clone, &["rev-parse", "master"]), second);
+    assert_eq!(git(&clone, &["rev-parse", "origin/master"]), second);
+    assert_eq!(git(&clone, &["rev-parse", "origin/dev"]), first);
+    assert_eq!(git(&clone, &["rev-parse", "v1"]), first);
+    assert_eq!(git(&clone, &["symbolic-ref", "refs/remotes/origin/HEAD"]), "refs/remotes/origin/master");
+    assert!(!command(&clone, &["rev-parse", "--verify", "refs/heads/dev"]).output().unwrap().status.success());
+
+    assert_eq!(git(&clone, &["config", "get", "remote.origin.url"]), url);
+    assert_eq!(git(&clone, &["config", "get", "remote.origin.fetch"]), "+refs/heads/*:refs/remotes/origin/*");
+    assert_eq!(git(&clone, &["config", "get", "branch.master.remote"]), "origin");
+    assert_eq!(git(&clone, &["config", "get", "branch.master.merge"]), "refs/heads/master");
+}
+
+#[test]
+fn bare_clone_fetches_branches_directly() {
+    let tmp = TempDir::new("clone-bare");
+    let tmp = tmp.path();
+    let (first, second) = origin(tmp);
+    let (_server, url) = serve(&tmp.join("origin"));
+
+    git(tmp, &["clone", "--bare", &url, "clone.git"]);
+    let clone = tmp.join("clone.git");
+    assert_eq!(git(&clone, &["symbolic-ref", "HEAD"]), "refs/heads/master");
+    assert_eq!(git(&clone, &["rev-parse", "refs/heads/master"]), second);
+    assert_eq!(git(&clone, &["rev-parse", "refs/heads/dev"]), first);
+    assert_eq!(git(&clone, &["rev-parse", "refs/tags/v1"]), first);
+    assert!(!command(&clone, &["rev-parse", "--verify", "refs/remotes/origin/master"]).output().unwrap().status.success());
+
+    assert_eq!(git(&clone, &["config", "get", "remote.origin.url"]), url);
+    assert!(!command(&clone, &["config", "get", "remote.origin.fetch"]).output().unwrap().status.success());
+}
@@ -0,0 +1,108 @@
+// Committing the index with `commit`, and writing commits directly with `commit-tree`.
+
+mod common;
+
+use std::{fs, io::Write, path::Path, process::Stdio};
+use git_starter_rust::{obj, repo::Repository, sha::Sha};
+use common::{commit, fail, git, init};
+
+fn message(dir: &Path, rev: &str) -> String {
+    let content = git(dir, &["cat-file", "-p", rev]);
+    content.split_once("\n\n").unwrap().1.to_string()
+}
+
+fn parents(dir: &Path, rev: &str) -> Vec<String> {
+    git(dir, &["cat-file", "-p", rev]).lines()
+        .filter_map(|l| l.strip_prefix("parent ").map(String::from))
+        .collect()
+}
+
+#[test]
+fn options_in_any_order() {
+    let tmp = init("commit-options");
+    let dir = tmp.path();
+    fs::write(dir.join("a.txt"), "a\n").unwrap();
+    git(dir, &["add", "a.txt"]);
+    let output = git(dir, &["commit", "-m", "first  \n\n\n", "--author", "Jo Doe <jo@example.com>"]);
+    assert!(output.starts_with("[master (root-commit) "), "{}", output);
+    assert!(output.ends_with("] first"));
+    assert_eq!(message(dir, "HEAD"), "first");
+    assert!(git(dir, &["cat-file", "-p", "HEAD"]).contains("\nauthor Jo Doe <jo@example.com> "));
+
+    assert!(fail(dir, &["commit", "-m", "nothing"]).ends_with("nothing to commit, working tree clean"));
+    git(dir, &["commit", "--allow-empty", "-m", "empty", "-m", "body"]);
+    assert_eq!(message(dir, "HEAD"), "empty\n\nbody");
+
+    let parent = git(dir, &["rev-parse", "HEAD~1"]);
+    assert!(git(dir, &["commit", "-m", "amended", "--amend", "--allow-empty"]).contains("amended"));
+    assert_eq!(message(dir, "HEAD"), "amended");
+    assert_eq!(parents(dir, "HEAD"), [parent]);
+    git(dir, &["commit", "--allow-empty", "--amend", "--author=Other <other@example.com>", "-m", "again"]);
+    assert!(git(dir, &["cat-file", "-p", "HEAD"]).contains("\nauthor Other <other@example.com> "));
+
+    fs::write(dir.join("msg"), "\n# not a comment with -F\nfrom file\n").unwrap();
+    git(dir, &["commit", "--allow-empty", "-F", "msg"]);
+    assert_eq!(message(dir, "HEAD"), "# not a comment with -F\nfrom file");
+
+    assert_eq!(fail(dir, &["commit", "--allow-empty", "-m", "x", "-F", "msg"]), "Options -m and -F cannot be used together.");
+    assert_eq!(fail(dir, &["commit", "-m", "x", "extra"]), "Unexpected argument 'extra'.");
+    assert_eq!(fail(dir, &["commit", "--allow-empty", "-m"]), "Not enough arguments provided: missing value for '-m'.");
+    assert_eq!(fail(dir, &["commit", "--allow-empty", "-m", " \n"]), "Aborting commit due to empty commit message.");
+    assert_eq!(fail(dir, &["commit", "--author", "nobody", "--allow-empty", "-m", "x"]), "--author 'nobody' is not 'Name <email>'.");
+}
+
+#[test]
+fn editor_and_merges() {
+    let tmp = init("commit-merge");
+    let dir = tmp.path();
+    let first = commit(dir, "a.txt", "first");
+    let second = commit(dir, "a.txt", "second");
+
+    // an ancestor of HEAD is not recorded as another parent
+    fs::write(dir.join(".git/MERGE_HEAD"), format!("{}\n", first)).unwrap();
+    fs::write(dir.join(".git/MERGE_MSG"), "Merge first\n").unwrap();
+    let output = common::command(dir, &["commit"]).env("GIT_EDITOR", "true").output().unwrap();
+    assert!(String::from_utf8(output.stdout).unwrap().contains("Merge first"));
+    assert_eq!(parents(dir, "HEAD"), [second.clone()]);
+    assert!(!dir.join(".git/MERGE_HEAD").exists());
+
+    git(dir, &["checkout", "-b", "side", &first]);
+    let side = commit(dir, "b.txt", "side");
+    git(dir, &["checkout", "master"]);
+    let head = git(dir, &["rev-parse", "HEAD"]);
+    fs::write(dir.join(".git/MERGE_HEAD"), format!("{}\n", side)).unwrap();
+    git(dir, &["add", "a.txt"]);
+    let output = common::command(dir, &["commit"]).env("GIT_EDITOR", "printf 'Merge side\\n# comment\\n' >").output().unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stdout));
+    assert_eq!(parents(dir, "HEAD"), [head, side]);
+    assert_eq!(message(dir, "HEAD"), "Merge side");
+}
+
+#[test]
+fn commit_tree_options() {
+    let tmp = init("commit-tree");
+    let dir = tmp.path();
+    let first = commit(dir, "a.txt", "first");
+    let second = commit(dir, "a.txt", "second");
+    let tree = git(dir, &["write-tree"]);
+    fs::write(dir.join("msg"), "from file").unwrap();
+
+    let id = git(dir, &["commit-tree", "-p", &first, "-m", "subject", &tree, "-p", "HEAD", "-F", "msg", "-p", &first]);
+    assert_eq!(parents(dir, &id), [first.clone(), second]);
+    let content = git(dir, &["cat-file", "-p", &id]);
+    assert!(content.starts_with(&format!("tree {}\n", tree)));
+    assert!(content.contains("\nauthor A U Thor <author@example.com> "));
+    // the message is kept verbatim, without adding a newline after the file
+    let (_, raw) = obj::read_raw(&Repository::discover(dir).unwrap(), &Sha::from_str(&id).unwrap()).unwrap();
+    assert!(raw.ends_with(b"\n\nsubject\n\nfrom file"), "{}", String::from_utf8_lossy(&raw));
+
+    let mut child = common::command(dir, &["commit-tree", &tree]).stdin(Stdio::piped()).stdout(Stdio::piped()).spawn().unwrap();
+    child.stdin.take().unwrap().write_all(b"from stdin\n").unwrap();
+    let id = String::from_utf8(child.wait_with_output().unwrap().stdout).unwrap();
+    assert_eq!(message(dir, id.trim_end()), "from stdin");
+    assert!(parents(dir, id.trim_end()).is_empty());
+
+    assert_eq!(fail(dir, &["commit-tree", "-m", "x"]), "Not enough arguments provided: missing tree.");
+    assert_eq!(fail(dir, &["commit-tree", &tree, "-p"]), "Not enough arguments provided: missing value for '-p'.");
+    assert_eq!(fail(dir, &["commit-tree", &tree, &tree, "-m", "x"]), format!("Unexpected argument '{}'.", tree));
+}
@@ -0,0 +1,100 @@
+// Helpers shared by the integration tests, which run the binary against temporary repositories.
+// Not every test uses all of them.
+#![allow(dead_code)]
+
+use std::{env, fs, io::{BufRead, BufReader, Read}, path::{Path, PathBuf}, process::{self, Child, Command, Stdio}, thread};
+
+const BIN: &str = env!("CARGO_BIN_EXE_git-starter-rust");
+
+// A directory that is removed again when the test is done with it.
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new(name: &str) -> TempDir {
+        let dir = env::temp_dir().join(format!("git-starter-rust-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        TempDir(dir)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+// Kills the server even if the test fails.
+pub struct Server(Child);
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+// Runs the binary without any system or global config, so that only the test's setup counts.
+pub fn command(dir: &Path, args: &[&str]) -> Command {
+    let mut cmd = Command::new(BIN);
+    cmd.args(args)
+        .current_dir(dir)
+        .env("GIT_CONFIG_NOSYSTEM", "1")
+        .env("GIT_CONFIG_GLOBAL", "/dev/null")
+        .env("GIT_AUTHOR_NAME", "A U Thor")
+        .env("GIT_AUTHOR_EMAIL", "author@example.com")
+        .env("GIT_COMMITTER_NAME", "C O Mitter")
+        .env("GIT_COMMITTER_EMAIL", "committer@example.com");
+    cmd
+}
+
+// The output of a command that must succeed, without the trailing newline.
+pub fn git(dir: &Path, args: &[&str]) -> String {
+    let output = command(dir, args).output().unwrap();
+    assert!(output.status.success(), "{:?} failed: {}", args, String::from_utf8_lossy(&output.stdout));
+    String::from_utf8(output.stdout).unwrap().trim_end().to_string()
+}
+
+// The error message of a command that must fail.
+pub fn fail(dir: &Path, args: &[&str]) -> String {
+    let output = command(dir, args).output().unwrap();
+    assert!(!output.status.success(), "{:?} succeeded", args);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    stdout.trim_end().strip_prefix("ERROR: ").unwrap_or(&stdout).to_string()
+}
+
+// A fresh repository without the user's excludes file, so that only the test's ignore rules count.
+pub fn init(name: &str) -> TempDir {
+    let tmp = TempDir::new(name);
+    git(tmp.path(), &["init", "."]);
+    git(tmp.path(), &["config", "set", "core.excludesFile", "/dev/null"]);
+    tmp
+}
+
+// Commits `file` with the message as its content and returns the new commit.
+pub fn commit(dir: &Path, file: &str, message: &str) -> String {
+    fs::write(dir.join(file), format!("{}\n", message)).unwrap();
+    git(dir, &["add", file]);
+    git(dir, &["commit", "-m", message]);
+    git(dir, &["rev-parse", "HEAD"])
+}
+
+// Starts a server for the repository on a free port and returns it with its URL.
+pub fn serve(dir: &Path) -> (Server, String) {
+    let mut child = command(dir, &["serve", "--listen", "127.0.0.1:0", "."])
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    let mut stdout = BufReader::new(child.stdout.take().unwrap());
+    let mut line = String::new();
+    stdout.read_line(&mut line).unwrap();
+    let url = line.trim_end().rsplit(' ').next().unwrap().trim_end_matches('/').to_string();
+    thread::spawn(move || stdout.read_to_end(&mut Vec::new()));
+
+    (Server(child), url)
+}
@@ -0,0 +1,76 @@
+// Finding the repository and its work tree through the environment, and the HEAD `init` sets up.
+
+mod common;
+
+use std::fs;
+use common::{command, commit, git, TempDir};
+
+#[test]
+fn git_dir_and_work_tree_from_environment() {
+    let tmp = TempDir::new("discover-env");
+    let tmp = tmp.path();
+    git(tmp, &["init", "repo"]);
+    let repo = tmp.join("repo");
+    let id = commit(&repo, "a.txt", "first");
+    fs::create_dir_all(tmp.join("elsewhere")).unwrap();
+    fs::write(tmp.join("elsewhere/a.txt"), "changed\n").unwrap();
+
+    let output = command(&tmp.join("elsewhere"), &["rev-parse", "HEAD"])
+        .env("GIT_DIR", "../repo/.git")
+        .output().unwrap();
+    assert_eq!(String::from_utf8(output.stdout).unwrap().trim_end(), id);
+
+    // the work tree defaults to the current dir with GIT_DIR and can be set explicitly
+    let status = |dir: &str, work_tree: Option<&str>| {
+        let mut cmd = command(&tmp.join(dir), &["status", "--porcelain"]);
+        cmd.env("GIT_DIR", tmp.join("repo/.git"));
+        if let Some(work_tree) = work_tree { cmd.env("GIT_WORK_TREE", tmp.join(work_tree)); }
+        String::from_utf8(cmd.output().unwrap().stdout).unwrap()
+    };
+    assert_eq!(status("elsewhere", None), " M a.txt\n");
+    assert_eq!(status("repo", None), "");
+    assert_eq!(status("elsewhere", Some("elsewhere")), " M a.txt\n");
+    fs::create_dir_all(tmp.join("elsewhere/sub")).unwrap();
+    assert_eq!(status("elsewhere/sub", Some("elsewhere")), " M a.txt\n");
+}
+
+#[test]
+fn ceiling_directories_stop_the_search() {
+    let tmp = TempDir::new("discover-ceiling");
+    let tmp = tmp.path();
+    git(tmp, &["init", "repo"]);
+    let repo = tmp.join("repo");
+    let id = commit(&repo, "a.txt", "first");
+    fs::create_dir_all(repo.join("sub")).unwrap();
+
+    let rev_parse = |ceiling: &str| {
+        command(&repo.join("sub"), &["rev-parse", "HEAD"])
+            .env("GIT_CEILING_DIRECTORIES", ceiling)
+            .output().unwrap()
+    };
+    assert_eq!(String::from_utf8(rev_parse(tmp.to_str().unwrap()).stdout).unwrap().trim_end(), id);
+    assert!(!rev_parse(repo.to_str().unwrap()).status.success());
+    assert!(!rev_parse(&format!("relative:{}", repo.to_str().unwrap())).status.success());
+}
+
+#[test]
+fn init_uses_global_default_branch() {
+    let tmp = TempDir::new("discover-init");
+    let tmp = tmp.path();
+    fs::write(tmp.join("global"), "[init]\n\tdefaultBranch = main\n").unwrap();
+
+    // the local config of the repository the command runs in doesn't count
+    git(tmp, &["init", "outer"]);
+    git(&tmp.join("outer"), &["config", "set", "init.defaultBranch", "local"]);
+    git(&tmp.join("outer"), &["init", "inner"]);
+    assert_eq!(git(&tmp.join("outer/inner"), &["symbolic-ref", "HEAD"]), "refs/heads/master");
+
+    let status = command(tmp, &["init", "repo"]).env("GIT_CONFIG_GLOBAL", tmp.join("global")).status().unwrap();
+    assert!(status.success());
+    assert_eq!(git(&tmp.join("repo"), &["symbolic-ref", "HEAD"]), "refs/heads/main");
+
+    // running init again keeps the branch checked out
+    git(&tmp.join("repo"), &["symbolic-ref", "HEAD", "refs/heads/dev"]);
+    git(tmp, &["init", "repo"]);
+    assert_eq!(git(&tmp.join("repo"), &["symbolic-ref", "HEAD"]), "refs/heads/dev");
+}
@@ -0,0 +1,68 @@
+// Our own client talking to our own server on localhost: clone, push and fetch over smart HTTP.
+
+mod common;
+
+use std::{collections::HashSet, fs};
+use git_starter_rust::{obj, pack::{fmt, proto}, refs::NULL_ID, repo::Repository, sha::Sha};
+use common::{commit, git, serve, TempDir};
+
+fn push(repo: &Repository, url: &str, name: &str, id: &str) -> String {
+    let id = Sha::from_str(id).unwrap();
+    let objs = obj::reachable(repo, &[id.clone()], &HashSet::new()).unwrap();
+
+    let mut body = proto::pkt_line(&format!("{} {} {}\0report-status\n", NULL_ID, id, name)).into_bytes();
+    body.extend(b"0000");
+    body.extend(fmt::write_pack(repo, &objs).unwrap());
+
+    let response = reqwest::blocking::Client::new()
+        .post(&format!("{}/git-receive-pack", url))
+        .header("Content-Type", "application/x-git-receive-pack-request")
+        .body(body)
+        .send()
+        .unwrap();
+    assert!(response.status().is_success());
+    response.text().unwrap()
+}
+
+#[test]
+fn clone_push_and_fetch_round_trip() {
+    let tmp = TempDir::new("serve");
+    let tmp = tmp.path();
+    let origin = tmp.join("origin");
+    git(tmp, &["init", "origin"]);
+    let first = commit(&origin, "a.txt", "first");
+
+    let (_server, url) = serve(&origin);
+
+    git(tmp, &["clone", &url, "one"]);
+    let one = tmp.join("one");
+    assert_eq!(git(&one, &["rev-parse", "HEAD"]), first);
+    assert_eq!(fs::read_to_string(one.join("a.txt")).unwrap(), "first\n");
+
+    // the checked out branch of a non-bare repository can't be pushed to, so push a new one
+    let second = commit(&one, "b.txt", "second");
+    let report = push(&Repository::discover(&one).unwrap(), &url, "refs/heads/feature", &second);
+    assert_eq!(report, format!("{}{}0000", proto::pkt_line("unpack ok\n"), proto::pkt_line("ok refs/heads/feature\n")));
+
+    // nothing outside of refs/ can be pushed to
+    let repo = Repository::discover(&one).unwrap();
+    for name in &["HEAD", "hooks/x", "refs/../HEAD"] {
+        let report = push(&repo, &url, name, &second);
+        assert_eq!(report, format!("{}{}0000", proto::pkt_line("unpack ok\n"), proto::pkt_line(&format!("ng {} funny refname\n", name))));
+    }
+    assert_eq!(fs::read_to_string(origin.join(".git/HEAD")).unwrap(), "ref: refs/heads/master\n");
+    assert!(!origin.join(".git/hooks/x").exists());
+
+    let refs = git(tmp, &["ls-remote", &url]);
+    assert_eq!(refs.lines().collect::<Vec<_>>(), [
+        format!("{}\tHEAD", first),
+        format!("{}\trefs/heads/feature", second),
+        format!("{}\trefs/heads/master", first)
+    ]);
+
+    git(tmp, &["clone", &url, "two"]);
+    let two = tmp.join("two");
+    assert_eq!(git(&two, &["rev-parse", "HEAD"]), first);
+    assert_eq!(git(&two, &["rev-parse", "origin/feature"]), second);
+    assert_eq!(git(&two, &["cat-file", "-p", &format!("{}:b.txt", second)]), "second");
+}